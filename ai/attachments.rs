@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use regex::Regex;
+use serde::Serialize;
+
+/// Files larger than this are truncated before being attached.
+pub const MAX_ATTACHMENT_BYTES: usize = 100_000;
+/// Once attachments reach this size in total, further files are skipped.
+pub const MAX_TOTAL_ATTACHMENT_BYTES: usize = 400_000;
+/// The most files a single message can attach, so a broad glob cannot attach a whole tree.
+pub const MAX_ATTACHMENTS: usize = 20;
+
+lazy_static! {
+    static ref ATTACHMENT_RE: Regex =
+        Regex::new(r#"(?s)\n*<attachment path="([^"]*)"( truncated)?>\n(.*?)\n</attachment>"#)
+            .unwrap();
+}
+
+/// A local file referenced from a message with `@path`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Attachment {
+    pub path: PathBuf,
+    /// The size of the file in bytes, before any truncation.
+    pub size: u64,
+}
+
+/// The files a message will attach, shown before it is sent.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct AttachmentPreview {
+    pub attachments: Vec<Attachment>,
+    /// References which did not match any files.
+    pub unmatched: Vec<String>,
+    /// Files which matched, but are over the attachment limits or aren't text.
+    pub skipped: Vec<PathBuf>,
+}
+
+impl AttachmentPreview {
+    pub fn is_empty(&self) -> bool {
+        self.attachments.is_empty() && self.unmatched.is_empty() && self.skipped.is_empty()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.attachments
+            .iter()
+            .map(|attachment| attachment.size.min(MAX_ATTACHMENT_BYTES as u64))
+            .sum()
+    }
+}
+
+/// A file attached to a sent message, summarised for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachedFile {
+    pub path: String,
+    pub lines: usize,
+    pub truncated: bool,
+}
+
+/// The paths referenced in a message, ie. `src/main.rs` and `*.toml` from
+/// "Explain @src/main.rs and @*.toml".
+///
+/// Only words starting with `@` are references, so email addresses are left alone.
+pub fn find_references(content: &str) -> Vec<&str> {
+    content
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|reference| reference.trim_end_matches([',', '.', ';', ':', '!', '?', ')']))
+        .filter(|reference| !reference.is_empty())
+        .collect()
+}
+
+fn expand_reference(reference: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let reference = match reference.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()
+            .ok_or(anyhow!("home directory not found"))?
+            .join(rest)
+            .to_string_lossy()
+            .to_string(),
+        None => reference.to_string(),
+    };
+
+    if !reference.contains(['*', '?', '[']) {
+        let path = PathBuf::from(reference);
+        return anyhow::Ok(if path.is_file() { vec![path] } else { vec![] });
+    }
+
+    let mut paths = glob::glob(&reference)?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_file())
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    anyhow::Ok(paths)
+}
+
+/// The text of a file, up to `MAX_ATTACHMENT_BYTES`, and whether it was truncated.
+///
+/// Binary files, which aren't valid utf-8, have no text.
+fn read_text(path: &Path) -> Option<(String, bool)> {
+    let mut contents = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(MAX_ATTACHMENT_BYTES as u64 + 1)
+        .read_to_end(&mut contents)
+        .ok()?;
+    let truncated = contents.len() > MAX_ATTACHMENT_BYTES;
+    contents.truncate(MAX_ATTACHMENT_BYTES);
+
+    // Truncating can split the last character, which is dropped rather than the whole file
+    if let Err(err) = std::str::from_utf8(&contents) {
+        if !truncated || err.error_len().is_some() {
+            return None;
+        }
+        contents.truncate(err.valid_up_to());
+    }
+    String::from_utf8(contents)
+        .ok()
+        .map(|contents| (contents, truncated))
+}
+
+/// Match the references in a message to files, applying the attachment limits.
+///
+/// Globs such as `**/*.rs` can walk a whole tree, so this should be run off the ui thread.
+pub fn resolve_attachments(content: &str) -> AttachmentPreview {
+    let mut preview = AttachmentPreview::default();
+    let mut total_size = 0;
+
+    for reference in find_references(content) {
+        let paths = match expand_reference(reference) {
+            Ok(paths) if !paths.is_empty() => paths,
+            _ => {
+                preview.unmatched.push(reference.to_string());
+                continue;
+            }
+        };
+
+        for path in paths {
+            if preview
+                .attachments
+                .iter()
+                .any(|attachment| attachment.path == path)
+            {
+                continue;
+            }
+
+            let size = std::fs::metadata(&path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            let attached_size = size.min(MAX_ATTACHMENT_BYTES as u64) as usize;
+            if preview.attachments.len() >= MAX_ATTACHMENTS
+                || total_size + attached_size > MAX_TOTAL_ATTACHMENT_BYTES
+                || read_text(&path).is_none()
+            {
+                preview.skipped.push(path);
+                continue;
+            }
+
+            total_size += attached_size;
+            preview.attachments.push(Attachment { path, size });
+        }
+    }
+
+    preview
+}
+
+/// Inline the files resolved for a message after its text, each wrapped in an `<attachment>`
+/// block so they can be told apart from the message, and from each other.
+///
+/// The references are left in the text, so editing the message attaches the files again.
+pub fn attach_files(content: &str, preview: &AttachmentPreview) -> String {
+    let mut attached = content.to_string();
+
+    for attachment in &preview.attachments {
+        let Some((contents, truncated)) = read_text(&attachment.path) else {
+            continue;
+        };
+
+        attached.push_str(&format!(
+            "\n\n<attachment path=\"{}\"{}>\n{}\n</attachment>",
+            attachment.path.display(),
+            if truncated { " truncated" } else { "" },
+            contents.trim_end_matches('\n')
+        ));
+    }
+
+    attached
+}
+
+/// Separate a message's text from the files attached to it.
+pub fn split_attachments(content: &str) -> (&str, Vec<AttachedFile>) {
+    let mut text_end = content.len();
+    let mut attached = Vec::new();
+
+    for captures in ATTACHMENT_RE.captures_iter(content) {
+        if let Some(matched) = captures.get(0) {
+            text_end = text_end.min(matched.start());
+        }
+        attached.push(AttachedFile {
+            path: captures[1].to_string(),
+            lines: captures[3].lines().count(),
+            truncated: captures.get(2).is_some(),
+        });
+    }
+
+    (&content[..text_end], attached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_files() {
+        let directory =
+            std::env::temp_dir().join(format!("archer-attachments-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(directory.join("b.rs"), "fn b() {}\nfn c() {}").unwrap();
+        std::fs::write(
+            directory.join("notes.txt"),
+            "x".repeat(MAX_ATTACHMENT_BYTES + 1),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("image.png"),
+            [0x89, 0x50, 0x4e, 0x47, 0xff, 0x00],
+        )
+        .unwrap();
+
+        let content = format!(
+            "Compare @{}, and @{}. Email me@example.com about @missing.rs and @{}",
+            directory.join("*.rs").display(),
+            directory.join("notes.txt").display(),
+            directory.join("image.png").display()
+        );
+        let preview = resolve_attachments(&content);
+        assert_eq!(preview.attachments.len(), 3);
+        assert_eq!(preview.unmatched, vec!["missing.rs"]);
+        assert_eq!(preview.skipped, vec![directory.join("image.png")]);
+
+        let attached = attach_files(&content, &preview);
+        let (text, files) = split_attachments(&attached);
+        assert_eq!(text, content);
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| (file.lines, file.truncated))
+                .collect::<Vec<_>>(),
+            vec![(1, false), (2, false), (1, true)]
+        );
+        assert!(files[0].path.ends_with("a.rs"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use std::pin::Pin;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use futures::Stream;
@@ -9,12 +10,16 @@ use eventsource_stream::{EventStream, Eventsource};
 use futures_lite::StreamExt;
 
 use super::config::{ModelConfig, ARCHER_CONFIG};
+use super::tools::ToolCall;
+use super::usage::CompletionUsage;
 
 #[derive(Serialize, Clone, PartialEq, Eq, Debug, Deserialize)]
 pub enum MessageRole {
     System,
     User,
     Assistant,
+    /// The result of a tool called by the preceding assistant message.
+    Tool(ToolCall),
 }
 
 #[derive(Clone, Serialize, Eq, PartialEq, Debug, Deserialize)]
@@ -24,10 +29,29 @@ pub struct Message {
     pub metadata: Option<MessageMetadata>,
 }
 
+impl Message {
+    /// A message without metadata, ie. one which was not generated by a model.
+    pub fn new(role: MessageRole, content: impl Into<String>) -> Self {
+        Message {
+            role,
+            content: content.into(),
+            metadata: None,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Eq, PartialEq, Debug, Deserialize)]
 pub struct MessageMetadata {
     pub model_config: ModelConfig,
     pub status: CompletionStatus,
+    #[serde(default)]
+    pub usage: CompletionUsage,
+    /// Set when the completion failed.
+    #[serde(default)]
+    pub error: Option<CompletionError>,
+    /// The fallback model which answered, when `model_config` was unavailable.
+    #[serde(default)]
+    pub fallback: Option<ModelConfig>,
 }
 
 pub struct ModelID {
@@ -50,6 +74,7 @@ pub trait CompletionModel: Sync + Send {
     ) -> anyhow::Result<Box<dyn CompletionResult>>;
 }
 
+#[async_trait]
 pub trait CompletionProvider: Sync {
     fn load() -> Self
     where
@@ -65,6 +90,11 @@ pub trait CompletionProvider: Sync {
 
         models
     }
+    /// Fetch the models the provider lists itself, ie. those pulled into Ollama, for
+    /// `list_models` to include. Runs in the background, so listing models never blocks a draw.
+    async fn refresh_models(&self) -> anyhow::Result<()> {
+        anyhow::Ok(())
+    }
     fn get_model(&self, model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>>;
 
     fn get_id(&self) -> String;
@@ -78,13 +108,253 @@ pub trait CompletionResult: Send + Sync {
         &'a mut self,
     ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>;
     fn get_content(&mut self) -> anyhow::Result<String>;
+    /// Stop the completion on the provider side.
+    ///
+    /// Called once the stream returned by `get_stream` has been dropped. Providers which stream
+    /// over a single http request stop generating when that request is closed.
+    async fn cancel(&mut self) -> anyhow::Result<()> {
+        anyhow::Ok(())
+    }
+    /// Why the completion failed, once its status is `Failed`.
+    fn get_error(&self) -> Option<CompletionError> {
+        None
+    }
+    /// The fallback model answering, when the requested model was unavailable.
+    fn get_fallback(&self) -> Option<ModelConfig> {
+        None
+    }
 }
 
 #[derive(Deserialize, Clone, Eq, PartialEq, Debug, Serialize)]
 pub enum CompletionStatus {
     Starting,
+    /// Waiting to send the request again, after it failed with a transient error.
+    Retrying {
+        attempt: u32,
+        max_retries: u32,
+    },
     Processing,
     Failed,
     Canceled,
     Succeeded,
 }
+
+/// The event yielded by completion streams when the completion fails partway through, with a
+/// json `CompletionError` as its data.
+pub const ERROR_EVENT: &str = "error";
+
+/// Why a completion failed, kept with the failed message so it can be shown in the viewer.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum CompletionError {
+    /// The api key is missing, or was rejected by the provider.
+    Auth(String),
+    RateLimit {
+        message: String,
+        /// How long the provider asked to wait before retrying, from `Retry-After`.
+        #[serde(default)]
+        retry_after_secs: Option<u64>,
+    },
+    /// Any other unsuccessful response from the provider.
+    Http {
+        status: u16,
+        message: String,
+        #[serde(default)]
+        retry_after_secs: Option<u64>,
+    },
+    /// The provider could not be reached, or the connection dropped.
+    Network(String),
+    /// An event in the response could not be parsed.
+    MalformedEvent(String),
+    /// The provider accepted the request, but reported the completion as failed.
+    Provider(String),
+}
+
+impl CompletionError {
+    /// Classify an unsuccessful response by its status, with the message from its body.
+    pub fn from_response(status: u16, body: &str) -> Self {
+        let message = error_message(body);
+        match status {
+            401 | 403 => CompletionError::Auth(message),
+            429 => CompletionError::RateLimit {
+                message,
+                retry_after_secs: None,
+            },
+            _ => CompletionError::Http {
+                status,
+                message,
+                retry_after_secs: None,
+            },
+        }
+    }
+
+    /// Whether the request could succeed if sent again, ie. after a dropped connection, a rate
+    /// limit, or an error on the provider's side.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            CompletionError::Network(_) | CompletionError::RateLimit { .. } => true,
+            CompletionError::Http { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    pub fn get_retry_after(&self) -> Option<Duration> {
+        match self {
+            CompletionError::RateLimit {
+                retry_after_secs, ..
+            }
+            | CompletionError::Http {
+                retry_after_secs, ..
+            } => retry_after_secs.map(Duration::from_secs),
+            _ => None,
+        }
+    }
+
+    fn with_retry_after(mut self, retry_after: Option<u64>) -> Self {
+        if let CompletionError::RateLimit {
+            retry_after_secs, ..
+        }
+        | CompletionError::Http {
+            retry_after_secs, ..
+        } = &mut self
+        {
+            *retry_after_secs = retry_after;
+        }
+        self
+    }
+
+    /// The error for a streamed event which could not be parsed, which is usually an error
+    /// reported by the provider partway through the response.
+    pub fn from_event(data: &str) -> Self {
+        let reported = serde_json::from_str::<serde_json::Value>(data)
+            .ok()
+            .filter(|value| value.get("error").is_some());
+        match reported {
+            Some(_) => CompletionError::Provider(error_message(data)),
+            None => CompletionError::MalformedEvent(data.chars().take(200).collect()),
+        }
+    }
+
+    /// A stream item reporting this error.
+    pub fn to_event(&self, id: String) -> (String, String, String) {
+        (
+            ERROR_EVENT.to_string(),
+            id,
+            serde_json::to_string(self).unwrap_or_default(),
+        )
+    }
+}
+
+impl std::fmt::Display for CompletionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompletionError::Auth(message) => write!(f, "authentication failed: {message}"),
+            CompletionError::RateLimit { message, .. } => write!(f, "rate limited: {message}"),
+            CompletionError::Http {
+                status, message, ..
+            } => write!(f, "http {status}: {message}"),
+            CompletionError::Network(message) => write!(f, "network error: {message}"),
+            CompletionError::MalformedEvent(message) => write!(f, "malformed event: {message}"),
+            CompletionError::Provider(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for CompletionError {}
+
+impl From<reqwest::Error> for CompletionError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.status() {
+            Some(status) => CompletionError::from_response(status.as_u16(), &err.to_string()),
+            None => CompletionError::Network(err.to_string()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for CompletionError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<CompletionError>() {
+            Ok(err) => err,
+            Err(err) => match err.downcast::<reqwest::Error>() {
+                Ok(err) => err.into(),
+                Err(err) => CompletionError::Provider(err.to_string()),
+            },
+        }
+    }
+}
+
+/// Providers wrap errors differently, ie. `{"error": {"message": ".."}}` or `{"detail": ".."}`,
+/// so the message is looked for in the common places before falling back to the whole body.
+fn error_message(body: &str) -> String {
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(body) {
+        for pointer in ["/error/message", "/error", "/detail", "/message"] {
+            if let Some(message) = value.pointer(pointer).and_then(|message| message.as_str()) {
+                return message.to_string();
+            }
+        }
+    }
+
+    let body = body.trim();
+    if body.is_empty() {
+        "no response body".to_string()
+    } else {
+        body.chars().take(200).collect()
+    }
+}
+
+/// Return the response if it was successful, or the error described by its body.
+pub async fn check_response(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, CompletionError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    // Only the delay in seconds form of `Retry-After` is used, as providers do not send dates
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    let body = response.text().await.unwrap_or_default();
+    Err(CompletionError::from_response(status.as_u16(), &body).with_retry_after(retry_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_error_from_response() {
+        assert_eq!(
+            CompletionError::from_response(
+                401,
+                r#"{"error": {"message": "Incorrect API key provided"}}"#
+            ),
+            CompletionError::Auth("Incorrect API key provided".to_string())
+        );
+        assert_eq!(
+            CompletionError::from_response(429, r#"{"detail": "Slow down"}"#),
+            CompletionError::RateLimit {
+                message: "Slow down".to_string(),
+                retry_after_secs: None
+            }
+        );
+        assert_eq!(
+            CompletionError::from_response(502, "Bad Gateway\n"),
+            CompletionError::Http {
+                status: 502,
+                message: "Bad Gateway".to_string(),
+                retry_after_secs: None
+            }
+        );
+        assert_eq!(
+            CompletionError::from_event(r#"{"error": "model not found"}"#),
+            CompletionError::Provider("model not found".to_string())
+        );
+        assert_eq!(
+            CompletionError::from_event("{\"choices\": ["),
+            CompletionError::MalformedEvent("{\"choices\": [".to_string())
+        );
+    }
+}
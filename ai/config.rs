@@ -1,34 +1,371 @@
+use anyhow::anyhow;
+use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use super::prompt::PromptTemplateVariant;
+use super::context::TruncationStrategy;
+use super::conversation::store::StorageBackend;
+use super::prompt::{CustomTemplate, PromptTemplateVariant};
+use super::providers::{RetryConfig, COMPLETION_PROVIDERS};
+use super::retrieval::RetrievalConfig;
+use super::tools::ToolsConfig;
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub default_completion_model: ModelConfig,
     pub default_title_model: ModelConfig,
     pub models: Vec<ModelConfig>,
     pub profiles: Vec<Profile>,
+    /// Prompt formats for completion models, referenced by name from a model's `template`.
+    #[serde(default)]
+    pub templates: Vec<CustomTemplate>,
+    #[serde(default)]
+    pub storage: StorageBackend,
+    #[serde(default)]
+    pub truncation: TruncationStrategy,
+    #[serde(default)]
+    pub retrieval: RetrievalConfig,
+    /// How failed requests are retried, with overrides for each provider.
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub tools: ToolsConfig,
 }
 
 #[derive(Serialize, Eq, PartialEq, Debug, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Profile {
     pub name: String,
     pub system_prompt: String,
+    /// Models to fall back to with this profile, after the model's own `fallbacks`.
+    #[serde(default)]
+    pub fallbacks: Vec<ModelReference>,
 }
 
+/// A model named by its provider, ie. in a list of fallbacks.
 #[derive(Eq, Serialize, PartialEq, Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ModelReference {
+    pub provider_id: String,
+    pub model_id: String,
+}
+
+#[derive(Eq, Serialize, PartialEq, Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ModelConfig {
     pub provider_id: String,
     pub model_id: String,
     pub extra_args: Option<HashMap<String, Value>>,
+    // Chat based providers (ie. OpenAI) send structured messages, and ignore the template
+    #[serde(default)]
     pub template: PromptTemplateVariant,
+    /// The model's context window in tokens, conversations are only truncated when it is set.
+    #[serde(default)]
+    pub context_length: Option<usize>,
+    /// Offer the registered tools to the model, for providers which support tool calls.
+    #[serde(default)]
+    pub tools: bool,
+    /// Models which answer instead, in order, when this model's provider is unavailable.
+    #[serde(default)]
+    pub fallbacks: Vec<ModelReference>,
+}
+
+impl Config {
+    pub fn get_template(&self, name: &str) -> Option<&CustomTemplate> {
+        self.templates.iter().find(|template| template.name == name)
+    }
+
+    /// The configured model for `reference`, or a model with the default settings when it is
+    /// not configured, ie. for models pulled into Ollama.
+    pub fn get_model_config(&self, reference: &ModelReference) -> ModelConfig {
+        self.models
+            .iter()
+            .find(|model_config| {
+                model_config.provider_id == reference.provider_id
+                    && model_config.model_id == reference.model_id
+            })
+            .cloned()
+            .unwrap_or(ModelConfig {
+                provider_id: reference.provider_id.clone(),
+                model_id: reference.model_id.clone(),
+                extra_args: None,
+                context_length: None,
+                tools: false,
+                template: Default::default(),
+                fallbacks: Vec::new(),
+            })
+    }
+
+    /// The models to try in order when `model_config` is unavailable, from its own `fallbacks`
+    /// and then the profile's, skipping any which are already in the list.
+    pub fn get_fallbacks(
+        &self,
+        model_config: &ModelConfig,
+        profile_fallbacks: &[ModelReference],
+    ) -> Vec<ModelConfig> {
+        let mut fallbacks = Vec::<ModelConfig>::new();
+        for reference in model_config.fallbacks.iter().chain(profile_fallbacks) {
+            let is_listed = |other: &ModelConfig| {
+                other.provider_id == reference.provider_id && other.model_id == reference.model_id
+            };
+            if !is_listed(model_config) && !fallbacks.iter().any(is_listed) {
+                fallbacks.push(self.get_model_config(reference));
+            }
+        }
+        fallbacks
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.profiles.is_empty() {
+            return Err(anyhow!("at least one profile must be configured"));
+        }
+
+        for profile in &self.profiles {
+            if profile.name.trim().is_empty() {
+                return Err(anyhow!("profile names cannot be empty"));
+            }
+            validate_fallbacks(&profile.fallbacks, &format!("profile '{}'", profile.name))?;
+        }
+
+        for (idx, template) in self.templates.iter().enumerate() {
+            let name = template.name.trim();
+            if name.is_empty() {
+                return Err(anyhow!("template names cannot be empty"));
+            }
+            if ["ChatML", "Mistral", "Llama"].contains(&name) {
+                return Err(anyhow!(
+                    "template '{name}' conflicts with a built in template"
+                ));
+            }
+            if self.templates[..idx]
+                .iter()
+                .any(|other| other.name == template.name)
+            {
+                return Err(anyhow!("template '{name}' is defined more than once"));
+            }
+        }
+
+        let default_models = [&self.default_completion_model, &self.default_title_model];
+        for model_config in default_models.into_iter().chain(self.models.iter()) {
+            if model_config.model_id.trim().is_empty() {
+                return Err(anyhow!(
+                    "model_id cannot be empty for provider '{}'",
+                    model_config.provider_id
+                ));
+            }
+
+            if COMPLETION_PROVIDERS
+                .get_provider(&model_config.provider_id)
+                .is_none()
+            {
+                return Err(anyhow!(
+                    "unknown provider_id '{}' for model '{}'",
+                    model_config.provider_id,
+                    model_config.model_id
+                ));
+            }
+
+            if let PromptTemplateVariant::Custom(name) = &model_config.template {
+                if self.get_template(name).is_none() {
+                    return Err(anyhow!(
+                        "unknown template '{name}' for model '{}'",
+                        model_config.model_id
+                    ));
+                }
+            }
+
+            validate_fallbacks(
+                &model_config.fallbacks,
+                &format!("model '{}'", model_config.model_id),
+            )?;
+        }
+
+        anyhow::Ok(())
+    }
+}
+
+fn validate_fallbacks(fallbacks: &[ModelReference], owner: &str) -> anyhow::Result<()> {
+    for fallback in fallbacks {
+        if COMPLETION_PROVIDERS
+            .get_provider(&fallback.provider_id)
+            .is_none()
+        {
+            return Err(anyhow!(
+                "unknown provider_id '{}' for fallback '{}' of {owner}",
+                fallback.provider_id,
+                fallback.model_id
+            ));
+        }
+    }
+    anyhow::Ok(())
 }
 
 const DEFAULT_CONFIG_STR: &str = include_str!("default.json");
+const USER_CONFIG_FILES: [&str; 2] = ["models.json", "models.toml"];
+
 lazy_static! {
-    pub static ref ARCHER_CONFIG: Config = serde_json::from_str(DEFAULT_CONFIG_STR).unwrap();
+    pub static ref PROJECT_NAME: String = env!("CARGO_CRATE_NAME").to_uppercase().to_string();
+    pub static ref DATA_FOLDER: Option<PathBuf> =
+        std::env::var(format!("{}_DATA", PROJECT_NAME.clone()))
+            .ok()
+            .map(PathBuf::from);
+    pub static ref CONFIG_FOLDER: Option<PathBuf> =
+        std::env::var(format!("{}_CONFIG", PROJECT_NAME.clone()))
+            .ok()
+            .map(PathBuf::from);
+    pub static ref ARCHER_CONFIG: Config = load_config().expect("failed to load archer config");
+}
+
+fn project_directory() -> Option<ProjectDirs> {
+    ProjectDirs::from("com", "kdheepak", env!("CARGO_PKG_NAME"))
+}
+
+pub fn get_data_dir() -> PathBuf {
+    if let Some(s) = DATA_FOLDER.clone() {
+        s
+    } else if let Some(proj_dirs) = project_directory() {
+        proj_dirs.data_local_dir().to_path_buf()
+    } else {
+        PathBuf::from(".").join(".data")
+    }
+}
+
+pub fn get_config_dir() -> PathBuf {
+    if let Some(s) = CONFIG_FOLDER.clone() {
+        s
+    } else if let Some(proj_dirs) = project_directory() {
+        proj_dirs.config_local_dir().to_path_buf()
+    } else {
+        PathBuf::from(".").join(".config")
+    }
+}
+
+/// Load the built in config, merged with any user config files found in the config directory.
+///
+/// User `models` are matched to built in models on `provider_id` and `model_id`, and `profiles`
+/// on `name`. Matching entries are deep merged, while new entries are appended.
+pub fn load_config() -> anyhow::Result<Config> {
+    let mut config: Value = serde_json::from_str(DEFAULT_CONFIG_STR)?;
+    let config_dir = get_config_dir();
+
+    for file_name in USER_CONFIG_FILES {
+        let path = config_dir.join(file_name);
+        if !path.exists() {
+            continue;
+        }
+
+        let user_config = read_user_config(&path)?;
+        config = merge_config(&config, &user_config);
+
+        // Deserialize after each file, so errors can be attributed to the file which caused them
+        serde_json::from_value::<Config>(config.clone())
+            .map_err(|err| anyhow!("invalid config in {}: {err}", path.display()))?
+            .validate()
+            .map_err(|err| anyhow!("invalid config in {}: {err}", path.display()))?;
+    }
+
+    let config: Config = serde_json::from_value(config)?;
+    config.validate()?;
+    anyhow::Ok(config)
+}
+
+fn read_user_config(path: &Path) -> anyhow::Result<Value> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("failed to read {}: {err}", path.display()))?;
+
+    let user_config: Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(contents.as_str())
+            .map_err(|err| anyhow!("failed to parse {}: {err}", path.display()))?,
+        _ => serde_json::from_str(contents.as_str())
+            .map_err(|err| anyhow!("failed to parse {}: {err}", path.display()))?,
+    };
+
+    if !user_config.is_object() {
+        return Err(anyhow!(
+            "failed to parse {}: expected a table of config values",
+            path.display()
+        ));
+    }
+
+    anyhow::Ok(user_config)
+}
+
+fn merge_config(base: &Value, user: &Value) -> Value {
+    match (base, user) {
+        (Value::Object(base_map), Value::Object(user_map)) => {
+            let mut merged = base_map.clone();
+            for (key, user_value) in user_map {
+                let merged_value = match (key.as_str(), merged.get(key)) {
+                    ("models", Some(base_value)) => {
+                        merge_keyed(base_value, user_value, &["provider_id", "model_id"])
+                    }
+                    ("profiles" | "templates", Some(base_value)) => {
+                        merge_keyed(base_value, user_value, &["name"])
+                    }
+                    ("default_completion_model" | "default_title_model", Some(base_value)) => {
+                        // A different default model replaces the built in one entirely,
+                        // instead of inheriting its template and extra_args
+                        if is_same_item(base_value, user_value, &["provider_id", "model_id"]) {
+                            merge_values(base_value, user_value)
+                        } else {
+                            user_value.clone()
+                        }
+                    }
+                    (_, Some(base_value)) => merge_values(base_value, user_value),
+                    (_, None) => user_value.clone(),
+                };
+                merged.insert(key.clone(), merged_value);
+            }
+            Value::Object(merged)
+        }
+        (_, user) => user.clone(),
+    }
+}
+
+fn merge_keyed(base: &Value, user: &Value, keys: &[&str]) -> Value {
+    match (base, user) {
+        (Value::Array(base_items), Value::Array(user_items)) => {
+            let mut merged = base_items.clone();
+            for user_item in user_items {
+                let existing = merged
+                    .iter_mut()
+                    .find(|base_item| is_same_item(base_item, user_item, keys));
+
+                if let Some(base_item) = existing {
+                    *base_item = merge_values(base_item, user_item);
+                } else {
+                    merged.push(user_item.clone());
+                }
+            }
+            Value::Array(merged)
+        }
+        (_, user) => user.clone(),
+    }
+}
+
+fn is_same_item(base: &Value, user: &Value, keys: &[&str]) -> bool {
+    keys.iter()
+        .all(|key| base.get(key).is_some() && base.get(key) == user.get(key))
+}
+
+fn merge_values(base: &Value, user: &Value) -> Value {
+    match (base, user) {
+        (Value::Object(base_map), Value::Object(user_map)) => {
+            let mut merged = base_map.clone();
+            for (key, user_value) in user_map {
+                let merged_value = match merged.get(key) {
+                    Some(base_value) => merge_values(base_value, user_value),
+                    None => user_value.clone(),
+                };
+                merged.insert(key.clone(), merged_value);
+            }
+            Value::Object(merged)
+        }
+        (_, user) => user.clone(),
+    }
 }
 
 pub fn merge(v: &Value, fields: &HashMap<String, Value>) -> Value {
@@ -43,3 +380,120 @@ pub fn merge(v: &Value, fields: &HashMap<String, Value>) -> Value {
         v => v.clone(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_default_config() {
+        let config: Config = serde_json::from_str(DEFAULT_CONFIG_STR).unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_merge_config_overrides_model() {
+        let base = json!({"models": [
+            {"provider_id": "TogetherAI", "model_id": "a", "template": "Llama", "extra_args": {"stop": ["</s>"], "top_k": 50}},
+            {"provider_id": "TogetherAI", "model_id": "b"},
+        ]});
+        let user = json!({"models": [
+            {"provider_id": "TogetherAI", "model_id": "a", "extra_args": {"top_k": 10}},
+            {"provider_id": "Ollama", "model_id": "c"},
+        ]});
+
+        let merged = merge_config(&base, &user);
+        assert_eq!(
+            merged,
+            json!({"models": [
+                {"provider_id": "TogetherAI", "model_id": "a", "template": "Llama", "extra_args": {"stop": ["</s>"], "top_k": 10}},
+                {"provider_id": "TogetherAI", "model_id": "b"},
+                {"provider_id": "Ollama", "model_id": "c"},
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_merge_config_profiles_and_defaults() {
+        let base = json!({
+            "default_completion_model": {"provider_id": "TogetherAI", "model_id": "a", "template": "Mistral"},
+            "profiles": [{"name": "Default", "system_prompt": "old"}]
+        });
+        let user = json!({
+            "default_completion_model": {"provider_id": "Ollama", "model_id": "b"},
+            "profiles": [{"name": "Default", "system_prompt": "new"}, {"name": "Other", "system_prompt": "other"}]
+        });
+
+        let merged = merge_config(&base, &user);
+        assert_eq!(
+            merged,
+            json!({
+                "default_completion_model": {"provider_id": "Ollama", "model_id": "b"},
+                "profiles": [{"name": "Default", "system_prompt": "new"}, {"name": "Other", "system_prompt": "other"}]
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_custom_template() {
+        let mut config: Config = serde_json::from_str(DEFAULT_CONFIG_STR).unwrap();
+        let mut model_config = config.default_completion_model.clone();
+        model_config.template = serde_json::from_value(json!("Phi")).unwrap();
+        config.models.push(model_config);
+        assert!(config.validate().is_err());
+
+        config.templates.push(CustomTemplate {
+            name: "Phi".to_string(),
+            ..Default::default()
+        });
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            serde_json::to_value(&config.models.last().unwrap().template).unwrap(),
+            json!("Phi")
+        );
+    }
+
+    #[test]
+    fn test_validate_unknown_provider() {
+        let mut config: Config = serde_json::from_str(DEFAULT_CONFIG_STR).unwrap();
+        config.models.push(ModelConfig {
+            provider_id: "Unknown".to_string(),
+            model_id: "model".to_string(),
+            extra_args: None,
+            context_length: None,
+            tools: false,
+            template: PromptTemplateVariant::ChatML,
+            fallbacks: Vec::new(),
+        });
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_get_fallbacks() {
+        let config: Config = serde_json::from_value(json!({
+            "default_completion_model": {"provider_id": "TogetherAI", "model_id": "a"},
+            "default_title_model": {"provider_id": "TogetherAI", "model_id": "a"},
+            "models": [
+                {"provider_id": "TogetherAI", "model_id": "a", "fallbacks": [
+                    {"provider_id": "Replicate", "model_id": "b"},
+                    {"provider_id": "TogetherAI", "model_id": "a"}
+                ]},
+                {"provider_id": "Replicate", "model_id": "b", "context_length": 4096}
+            ],
+            "profiles": [{"name": "Default", "system_prompt": "", "fallbacks": [
+                {"provider_id": "Replicate", "model_id": "b"},
+                {"provider_id": "Ollama", "model_id": "c"}
+            ]}]
+        }))
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        // Configured fallbacks keep their settings, and each model is only tried once
+        let fallbacks = config.get_fallbacks(&config.models[0], &config.profiles[0].fallbacks);
+        assert_eq!(fallbacks.len(), 2);
+        assert_eq!(fallbacks[0], config.models[1]);
+        assert_eq!(fallbacks[1].provider_id, "Ollama");
+        assert_eq!(fallbacks[1].model_id, "c");
+    }
+}
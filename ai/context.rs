@@ -0,0 +1,372 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use tiktoken_rs::CoreBPE;
+
+use super::completion::{Message, MessageRole};
+use super::config::{ModelConfig, ARCHER_CONFIG};
+use super::providers::get_model;
+
+/// Tokens reserved for the completion, when `max_tokens` is not set in a model's `extra_args`.
+const DEFAULT_COMPLETION_TOKENS: usize = 2000;
+/// Approximate tokens added around each message by chat formats and prompt templates.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+/// Summaries kept for reuse, before the cache is cleared.
+const MAX_CACHED_SUMMARIES: usize = 64;
+
+/// A user message, and the responses which follow it.
+type Turn = Vec<Message>;
+
+lazy_static! {
+    // Providers each have their own tokenizer, though cl100k is close enough for a budget
+    static ref TOKENIZER: Option<CoreBPE> = tiktoken_rs::cl100k_base().ok();
+    /// Summaries of dropped turns, keyed on a hash of the turns, as the same turns are dropped
+    /// again each time a message is sent.
+    static ref SUMMARIES: Mutex<HashMap<u64, String>> = Mutex::new(HashMap::new());
+}
+
+/// How to shorten a conversation which does not fit in a model's context length.
+///
+/// Leading system messages, and the latest message, are always kept.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub enum TruncationStrategy {
+    /// Drop the oldest turns until the conversation fits.
+    #[default]
+    DropOldest,
+    /// Keep only the last N turns, dropping more if they still do not fit.
+    KeepLast(usize),
+    /// Replace the turns which do not fit with a summary, written by the title model.
+    Summarize,
+}
+
+pub fn count_tokens(text: &str) -> usize {
+    match TOKENIZER.as_ref() {
+        Some(tokenizer) => tokenizer.encode_with_special_tokens(text).len(),
+        // Roughly four characters to a token, for english text
+        None => text.len().div_ceil(4),
+    }
+}
+
+pub fn count_message_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| count_tokens(&message.content) + MESSAGE_OVERHEAD_TOKENS)
+        .sum()
+}
+
+/// Tokens available for the prompt, once room has been left for the completion.
+pub fn prompt_budget(model_config: &ModelConfig) -> Option<usize> {
+    let completion_tokens = model_config
+        .extra_args
+        .as_ref()
+        .and_then(|extra_args| extra_args.get("max_tokens"))
+        .and_then(|max_tokens| max_tokens.as_u64())
+        .map(|max_tokens| max_tokens as usize)
+        .unwrap_or(DEFAULT_COMPLETION_TOKENS);
+
+    model_config
+        .context_length
+        .map(|context_length| context_length.saturating_sub(completion_tokens))
+}
+
+/// Shorten `messages` to fit the model's context length, if it has one.
+pub async fn fit_to_context(
+    messages: Vec<Message>,
+    model_config: &ModelConfig,
+    strategy: &TruncationStrategy,
+) -> anyhow::Result<Vec<Message>> {
+    let Some(budget) = prompt_budget(model_config) else {
+        return anyhow::Ok(messages);
+    };
+    if count_message_tokens(&messages) <= budget {
+        return anyhow::Ok(messages);
+    }
+
+    let (mut system, mut turns) = split_turns(messages);
+    let kept = match strategy {
+        TruncationStrategy::DropOldest => drop_oldest(&system, turns, budget)?.0,
+        TruncationStrategy::KeepLast(count) => {
+            let last = turns.split_off(turns.len().saturating_sub((*count).max(1)));
+            drop_oldest(&system, last, budget)?.0
+        }
+        TruncationStrategy::Summarize => {
+            let (kept, dropped) = drop_oldest(&system, turns, budget)?;
+            let model_config = &ARCHER_CONFIG.default_title_model;
+            match summarize_cached(&dropped, model_config).await {
+                Ok(summary) => {
+                    system.push(Message {
+                        role: MessageRole::System,
+                        content: format!("A summary of the earlier conversation:\n\n{summary}"),
+                        metadata: None,
+                    });
+
+                    // The summary takes up room which was available to the kept turns
+                    match drop_oldest(&system, kept.clone(), budget) {
+                        Ok((kept, _)) => kept,
+                        Err(_) => {
+                            system.pop();
+                            kept
+                        }
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Failed to summarize conversation, dropping instead: {err}");
+                    kept
+                }
+            }
+        }
+    };
+
+    anyhow::Ok(
+        system
+            .into_iter()
+            .chain(kept.into_iter().flatten())
+            .collect(),
+    )
+}
+
+/// Split off the leading system messages, and group the rest into turns, each starting with a
+/// user message.
+fn split_turns(messages: Vec<Message>) -> (Vec<Message>, Vec<Turn>) {
+    let mut system = Vec::new();
+    let mut turns: Vec<Turn> = Vec::new();
+
+    for message in messages {
+        if turns.is_empty() && message.role == MessageRole::System {
+            system.push(message);
+        } else if message.role == MessageRole::User || turns.is_empty() {
+            turns.push(vec![message]);
+        } else if let Some(turn) = turns.last_mut() {
+            turn.push(message);
+        }
+    }
+
+    (system, turns)
+}
+
+/// Drop turns from the start until the rest fit in the budget, returning the kept and dropped
+/// turns.
+fn drop_oldest(
+    system: &[Message],
+    mut turns: Vec<Turn>,
+    budget: usize,
+) -> anyhow::Result<(Vec<Turn>, Vec<Turn>)> {
+    let system_tokens = count_message_tokens(system);
+    let mut total = system_tokens
+        + turns
+            .iter()
+            .map(|turn| count_message_tokens(turn))
+            .sum::<usize>();
+
+    let mut dropped = Vec::new();
+    while total > budget && turns.len() > 1 {
+        let turn = turns.remove(0);
+        total -= count_message_tokens(&turn);
+        dropped.push(turn);
+    }
+
+    if total > budget {
+        return Err(anyhow!(
+            "the message is {total} tokens long, which does not fit in the model's {budget} token prompt budget"
+        ));
+    }
+
+    anyhow::Ok((turns, dropped))
+}
+
+fn get_role_name(role: &MessageRole) -> &'static str {
+    match role {
+        MessageRole::System => "System",
+        MessageRole::User => "User",
+        MessageRole::Assistant => "Assistant",
+        MessageRole::Tool(_) => "Tool",
+    }
+}
+
+/// A hash of each run of turns from the start, ie. the first turn, the first two turns and so on.
+fn hash_turn_prefixes(turns: &[Turn]) -> Vec<u64> {
+    let mut hasher = DefaultHasher::new();
+    turns
+        .iter()
+        .map(|turn| {
+            for message in turn {
+                (get_role_name(&message.role), &message.content).hash(&mut hasher);
+            }
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Summarise turns, reusing the summary of any of the turns which were dropped before.
+///
+/// Turns are dropped from the start as the conversation grows, so an earlier summary is
+/// extended with the turns dropped since, rather than summarizing them all again.
+async fn summarize_cached(turns: &[Turn], model_config: &ModelConfig) -> anyhow::Result<String> {
+    let prefixes = hash_turn_prefixes(turns);
+    let cached = SUMMARIES.lock().ok().and_then(|summaries| {
+        prefixes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(idx, hash)| Some((idx + 1, summaries.get(hash)?.clone())))
+    });
+
+    let summary = match cached {
+        Some((summarized, summary)) if summarized == turns.len() => return anyhow::Ok(summary),
+        Some((summarized, summary)) => {
+            summarize(&turns[summarized..], Some(&summary), model_config).await?
+        }
+        None => summarize(turns, None, model_config).await?,
+    };
+
+    if let (Ok(mut summaries), Some(hash)) = (SUMMARIES.lock(), prefixes.last()) {
+        if summaries.len() >= MAX_CACHED_SUMMARIES {
+            summaries.clear();
+        }
+        summaries.insert(*hash, summary.clone());
+    }
+    anyhow::Ok(summary)
+}
+
+/// Summarise turns, following on from the summary of any earlier turns, keeping the most recent
+/// turns if they are too long for the model.
+async fn summarize(
+    turns: &[Turn],
+    previous: Option<&str>,
+    model_config: &ModelConfig,
+) -> anyhow::Result<String> {
+    if turns.is_empty() {
+        return Err(anyhow!("there are no messages to summarize"));
+    }
+
+    let turns = match prompt_budget(model_config) {
+        Some(budget) => drop_oldest(&[], turns.to_vec(), budget)?.0,
+        None => turns.to_vec(),
+    };
+
+    let mut transcript = turns
+        .iter()
+        .flatten()
+        .map(|message| format!("{}: {}", get_role_name(&message.role), message.content))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    if let Some(previous) = previous {
+        transcript = format!("Summary of the conversation before this: {previous}\n\n{transcript}");
+    }
+
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: "You are a helpful assistant, who summarizes conversations.".to_string(),
+            metadata: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: format!(
+                "Please summarize the conversation below in a few sentences, keeping any details needed to continue it.
+Please do not respond with anything else except the summary.
+
+{transcript}"
+            ),
+            metadata: None,
+        },
+    ];
+
+    let model = get_model(model_config)?;
+    let summary = model.get_completion(messages).await?.get_content()?;
+    anyhow::Ok(summary.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::providers::Mock;
+
+    #[tokio::test]
+    async fn test_fit_to_context() {
+        let long = "word ".repeat(400);
+        let messages = vec![
+            Message::new(MessageRole::System, "You are a helpful assistant."),
+            Message::new(MessageRole::User, &long),
+            Message::new(MessageRole::Assistant, &long),
+            Message::new(MessageRole::User, "first"),
+            Message::new(MessageRole::Assistant, "second"),
+            Message::new(MessageRole::User, "third"),
+        ];
+        let model_config = ModelConfig {
+            provider_id: "Ollama".to_string(),
+            model_id: "model".to_string(),
+            extra_args: None,
+            template: Default::default(),
+            fallbacks: Vec::new(),
+            context_length: Some(DEFAULT_COMPLETION_TOKENS + 100),
+            tools: false,
+        };
+
+        let fitted = fit_to_context(
+            messages.clone(),
+            &model_config,
+            &TruncationStrategy::DropOldest,
+        )
+        .await
+        .unwrap();
+        let contents = fitted
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            contents,
+            vec!["You are a helpful assistant.", "first", "second", "third"]
+        );
+
+        let fitted = fit_to_context(
+            messages.clone(),
+            &model_config,
+            &TruncationStrategy::KeepLast(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fitted.len(), 2);
+        assert_eq!(fitted[1].content, "third");
+
+        // The latest message alone is too long for the model
+        let mut too_long = messages;
+        too_long.push(Message::new(MessageRole::User, &long));
+        assert!(
+            fit_to_context(too_long, &model_config, &TruncationStrategy::DropOldest)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_summarize_cached() {
+        let echo = Mock::model_config(serde_json::json!({"token_delay_ms": 0}));
+        let failing = Mock::model_config(serde_json::json!({"error": "summarized again"}));
+
+        let id = uuid::Uuid::new_v4();
+        let first = vec![Message::new(MessageRole::User, format!("first {id}"))];
+        let second = vec![Message::new(MessageRole::User, format!("second {id}"))];
+
+        let summary = summarize_cached(std::slice::from_ref(&first), &echo)
+            .await
+            .unwrap();
+        assert!(summary.contains(&format!("User: first {id}")));
+
+        // The same turns are dropped again, and are not summarized again
+        let cached = summarize_cached(std::slice::from_ref(&first), &failing)
+            .await
+            .unwrap();
+        assert_eq!(cached, summary);
+
+        // Only the newly dropped turn is summarized, following on from the earlier summary
+        let extended = summarize_cached(&[first, second], &echo).await.unwrap();
+        assert!(extended.contains(&format!(
+            "Summary of the conversation before this: {summary}"
+        )));
+        assert!(extended.ends_with(&format!("User: second {id}")));
+    }
+}
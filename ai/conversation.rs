@@ -1,4 +1,9 @@
+pub mod export;
+pub mod search;
+pub mod store;
+
 use dirs::home_dir;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -6,15 +11,16 @@ use crate::ai::completion::Message;
 use anyhow::anyhow;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
-use walkdir::WalkDir;
 
 use super::completion::MessageRole;
 use super::config::{Profile, ARCHER_CONFIG};
+use super::usage::{current_day, CompletionUsage, UsageTotals};
+use search::SearchIndex;
+use store::{ConversationStore, JsonFileStore, SqliteStore, StorageBackend};
 
 pub const CONVERSATION_DIR: &str = ".archer/conversations/";
+const DATABASE_FILE: &str = "conversations.db";
 
 fn get_conversation_dir() -> PathBuf {
     if let Some(conversation_dir) = home_dir().and_then(|x| Some(x.join(CONVERSATION_DIR))) {
@@ -26,98 +32,174 @@ fn get_conversation_dir() -> PathBuf {
 
 #[derive(Clone)]
 pub struct ConversationMetadata {
-    path: PathBuf,
     title: String,
 }
 
+impl ConversationMetadata {
+    fn new(id: &Uuid, title: Option<String>) -> Self {
+        ConversationMetadata {
+            title: title.unwrap_or(id.to_string()),
+        }
+    }
+}
+
+/// Open the store configured with `storage`, importing any conversation files into SQLite.
+pub fn open_store() -> anyhow::Result<Box<dyn ConversationStore>> {
+    let conversation_dir = get_conversation_dir();
+    match ARCHER_CONFIG.storage {
+        StorageBackend::Sqlite => {
+            let mut store = SqliteStore::open(&conversation_dir.join(DATABASE_FILE))?;
+            store.import_json_files(&conversation_dir)?;
+            anyhow::Ok(Box::new(store))
+        }
+        StorageBackend::Json => anyhow::Ok(Box::new(JsonFileStore::new(conversation_dir))),
+    }
+}
+
 pub struct ConversationManager {
-    pub conversation_files: IndexMap<Uuid, ConversationMetadata>,
+    store: Box<dyn ConversationStore>,
+    pub conversations: IndexMap<Uuid, ConversationMetadata>,
     pub active_conversation: usize,
     pub selected_conversation: usize,
+    pub search_index: SearchIndex,
+    /// The search index is built the first time a search is run, as it reads every conversation.
+    search_indexed: bool,
+    search_query: String,
+    /// Conversations matching the search query, with their matching messages.
+    search_results: Option<IndexMap<Uuid, Vec<Uuid>>>,
+    /// Usage for the current day, cached as it is shown on every draw.
+    daily_usage: (String, UsageTotals),
 }
 
-impl Default for ConversationManager {
-    fn default() -> Self {
-        // Load existing Conversations
-        let mut conversation_files = IndexMap::<Uuid, ConversationMetadata>::new();
-        let conversation_dir = get_conversation_dir();
-        for entry in WalkDir::new(conversation_dir) {
-            if let Some(entry) = entry.ok() {
-                if entry.path().is_dir() {
-                    continue;
-                }
-
-                let path = PathBuf::from(entry.clone().path());
-                if let Some(contents) = std::fs::read_to_string(path.clone()).ok() {
-                    let convo: Result<Conversation, serde_json::Error> =
-                        serde_json::from_str(contents.as_str());
-                    if let Some(convo) = convo.ok() {
-                        conversation_files.insert(
-                            convo.id,
-                            ConversationMetadata {
-                                path,
-                                title: convo.title.unwrap_or(convo.id.to_string()),
-                            },
-                        );
-                    }
-                }
-            }
-        }
+impl ConversationManager {
+    /// Load the list of conversations from the configured store.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::with_store(open_store()?)
+    }
 
-        ConversationManager {
-            conversation_files,
+    pub fn with_store(store: Box<dyn ConversationStore>) -> anyhow::Result<Self> {
+        let conversations = store
+            .list_conversations()?
+            .into_iter()
+            .map(|(id, title)| (id, ConversationMetadata::new(&id, title)))
+            .collect::<IndexMap<Uuid, ConversationMetadata>>();
+        let day = current_day();
+        let daily_usage = (day.clone(), store.get_usage(day.as_str())?);
+
+        anyhow::Ok(ConversationManager {
+            store,
+            conversations,
             active_conversation: 0,
             selected_conversation: 0,
-        }
+            search_index: SearchIndex::default(),
+            search_indexed: false,
+            search_query: String::new(),
+            search_results: None,
+            daily_usage,
+        })
     }
-}
 
-impl ConversationManager {
     pub fn load_conversation(&mut self, id: &Uuid) -> anyhow::Result<Conversation> {
-        let file_path = self.get_file_path(id);
-        let contents = std::fs::read_to_string(file_path.as_path())?;
-        let convo: Conversation = serde_json::from_str(contents.as_str())?;
+        self.store.load_conversation(id)
+    }
+
+    pub fn save_conversation(&mut self, conversation: &Conversation) -> anyhow::Result<()> {
+        self.store.save_conversation(conversation)?;
+        self.index_conversation(conversation);
+        anyhow::Ok(())
+    }
+
+    pub fn delete_conversation(&mut self, id: &Uuid) -> anyhow::Result<()> {
+        self.remove_conversation(id);
+        self.store.delete_conversation(id)
+    }
+
+    /// Add a completion to the running total for today.
+    pub fn record_usage(&mut self, usage: &CompletionUsage) -> anyhow::Result<()> {
+        let day = current_day();
+        self.store.record_usage(day.as_str(), usage)?;
+        self.daily_usage = (day.clone(), self.store.get_usage(day.as_str())?);
+        anyhow::Ok(())
+    }
 
-        anyhow::Ok(convo)
+    pub fn get_daily_usage(&self) -> &UsageTotals {
+        &self.daily_usage.1
     }
 
     pub fn new_conversation(&mut self) -> Conversation {
-        let id = Uuid::now_v7();
         let profile = ARCHER_CONFIG.profiles.get(0).unwrap().clone();
         let convo = Conversation::new(profile);
 
-        let metadata = ConversationMetadata {
-            path: convo.get_file_path(),
-            title: id.to_string(),
-        };
-
-        self.conversation_files.insert(convo.id, metadata);
+        self.conversations
+            .insert(convo.id, ConversationMetadata::new(&convo.id, None));
 
         convo
     }
 
     pub fn add_conversation(&mut self, conversation: Conversation) {
-        let metadata = ConversationMetadata {
-            path: conversation.get_file_path(),
-            title: conversation.title.unwrap_or(conversation.id.to_string()),
+        self.search_index.index_conversation(&conversation);
+        self.conversations.insert(
+            conversation.id,
+            ConversationMetadata::new(&conversation.id, conversation.title),
+        );
+    }
+
+    /// The ids of the listed conversations, filtered by the search query.
+    pub fn list_ids(&self) -> Vec<Uuid> {
+        self.conversations
+            .keys()
+            .filter(|id| {
+                self.search_results
+                    .as_ref()
+                    .is_none_or(|results| results.contains_key(*id))
+            })
+            .copied()
+            .collect()
+    }
+
+    pub fn get_search_query(&self) -> &str {
+        self.search_query.as_str()
+    }
+
+    /// Filter the listed conversations to those matching every word in the query.
+    pub fn set_search_query(&mut self, query: String) {
+        self.search_results = if query.trim().is_empty() {
+            None
+        } else {
+            self.build_search_index();
+            Some(self.search_index.search(query.as_str()))
         };
-        self.conversation_files.insert(conversation.id, metadata);
+        self.search_query = query;
+        self.selected_conversation = 0;
     }
 
-    pub fn get_file_path(&self, id: &Uuid) -> PathBuf {
-        let conversation_dir = get_conversation_dir();
-        let directory = PathBuf::from(conversation_dir);
-        let file_path = directory.join(format!("{}.json", id));
-        file_path
+    fn build_search_index(&mut self) {
+        if self.search_indexed {
+            return;
+        }
+
+        for id in self.conversations.keys() {
+            if let Ok(conversation) = self.store.load_conversation(id) {
+                self.search_index.index_conversation(&conversation);
+            }
+        }
+        self.search_indexed = true;
+    }
+
+    /// Messages matching the active search in the conversation provided.
+    pub fn get_search_matches(&self, id: &Uuid) -> Option<&Vec<Uuid>> {
+        self.search_results
+            .as_ref()
+            .and_then(|results| results.get(id))
+    }
+
+    /// Refresh the search index with the latest messages in a conversation.
+    pub fn index_conversation(&mut self, conversation: &Conversation) {
+        self.search_index.index_conversation(conversation);
     }
 
     pub fn load_selected_conversation(&mut self) -> anyhow::Result<Conversation> {
-        let ids = self
-            .conversation_files
-            .keys()
-            .into_iter()
-            .map(|x| x.clone())
-            .collect::<Vec<Uuid>>();
+        let ids = self.list_ids();
 
         if let Some(id) = ids.get(self.selected_conversation) {
             self.activate_selected_conversation();
@@ -129,20 +211,14 @@ impl ConversationManager {
 
     pub fn set_active_conversation(&mut self, conversation: &Conversation) {
         self.active_conversation = self
-            .conversation_files
-            .keys()
+            .list_ids()
             .into_iter()
-            .position(|x| x == &conversation.id)
-            .unwrap();
+            .position(|x| x == conversation.id)
+            .unwrap_or(0);
     }
 
     pub fn get_selected_uuid(&mut self) -> anyhow::Result<Uuid> {
-        let ids = self
-            .conversation_files
-            .keys()
-            .into_iter()
-            .map(|x| x.clone())
-            .collect::<Vec<Uuid>>();
+        let ids = self.list_ids();
         if let Some(id) = ids.get(self.selected_conversation) {
             return Ok(*id);
         } else {
@@ -150,8 +226,26 @@ impl ConversationManager {
         }
     }
 
+    /// Find a conversation by its id, or its title.
+    pub fn find_conversation(&self, query: &str) -> Option<Uuid> {
+        if let Ok(id) = Uuid::from_str(query) {
+            if self.conversations.contains_key(&id) {
+                return Some(id);
+            }
+        }
+
+        self.conversations
+            .iter()
+            .find(|(_, metadata)| metadata.title == query)
+            .map(|(id, _)| *id)
+    }
+
     pub fn remove_conversation(&mut self, id: &Uuid) {
-        self.conversation_files.shift_remove(id);
+        self.search_index.remove_conversation(id);
+        if let Some(results) = self.search_results.as_mut() {
+            results.shift_remove(id);
+        }
+        self.conversations.shift_remove(id);
     }
 
     pub fn activate_selected_conversation(&mut self) {
@@ -159,13 +253,13 @@ impl ConversationManager {
     }
 
     pub fn select_next_conversation(&mut self) {
-        if self.selected_conversation < (self.conversation_files.len() - 1) {
+        if self.selected_conversation < (self.list_ids().len().max(1) - 1) {
             self.selected_conversation += 1;
         }
     }
 
     pub fn list_conversations(&self) -> Vec<String> {
-        self.conversation_files
+        self.conversations
             .keys()
             .into_iter()
             .map(|x| x.to_string())
@@ -173,9 +267,9 @@ impl ConversationManager {
     }
 
     pub fn list_titles(&self) -> Vec<String> {
-        self.conversation_files
-            .values()
-            .into_iter()
+        self.list_ids()
+            .iter()
+            .filter_map(|id| self.conversations.get(id))
             .map(|metadata| metadata.title.clone())
             .collect::<Vec<String>>()
     }
@@ -187,32 +281,122 @@ impl ConversationManager {
     }
 
     pub fn update_conversation(&mut self, conversation: Conversation) {
-        let metadata = ConversationMetadata {
-            path: conversation.get_file_path(),
-            title: conversation.title.unwrap_or(conversation.id.to_string()),
-        };
+        self.search_index.index_conversation(&conversation);
+        let metadata = ConversationMetadata::new(&conversation.id, conversation.title);
         *self
-            .conversation_files
+            .conversations
             .entry(conversation.id)
             .or_insert(metadata.clone()) = metadata.clone();
     }
 }
 
+/// A message in the conversation tree.
+///
+/// Messages sharing a parent are alternative branches of the conversation, ie. regenerated
+/// completions or edited user messages.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MessageNode {
+    pub parent: Option<Uuid>,
+    pub message: Message,
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "ConversationData")]
 pub struct Conversation {
     pub id: Uuid,
-    pub messages: IndexMap<Uuid, Message>,
+    /// Every message in the conversation, across all branches, in the order they were added.
+    pub nodes: IndexMap<Uuid, MessageNode>,
+    /// The last message of the active branch, the active path is found by walking its parents.
+    pub active_leaf: Option<Uuid>,
+    /// Index into the active path.
     pub selected_message: Option<usize>,
     pub title: Option<String>,
     pub profile: Profile,
     pub profile_messages: Vec<Uuid>,
 }
 
+/// Generations stored alongside a message, before conversations were stored as a tree.
+#[derive(Deserialize, Default)]
+struct LegacyAlternates {
+    previous: Vec<Message>,
+    next: Vec<Message>,
+}
+
+/// The on disk representation of a conversation, which may be in the legacy flat format.
+#[derive(Deserialize)]
+struct ConversationData {
+    id: Uuid,
+    #[serde(default)]
+    nodes: IndexMap<Uuid, MessageNode>,
+    #[serde(default)]
+    active_leaf: Option<Uuid>,
+    #[serde(default)]
+    messages: IndexMap<Uuid, Message>,
+    #[serde(default)]
+    alternates: HashMap<Uuid, LegacyAlternates>,
+    selected_message: Option<usize>,
+    title: Option<String>,
+    profile: Profile,
+    profile_messages: Vec<Uuid>,
+}
+
+impl From<ConversationData> for Conversation {
+    fn from(data: ConversationData) -> Self {
+        let mut nodes = data.nodes;
+        let mut active_leaf = data.active_leaf;
+
+        // Flat conversations are migrated to a single branch, with any alternate generations
+        // becoming sibling branches of the message they were generated for.
+        if nodes.is_empty() {
+            let mut alternates = data.alternates;
+            let mut parent = None;
+            for (id, message) in data.messages {
+                let LegacyAlternates { previous, next } =
+                    alternates.remove(&id).unwrap_or_default();
+
+                for alternate in previous {
+                    nodes.insert(
+                        Uuid::new_v4(),
+                        MessageNode {
+                            parent,
+                            message: alternate,
+                        },
+                    );
+                }
+                nodes.insert(id, MessageNode { parent, message });
+                for alternate in next.into_iter().rev() {
+                    nodes.insert(
+                        Uuid::new_v4(),
+                        MessageNode {
+                            parent,
+                            message: alternate,
+                        },
+                    );
+                }
+
+                parent = Some(id);
+            }
+            active_leaf = parent;
+        }
+
+        Conversation {
+            id: data.id,
+            nodes,
+            active_leaf,
+            selected_message: data.selected_message,
+            title: data.title,
+            profile: data.profile,
+            profile_messages: data.profile_messages,
+        }
+    }
+}
+
 impl Conversation {
     pub fn new(profile: Profile) -> Self {
         let mut convo = Conversation {
             id: Uuid::now_v7(),
-            messages: IndexMap::<Uuid, Message>::new(),
+            nodes: IndexMap::<Uuid, MessageNode>::new(),
+            active_leaf: None,
             selected_message: None,
             title: None,
             profile: profile.clone(),
@@ -222,16 +406,30 @@ impl Conversation {
         convo.set_profile(profile);
         convo
     }
-    pub fn get_file_path(&self) -> PathBuf {
-        let conversation_dir = get_conversation_dir();
-        let directory = PathBuf::from(conversation_dir);
-        let file_path = directory.join(format!("{}.json", self.id));
-        file_path
+    /// Whether a saved conversation stores its messages as a flat list, rather than a tree.
+    pub fn is_legacy_format(contents: &str) -> bool {
+        serde_json::from_str::<serde_json::Value>(contents)
+            .map(|value| value.get("nodes").is_none())
+            .unwrap_or(false)
+    }
+
+    /// Usage across every completion in the conversation, including other branches.
+    pub fn get_usage_totals(&self) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        for node in self.nodes.values() {
+            if node.message.role != MessageRole::Assistant {
+                continue;
+            }
+            if let Some(metadata) = &node.message.metadata {
+                totals.add(&metadata.usage);
+            }
+        }
+        totals
     }
 
     pub fn has_no_user_messages(&self) -> bool {
-        for (_, message) in &self.messages {
-            if message.role == MessageRole::User {
+        for (_, node) in &self.nodes {
+            if node.message.role == MessageRole::User {
                 return false;
             }
         }
@@ -244,48 +442,91 @@ impl Conversation {
 
     pub fn set_profile(&mut self, profile: Profile) {
         self.profile = profile.clone();
+        let message = Message {
+            role: MessageRole::System,
+            content: profile.system_prompt.clone(),
+            metadata: None,
+        };
 
-        // Remove Existing Profile Messages
-        for message_uuid in &self.profile_messages {
-            self.messages.shift_remove(message_uuid);
+        // The profile message is shared by every branch, so it is updated in place
+        self.profile_messages
+            .retain(|id| self.nodes.contains_key(id));
+        if let Some(profile_uuid) = self.profile_messages.first() {
+            self.replace_message(*profile_uuid, message);
+            return;
         }
 
+        // Otherwise it becomes the new root of the conversation
         let profile_uuid = self.generate_message_id();
-        self.profile_messages.push(profile_uuid);
-        self.add_message(
+        for node in self.nodes.values_mut() {
+            if node.parent.is_none() {
+                node.parent = Some(profile_uuid);
+            }
+        }
+        self.nodes.shift_insert(
+            0,
             profile_uuid,
-            Message {
-                role: MessageRole::System,
-                content: profile.system_prompt.clone(),
-                metadata: None,
+            MessageNode {
+                parent: None,
+                message,
             },
         );
+        self.profile_messages.push(profile_uuid);
+        if self.active_leaf.is_none() {
+            self.active_leaf = Some(profile_uuid);
+        }
+        self.select_last_message();
     }
 
+    /// Add a message as a child of the active leaf, making it the new active leaf.
     pub fn add_message(&mut self, id: Uuid, message: Message) {
-        self.messages.insert(id, message);
+        self.nodes.insert(
+            id,
+            MessageNode {
+                parent: self.active_leaf,
+                message,
+            },
+        );
+        self.active_leaf = Some(id);
         self.select_last_message();
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        let conversation_dir = get_conversation_dir();
-        let data = serde_json::to_string(self)?;
-        let file_path = self.get_file_path();
-        let directory = PathBuf::from(conversation_dir);
-
-        tokio::spawn(async move {
-            tokio::fs::create_dir_all(directory).await?;
-            let mut file = File::create(file_path).await?;
-            file.write_all(data.as_bytes()).await?;
-            anyhow::Ok(())
-        });
+    /// The message ids along the active branch, from the root to the active leaf.
+    pub fn get_active_path(&self) -> Vec<Uuid> {
+        let mut path = Vec::new();
+        let mut current = self.active_leaf;
+        while let Some(id) = current {
+            path.push(id);
+            current = self.nodes.get(&id).and_then(|node| node.parent);
+        }
+        path.reverse();
+        path
+    }
 
-        anyhow::Ok(())
+    /// The messages along the active branch.
+    pub fn get_messages(&self) -> Vec<(Uuid, &Message)> {
+        self.get_active_path()
+            .into_iter()
+            .filter_map(|id| self.nodes.get(&id).map(|node| (id, &node.message)))
+            .collect()
     }
+
+    /// Delete the selected message from every branch, its children are moved up to its parent.
     pub fn delete_selected_message(&mut self) {
-        if let Some(Some(uuid)) = self.selected_message.map(|idx| self.get_uuid_by_index(idx)) {
-            self.messages.remove(&uuid);
-            self.select_prev_message();
+        if let Some(uuid) = self.get_selected_uuid() {
+            if let Some(node) = self.nodes.shift_remove(&uuid) {
+                for child in self.nodes.values_mut() {
+                    if child.parent == Some(uuid) {
+                        child.parent = node.parent;
+                    }
+                }
+
+                if self.active_leaf == Some(uuid) {
+                    self.active_leaf = node.parent;
+                }
+                self.profile_messages.retain(|id| id != &uuid);
+                self.select_prev_message();
+            }
         }
     }
 
@@ -298,25 +539,131 @@ impl Conversation {
     }
 
     pub fn get_position(&self) -> (usize, usize) {
-        (self.messages.len(), self.selected_message.unwrap_or(0))
+        (
+            self.get_active_path().len(),
+            self.selected_message.unwrap_or(0),
+        )
     }
 
     pub fn get_uuid_by_index(&self, id: usize) -> Option<Uuid> {
-        Vec::from_iter(self.messages.keys()).get(id).map(|x| **x)
+        self.get_active_path().get(id).copied()
     }
 
     pub fn select_last_message(&mut self) {
-        self.selected_message = Some(self.messages.len() - 1);
+        let len = self.get_active_path().len();
+        self.selected_message = if len == 0 { None } else { Some(len - 1) };
     }
 
     pub fn replace_message(&mut self, id: Uuid, message: Message) {
-        *self.messages.get_mut(&id).unwrap() = message;
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.message = message;
+        }
+    }
+
+    /// The children of a message, or the roots of the conversation if no parent is provided.
+    pub fn get_children(&self, parent: Option<Uuid>) -> Vec<Uuid> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.parent == parent)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Every branch sharing a parent with the message provided, including itself.
+    pub fn get_siblings(&self, id: &Uuid) -> Vec<Uuid> {
+        match self.nodes.get(id) {
+            Some(node) => self.get_children(node.parent),
+            None => Vec::new(),
+        }
+    }
+
+    /// Move the active path to the parent of the message provided, so the next message added
+    /// starts a new branch alongside it.
+    pub fn branch_from(&mut self, id: &Uuid) {
+        if let Some(node) = self.nodes.get(id) {
+            self.active_leaf = node.parent;
+            self.select_last_message();
+        }
+    }
+
+    pub fn select_next_branch(&mut self, id: &Uuid) {
+        self.select_sibling_branch(id, 1);
+    }
+
+    pub fn select_prev_branch(&mut self, id: &Uuid) {
+        self.select_sibling_branch(id, -1);
+    }
+
+    /// Activate a sibling of the message provided, following its most recent descendants.
+    fn select_sibling_branch(&mut self, id: &Uuid, offset: isize) {
+        let siblings = self.get_siblings(id);
+        let Some(position) = siblings.iter().position(|x| x == id) else {
+            return;
+        };
+
+        let Some(sibling) = position
+            .checked_add_signed(offset)
+            .and_then(|idx| siblings.get(idx))
+        else {
+            return;
+        };
+
+        let mut leaf = *sibling;
+        while let Some(child) = self.get_children(Some(leaf)).last() {
+            leaf = *child;
+        }
+        self.active_leaf = Some(leaf);
+
+        // The selected message keeps its depth, unless the new branch is shorter
+        let len = self.get_active_path().len();
+        if self.selected_message.is_some_and(|idx| idx >= len) {
+            self.select_last_message();
+        }
+    }
+
+    /// Select a message, activating the branch it is on if it is not on the active path.
+    pub fn activate_message(&mut self, id: &Uuid) {
+        if !self.nodes.contains_key(id) {
+            return;
+        }
+
+        if !self.get_active_path().contains(id) {
+            let mut leaf = *id;
+            while let Some(child) = self.get_children(Some(leaf)).last() {
+                leaf = *child;
+            }
+            self.active_leaf = Some(leaf);
+        }
+
+        self.selected_message = self.get_active_path().iter().position(|x| x == id);
+    }
+
+    /// The position of a message among its sibling branches, if it has any.
+    pub fn get_branch_position(&self, id: &Uuid) -> Option<(usize, usize)> {
+        let siblings = self.get_siblings(id);
+        if siblings.len() < 2 {
+            return None;
+        }
+
+        siblings
+            .iter()
+            .position(|x| x == id)
+            .map(|idx| (idx + 1, siblings.len()))
+    }
+
+    /// All messages preceding the message provided on the active branch.
+    pub fn messages_before(&self, id: &Uuid) -> Vec<Message> {
+        self.get_messages()
+            .into_iter()
+            .take_while(|(message_id, _)| message_id != id)
+            .map(|(_, message)| message.clone())
+            .collect()
     }
 
     pub fn get_selected_message(&self) -> anyhow::Result<Message> {
         if let Some(Some(uuid)) = self.selected_message.map(|idx| self.get_uuid_by_index(idx)) {
-            if let Some(message) = self.messages.get(&uuid) {
-                return anyhow::Ok(message.clone());
+            if let Some(node) = self.nodes.get(&uuid) {
+                return anyhow::Ok(node.message.clone());
             }
         }
         return Err(anyhow!("Could not retrieve message"));
@@ -324,7 +671,7 @@ impl Conversation {
 
     pub fn select_next_message(&mut self) {
         if let Some(currently_selected) = self.selected_message {
-            if currently_selected < (self.messages.len() - 1) {
+            if currently_selected < (self.get_active_path().len().max(1) - 1) {
                 self.selected_message = Some(currently_selected + 1);
             }
         } else {
@@ -342,3 +689,73 @@ impl Conversation {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy_conversation() {
+        let (system, user, assistant) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        // Built as a string, as `json!` does not preserve the order of the messages
+        let contents = format!(
+            r#"{{
+                "id": "{id}",
+                "messages": {{
+                    "{system}": {{"role": "System", "content": "prompt", "metadata": null}},
+                    "{user}": {{"role": "User", "content": "hello", "metadata": null}},
+                    "{assistant}": {{"role": "Assistant", "content": "second", "metadata": null}}
+                }},
+                "alternates": {{
+                    "{assistant}": {{
+                        "previous": [{{"role": "Assistant", "content": "first", "metadata": null}}],
+                        "next": [{{"role": "Assistant", "content": "third", "metadata": null}}]
+                    }}
+                }},
+                "selected_message": 2,
+                "title": null,
+                "profile": {{"name": "Default", "system_prompt": "prompt"}},
+                "profile_messages": ["{system}"]
+            }}"#,
+            id = Uuid::now_v7(),
+        );
+
+        assert!(Conversation::is_legacy_format(contents.as_str()));
+        let convo: Conversation = serde_json::from_str(contents.as_str()).unwrap();
+        assert_eq!(convo.get_active_path(), vec![system, user, assistant]);
+        assert_eq!(convo.get_branch_position(&assistant), Some((2, 3)));
+
+        let saved = serde_json::to_string(&convo).unwrap();
+        assert!(!Conversation::is_legacy_format(saved.as_str()));
+        let reloaded: Conversation = serde_json::from_str(saved.as_str()).unwrap();
+        assert_eq!(reloaded, convo);
+    }
+
+    #[test]
+    fn test_switch_branches() {
+        let mut convo = Conversation::new(Profile::default());
+        let (user, first, second, reply) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        convo.add_message(user, Message::new(MessageRole::User, "hello"));
+        convo.add_message(first, Message::new(MessageRole::Assistant, "first"));
+        convo.add_message(reply, Message::new(MessageRole::User, "reply"));
+
+        convo.branch_from(&first);
+        convo.add_message(second, Message::new(MessageRole::Assistant, "second"));
+        assert_eq!(convo.get_active_path()[1..], [user, second]);
+        assert_eq!(convo.get_branch_position(&second), Some((2, 2)));
+        assert_eq!(convo.messages_before(&second).len(), 2);
+
+        // Switching back follows the descendants of the previous branch
+        convo.select_prev_branch(&second);
+        assert_eq!(convo.get_active_path()[1..], [user, first, reply]);
+        convo.select_prev_branch(&first);
+        assert_eq!(convo.active_leaf, Some(reply));
+        convo.select_next_branch(&first);
+        assert_eq!(convo.active_leaf, Some(second));
+    }
+}
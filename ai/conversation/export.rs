@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use super::Conversation;
+use crate::ai::completion::{Message, MessageRole};
+use crate::ai::config::Profile;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Display, EnumString, Serialize, Deserialize,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    #[strum(serialize = "markdown", serialize = "md")]
+    Markdown,
+    Html,
+    #[strum(serialize = "text", serialize = "txt")]
+    Text,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Text => "txt",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportedConversation<'a> {
+    title: String,
+    profile: &'a Profile,
+    messages: Vec<&'a Message>,
+}
+
+impl Conversation {
+    /// Render the active branch of the conversation, along with its profile.
+    pub fn export(&self, format: ExportFormat) -> anyhow::Result<String> {
+        let title = self.title.clone().unwrap_or(self.id.to_string());
+        let messages = self
+            .get_messages()
+            .into_iter()
+            .map(|(_, message)| message)
+            .filter(|message| message.role != MessageRole::System)
+            .collect::<Vec<&Message>>();
+
+        let exported = match format {
+            ExportFormat::Markdown => export_markdown(&title, &self.profile, &messages),
+            ExportFormat::Html => export_html(&title, &self.profile, &messages),
+            ExportFormat::Text => export_text(&title, &self.profile, &messages),
+            ExportFormat::Json => serde_json::to_string_pretty(&ExportedConversation {
+                title,
+                profile: &self.profile,
+                messages,
+            })?,
+        };
+
+        anyhow::Ok(exported)
+    }
+}
+
+/// The role of a message, followed by the model and status for assistant messages, or the call
+/// for tool messages.
+fn get_header(message: &Message) -> String {
+    let role = match message.role {
+        MessageRole::System => "System",
+        MessageRole::User => "User",
+        MessageRole::Assistant => "Assistant",
+        MessageRole::Tool(_) => "Tool",
+    };
+
+    match (&message.role, &message.metadata) {
+        (MessageRole::Assistant, Some(metadata)) => format!(
+            "{role} ({}, {:?})",
+            metadata.model_config.model_id, metadata.status
+        ),
+        (MessageRole::Tool(call), _) => format!("{role} ({}, {})", call.name, call.arguments),
+        _ => role.to_string(),
+    }
+}
+
+fn export_markdown(title: &str, profile: &Profile, messages: &[&Message]) -> String {
+    let mut output = format!("# {title}\n\n**Profile:** {}\n", profile.name);
+    for line in profile.system_prompt.trim().lines() {
+        output.push_str(format!("\n> {line}").trim_end());
+    }
+    output.push('\n');
+
+    for message in messages {
+        output.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            get_header(message),
+            message.content.trim()
+        ));
+    }
+
+    output
+}
+
+fn export_text(title: &str, profile: &Profile, messages: &[&Message]) -> String {
+    let mut output = format!(
+        "{title}\n{}\n\nProfile: {}\n{}\n",
+        "=".repeat(title.chars().count()),
+        profile.name,
+        profile.system_prompt.trim()
+    );
+
+    for message in messages {
+        let header = get_header(message);
+        output.push_str(&format!(
+            "\n{header}\n{}\n{}\n",
+            "-".repeat(header.chars().count()),
+            message.content.trim()
+        ));
+    }
+
+    output
+}
+
+fn export_html(title: &str, profile: &Profile, messages: &[&Message]) -> String {
+    let mut output = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n<p><strong>Profile:</strong> {name}</p>\n<blockquote>{prompt}</blockquote>\n",
+        title = escape_html(title),
+        name = escape_html(&profile.name),
+        prompt = escape_html(profile.system_prompt.trim()),
+    );
+
+    for message in messages {
+        output.push_str(&format!(
+            "<h2>{}</h2>\n<pre style=\"white-space: pre-wrap\">{}</pre>\n",
+            escape_html(&get_header(message)),
+            escape_html(message.content.trim())
+        ));
+    }
+
+    output.push_str("</body>\n</html>\n");
+    output
+}
+
+fn escape_html(content: &str) -> String {
+    content
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::completion::{CompletionStatus, MessageMetadata};
+    use crate::ai::config::ARCHER_CONFIG;
+    use uuid::Uuid;
+
+    fn get_conversation() -> Conversation {
+        let mut convo = Conversation::new(Profile {
+            name: "Default".to_string(),
+            system_prompt: "Be <brief>.".to_string(),
+            ..Default::default()
+        });
+        convo.title = Some("Greeting".to_string());
+        convo.add_message(
+            Uuid::new_v4(),
+            Message {
+                role: MessageRole::User,
+                content: "Hello".to_string(),
+                metadata: None,
+            },
+        );
+        convo.add_message(
+            Uuid::new_v4(),
+            Message {
+                role: MessageRole::Assistant,
+                content: "Hi!".to_string(),
+                metadata: Some(MessageMetadata {
+                    model_config: ARCHER_CONFIG.default_completion_model.clone(),
+                    status: CompletionStatus::Succeeded,
+                    usage: Default::default(),
+                    error: None,
+                    fallback: None,
+                }),
+            },
+        );
+        convo
+    }
+
+    #[test]
+    fn test_export_markdown() {
+        let exported = get_conversation().export(ExportFormat::Markdown).unwrap();
+        let model_id = &ARCHER_CONFIG.default_completion_model.model_id;
+        assert_eq!(
+            exported,
+            format!("# Greeting\n\n**Profile:** Default\n\n> Be <brief>.\n\n## User\n\nHello\n\n## Assistant ({model_id}, Succeeded)\n\nHi!\n")
+        );
+    }
+
+    #[test]
+    fn test_export_html_escapes_content() {
+        let exported = get_conversation().export(ExportFormat::Html).unwrap();
+        assert!(exported.contains("<blockquote>Be &lt;brief&gt;.</blockquote>"));
+        assert!(!exported.contains("<brief>"));
+    }
+
+    #[test]
+    fn test_parse_export_format() {
+        assert_eq!(
+            "md".parse::<ExportFormat>().unwrap(),
+            ExportFormat::Markdown
+        );
+        assert_eq!("HTML".parse::<ExportFormat>().unwrap(), ExportFormat::Html);
+        assert!("pdf".parse::<ExportFormat>().is_err());
+    }
+}
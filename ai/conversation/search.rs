@@ -0,0 +1,153 @@
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+use super::Conversation;
+
+/// Where a word was found, the message is `None` for words in the conversation title.
+type Posting = (Uuid, Option<Uuid>);
+
+/// An inverted index from words to the conversations and messages containing them.
+///
+/// Query terms are matched as word prefixes, so results can be filtered as the query is typed.
+#[derive(Default)]
+pub struct SearchIndex {
+    words: BTreeMap<String, BTreeSet<Posting>>,
+    conversation_words: HashMap<Uuid, BTreeSet<String>>,
+}
+
+/// Split content into lowercase alphanumeric words.
+pub fn tokenize(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+impl SearchIndex {
+    /// Add every message across all branches of a conversation, replacing any earlier entries.
+    pub fn index_conversation(&mut self, conversation: &Conversation) {
+        self.remove_conversation(&conversation.id);
+
+        let mut entries = Vec::<(String, Posting)>::new();
+        if let Some(title) = &conversation.title {
+            for word in tokenize(title) {
+                entries.push((word, (conversation.id, None)));
+            }
+        }
+        for (message_id, node) in &conversation.nodes {
+            for word in tokenize(&node.message.content) {
+                entries.push((word, (conversation.id, Some(*message_id))));
+            }
+        }
+
+        let words = self.conversation_words.entry(conversation.id).or_default();
+        for (word, posting) in entries {
+            words.insert(word.clone());
+            self.words.entry(word).or_default().insert(posting);
+        }
+    }
+
+    pub fn remove_conversation(&mut self, id: &Uuid) {
+        for word in self.conversation_words.remove(id).unwrap_or_default() {
+            if let Some(postings) = self.words.get_mut(&word) {
+                postings.retain(|(conversation_id, _)| conversation_id != id);
+                if postings.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Find the conversations containing every term in the query, along with the messages
+    /// which contain any of the terms.
+    pub fn search(&self, query: &str) -> IndexMap<Uuid, Vec<Uuid>> {
+        let mut results: Option<HashMap<Uuid, BTreeSet<Uuid>>> = None;
+        for term in tokenize(query) {
+            let mut matches = HashMap::<Uuid, BTreeSet<Uuid>>::new();
+            for (_, postings) in self
+                .words
+                .range(term.clone()..)
+                .take_while(|(word, _)| word.starts_with(term.as_str()))
+            {
+                for (conversation_id, message_id) in postings {
+                    let messages = matches.entry(*conversation_id).or_default();
+                    messages.extend(message_id);
+                }
+            }
+
+            results = Some(match results {
+                None => matches,
+                Some(mut results) => {
+                    results.retain(|id, _| matches.contains_key(id));
+                    for (id, messages) in results.iter_mut() {
+                        messages.extend(matches.remove(id).unwrap_or_default());
+                    }
+                    results
+                }
+            });
+        }
+
+        results
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, messages)| (id, messages.into_iter().collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::completion::{Message, MessageRole};
+    use crate::ai::config::Profile;
+
+    fn get_conversation(title: &str, contents: &[&str]) -> (Conversation, Vec<Uuid>) {
+        let mut convo = Conversation::new(Profile::default());
+        convo.title = Some(title.to_string());
+        let ids = contents
+            .iter()
+            .map(|content| {
+                let id = Uuid::new_v4();
+                convo.add_message(
+                    id,
+                    Message {
+                        role: MessageRole::User,
+                        content: content.to_string(),
+                        metadata: None,
+                    },
+                );
+                id
+            })
+            .collect();
+        (convo, ids)
+    }
+
+    #[test]
+    fn test_search_prefixes_across_messages() {
+        let (rust, rust_ids) = get_conversation("Rust", &["Borrow checker errors", "Lifetimes"]);
+        let (python, _) = get_conversation("Python", &["Decorators and lifetimes?"]);
+
+        let mut index = SearchIndex::default();
+        index.index_conversation(&rust);
+        index.index_conversation(&python);
+
+        let results = index.search("borr LIFE");
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results.get(&rust.id),
+            Some(&{
+                let mut ids = rust_ids.clone();
+                ids.sort();
+                ids
+            })
+        );
+
+        // Title matches have no matching messages
+        assert_eq!(index.search("python").get(&python.id), Some(&Vec::new()));
+        assert!(index.search("").is_empty());
+
+        index.remove_conversation(&rust.id);
+        assert_eq!(index.search("lifetimes").len(), 1);
+    }
+}
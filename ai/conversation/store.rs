@@ -0,0 +1,392 @@
+use anyhow::anyhow;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+use super::Conversation;
+use crate::ai::usage::{CompletionUsage, UsageTotals};
+
+/// Where conversations are persisted, selected with `storage` in the config.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    #[default]
+    Sqlite,
+    Json,
+}
+
+/// Persistence for conversations.
+///
+/// Listing only returns titles, so the full conversation is only read when it is loaded.
+pub trait ConversationStore {
+    /// The id and title of every stored conversation, in the order they were created.
+    fn list_conversations(&self) -> anyhow::Result<Vec<(Uuid, Option<String>)>>;
+    fn load_conversation(&self, id: &Uuid) -> anyhow::Result<Conversation>;
+    fn save_conversation(&self, conversation: &Conversation) -> anyhow::Result<()>;
+    fn delete_conversation(&self, id: &Uuid) -> anyhow::Result<()>;
+    /// Add a completion to the running total for a day.
+    fn record_usage(&self, day: &str, usage: &CompletionUsage) -> anyhow::Result<()>;
+    fn get_usage(&self, day: &str) -> anyhow::Result<UsageTotals>;
+}
+
+/// Stores every conversation as a row in a SQLite database.
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS conversations (
+                id TEXT PRIMARY KEY,
+                title TEXT,
+                data TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS imports (
+                source TEXT PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS usage (
+                day TEXT PRIMARY KEY,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                completions INTEGER NOT NULL
+            );",
+        )?;
+
+        anyhow::Ok(SqliteStore { connection })
+    }
+
+    /// Import the conversation files in `directory` the first time it is seen.
+    ///
+    /// The files are left in place, so the json store can still be switched back to.
+    /// Returns the number of conversations imported.
+    pub fn import_json_files(&mut self, directory: &Path) -> anyhow::Result<usize> {
+        let source = directory.to_string_lossy().to_string();
+        let imported: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT source FROM imports WHERE source = ?1",
+                params![source],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if imported.is_some() {
+            return anyhow::Ok(0);
+        }
+
+        let mut conversations = JsonFileStore::new(directory.to_path_buf())
+            .read_all()
+            .into_iter()
+            .map(|(_, conversation)| conversation)
+            .collect::<Vec<Conversation>>();
+        conversations.sort_by_key(|conversation| conversation.id);
+
+        let transaction = self.connection.transaction()?;
+        for conversation in &conversations {
+            transaction.execute(
+                "INSERT OR IGNORE INTO conversations (id, title, data) VALUES (?1, ?2, ?3)",
+                params![
+                    conversation.id.to_string(),
+                    conversation.title,
+                    serde_json::to_string(conversation)?
+                ],
+            )?;
+        }
+        transaction.execute("INSERT INTO imports (source) VALUES (?1)", params![source])?;
+        transaction.commit()?;
+
+        anyhow::Ok(conversations.len())
+    }
+}
+
+impl ConversationStore for SqliteStore {
+    fn list_conversations(&self) -> anyhow::Result<Vec<(Uuid, Option<String>)>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, title FROM conversations ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?;
+
+        let mut conversations = Vec::new();
+        for row in rows {
+            let (id, title) = row?;
+            conversations.push((Uuid::parse_str(id.as_str())?, title));
+        }
+
+        anyhow::Ok(conversations)
+    }
+
+    fn load_conversation(&self, id: &Uuid) -> anyhow::Result<Conversation> {
+        let data: String = self
+            .connection
+            .query_row(
+                "SELECT data FROM conversations WHERE id = ?1",
+                params![id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or(anyhow!("conversation {id} not found"))?;
+
+        anyhow::Ok(serde_json::from_str(data.as_str())?)
+    }
+
+    fn save_conversation(&self, conversation: &Conversation) -> anyhow::Result<()> {
+        // Upserting keeps the rowid, so conversations stay in the order they were created
+        self.connection.execute(
+            "INSERT INTO conversations (id, title, data) VALUES (?1, ?2, ?3)
+            ON CONFLICT(id) DO UPDATE SET title = excluded.title, data = excluded.data",
+            params![
+                conversation.id.to_string(),
+                conversation.title,
+                serde_json::to_string(conversation)?
+            ],
+        )?;
+
+        anyhow::Ok(())
+    }
+
+    fn delete_conversation(&self, id: &Uuid) -> anyhow::Result<()> {
+        self.connection.execute(
+            "DELETE FROM conversations WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+
+        anyhow::Ok(())
+    }
+
+    fn record_usage(&self, day: &str, usage: &CompletionUsage) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT INTO usage (day, prompt_tokens, completion_tokens, completions)
+            VALUES (?1, ?2, ?3, 1)
+            ON CONFLICT(day) DO UPDATE SET
+                prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+                completion_tokens = completion_tokens + excluded.completion_tokens,
+                completions = completions + 1",
+            params![
+                day,
+                usage.prompt_tokens.unwrap_or_default() as i64,
+                usage.completion_tokens.unwrap_or_default() as i64
+            ],
+        )?;
+
+        anyhow::Ok(())
+    }
+
+    fn get_usage(&self, day: &str) -> anyhow::Result<UsageTotals> {
+        let totals = self
+            .connection
+            .query_row(
+                "SELECT prompt_tokens, completion_tokens, completions FROM usage WHERE day = ?1",
+                params![day],
+                |row| {
+                    Ok(UsageTotals {
+                        prompt_tokens: row.get::<_, i64>(0)? as u64,
+                        completion_tokens: row.get::<_, i64>(1)? as u64,
+                        completions: row.get::<_, i64>(2)? as u64,
+                    })
+                },
+            )
+            .optional()?;
+
+        anyhow::Ok(totals.unwrap_or_default())
+    }
+}
+
+/// Daily usage totals are kept alongside the conversation files, under a name which is not
+/// picked up as a conversation.
+const USAGE_FILE: &str = "usage.totals";
+
+/// Stores every conversation as a json file in a directory.
+pub struct JsonFileStore {
+    directory: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new(directory: PathBuf) -> Self {
+        JsonFileStore { directory }
+    }
+
+    fn get_file_path(&self, id: &Uuid) -> PathBuf {
+        self.directory.join(format!("{}.json", id))
+    }
+
+    fn read_usage(&self) -> anyhow::Result<HashMap<String, UsageTotals>> {
+        let path = self.directory.join(USAGE_FILE);
+        if !path.exists() {
+            return anyhow::Ok(HashMap::new());
+        }
+
+        anyhow::Ok(serde_json::from_str(
+            std::fs::read_to_string(path)?.as_str(),
+        )?)
+    }
+
+    /// Parse every conversation file in the directory, skipping any which are invalid.
+    ///
+    /// Files saved before messages were stored as a tree are rewritten in the current format.
+    fn read_all(&self) -> Vec<(PathBuf, Conversation)> {
+        let mut conversations = Vec::new();
+        for entry in WalkDir::new(&self.directory).into_iter().flatten() {
+            if entry.path().extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            let path = entry.path().to_path_buf();
+            let Ok(contents) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok(conversation) = serde_json::from_str::<Conversation>(contents.as_str()) else {
+                continue;
+            };
+
+            if Conversation::is_legacy_format(contents.as_str()) {
+                if let Ok(data) = serde_json::to_string(&conversation) {
+                    std::fs::write(&path, data).ok();
+                }
+            }
+
+            conversations.push((path, conversation));
+        }
+
+        conversations
+    }
+}
+
+impl ConversationStore for JsonFileStore {
+    fn list_conversations(&self) -> anyhow::Result<Vec<(Uuid, Option<String>)>> {
+        anyhow::Ok(
+            self.read_all()
+                .into_iter()
+                .map(|(_, conversation)| (conversation.id, conversation.title))
+                .collect(),
+        )
+    }
+
+    fn load_conversation(&self, id: &Uuid) -> anyhow::Result<Conversation> {
+        let contents = std::fs::read_to_string(self.get_file_path(id))?;
+        anyhow::Ok(serde_json::from_str(contents.as_str())?)
+    }
+
+    fn save_conversation(&self, conversation: &Conversation) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+
+        // Write to a temporary file first, so a failed write can't truncate the conversation
+        let file_path = self.get_file_path(&conversation.id);
+        let temp_path = file_path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string(conversation)?)?;
+        std::fs::rename(temp_path, file_path)?;
+
+        anyhow::Ok(())
+    }
+
+    fn delete_conversation(&self, id: &Uuid) -> anyhow::Result<()> {
+        std::fs::remove_file(self.get_file_path(id))?;
+        anyhow::Ok(())
+    }
+
+    fn record_usage(&self, day: &str, usage: &CompletionUsage) -> anyhow::Result<()> {
+        let mut totals = self.read_usage()?;
+        totals.entry(day.to_string()).or_default().add(usage);
+
+        std::fs::create_dir_all(&self.directory)?;
+        std::fs::write(
+            self.directory.join(USAGE_FILE),
+            serde_json::to_string(&totals)?,
+        )?;
+        anyhow::Ok(())
+    }
+
+    fn get_usage(&self, day: &str) -> anyhow::Result<UsageTotals> {
+        anyhow::Ok(self.read_usage()?.remove(day).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::config::Profile;
+
+    #[test]
+    fn test_sqlite_store_round_trip() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let mut first = Conversation::new(Profile::default());
+        let second = Conversation::new(Profile::default());
+        store.save_conversation(&first).unwrap();
+        store.save_conversation(&second).unwrap();
+
+        first.title = Some("First".to_string());
+        store.save_conversation(&first).unwrap();
+
+        assert_eq!(
+            store.list_conversations().unwrap(),
+            vec![(first.id, Some("First".to_string())), (second.id, None)]
+        );
+        assert_eq!(store.load_conversation(&first.id).unwrap(), first);
+
+        store.delete_conversation(&first.id).unwrap();
+        assert!(store.load_conversation(&first.id).is_err());
+        assert_eq!(store.list_conversations().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sqlite_store_usage_totals() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        store
+            .record_usage(
+                "2024-01-01",
+                &CompletionUsage::from_tokens(Some(10), Some(20)),
+            )
+            .unwrap();
+        store
+            .record_usage("2024-01-01", &CompletionUsage::from_tokens(Some(5), None))
+            .unwrap();
+
+        assert_eq!(
+            store.get_usage("2024-01-01").unwrap(),
+            UsageTotals {
+                prompt_tokens: 15,
+                completion_tokens: 20,
+                completions: 2,
+            }
+        );
+        assert_eq!(
+            store.get_usage("2024-01-02").unwrap(),
+            UsageTotals::default()
+        );
+    }
+
+    #[test]
+    fn test_import_json_files_once() {
+        let directory = std::env::temp_dir().join(format!("archer-import-{}", Uuid::new_v4()));
+        let conversation = Conversation::new(Profile::default());
+        JsonFileStore::new(directory.clone())
+            .save_conversation(&conversation)
+            .unwrap();
+        std::fs::write(directory.join("invalid.json"), "{").unwrap();
+
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        assert_eq!(store.import_json_files(&directory).unwrap(), 1);
+        assert_eq!(store.import_json_files(&directory).unwrap(), 0);
+        assert_eq!(
+            store.load_conversation(&conversation.id).unwrap(),
+            conversation
+        );
+
+        std::fs::remove_dir_all(directory).ok();
+    }
+}
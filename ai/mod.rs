@@ -1,5 +1,10 @@
+pub mod attachments;
 pub mod completion;
 pub mod config;
+pub mod context;
 pub mod conversation;
 pub mod prompt;
 pub mod providers;
+pub mod retrieval;
+pub mod tools;
+pub mod usage;
@@ -10,7 +10,7 @@ impl PromptTemplate for ChatMLTemplate {
         let mut system_prompt = String::new();
         let mut prompt = String::new();
         for message in messages {
-            match message.role {
+            match &message.role {
                 MessageRole::System => system_prompt.push_str(message.content.as_str()),
                 MessageRole::User => {
                     prompt.push_str(
@@ -23,6 +23,15 @@ impl PromptTemplate for ChatMLTemplate {
                         format!("<|im_start|>assistant\n{}<|im_end|>\n", message.content).as_str(),
                     );
                 }
+                MessageRole::Tool(call) => {
+                    prompt.push_str(
+                        format!(
+                            "<|im_start|>user\n{}<|im_end|>\n",
+                            call.format_result(&message.content)
+                        )
+                        .as_str(),
+                    );
+                }
             }
         }
 
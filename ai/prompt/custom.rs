@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use super::{PromptResult, PromptTemplate};
+use crate::ai::completion::{Message, MessageRole};
+
+/// Where the system prompt is put in the generated prompt.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub enum SystemPlacement {
+    /// As its own turn, wrapped in the system prefix and suffix.
+    #[default]
+    Separate,
+    /// At the start of the first user message, for models without a system role.
+    FirstUser,
+    /// Left out of the prompt, and only passed to providers which accept it separately.
+    Excluded,
+}
+
+/// A prompt format defined in config, so new model families can be used without code changes.
+///
+/// Each message is wrapped in the prefix and suffix for its role, ie. Zephyr wraps user messages
+/// in `<|user|>\n` and `</s>\n`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CustomTemplate {
+    pub name: String,
+    /// Added once, at the start of the prompt.
+    pub bos_token: String,
+    /// Added after each assistant message.
+    pub eos_token: String,
+    pub system_prefix: String,
+    pub system_suffix: String,
+    pub user_prefix: String,
+    pub user_suffix: String,
+    pub assistant_prefix: String,
+    pub assistant_suffix: String,
+    pub system_placement: SystemPlacement,
+    /// Added at the end of the prompt to start the response, defaults to the assistant prefix.
+    pub generation_prefix: Option<String>,
+}
+
+impl PromptTemplate for CustomTemplate {
+    fn generate_prompt(&self, messages: &Vec<Message>) -> PromptResult {
+        let system_prompt = messages
+            .iter()
+            .filter(|message| message.role == MessageRole::System)
+            .map(|message| message.content.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        let mut prompt = self.bos_token.clone();
+        let mut pending_system_prompt = match self.system_placement {
+            SystemPlacement::Separate => {
+                if !system_prompt.is_empty() {
+                    prompt.push_str(&self.system_prefix);
+                    prompt.push_str(&system_prompt);
+                    prompt.push_str(&self.system_suffix);
+                }
+                None
+            }
+            SystemPlacement::FirstUser => Some(system_prompt.clone()),
+            SystemPlacement::Excluded => None,
+        };
+
+        for message in messages {
+            let content = match &message.role {
+                MessageRole::System => continue,
+                MessageRole::Assistant => {
+                    prompt.push_str(&self.assistant_prefix);
+                    prompt.push_str(&message.content);
+                    prompt.push_str(&self.assistant_suffix);
+                    prompt.push_str(&self.eos_token);
+                    continue;
+                }
+                MessageRole::User => message.content.clone(),
+                MessageRole::Tool(call) => call.format_result(&message.content),
+            };
+
+            prompt.push_str(&self.user_prefix);
+            if let Some(system_prompt) = pending_system_prompt.take() {
+                if !system_prompt.is_empty() {
+                    prompt.push_str(&format!("{system_prompt}\n\n"));
+                }
+            }
+            prompt.push_str(&content);
+            prompt.push_str(&self.user_suffix);
+        }
+
+        prompt.push_str(
+            self.generation_prefix
+                .as_ref()
+                .unwrap_or(&self.assistant_prefix),
+        );
+
+        // The system prompt is already placed in the prompt, unless it is excluded
+        let prompt_template = "{prompt}".to_string();
+        let full_prompt = prompt.clone();
+
+        PromptResult {
+            prompt,
+            system_prompt,
+            prompt_template,
+            full_prompt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_template() {
+        let messages = vec![
+            Message::new(MessageRole::System, "Be brief."),
+            Message::new(MessageRole::User, "Hi"),
+            Message::new(MessageRole::Assistant, "Hello"),
+            Message::new(MessageRole::User, "Bye"),
+        ];
+
+        let zephyr = CustomTemplate {
+            system_prefix: "<|system|>\n".to_string(),
+            system_suffix: "</s>\n".to_string(),
+            user_prefix: "<|user|>\n".to_string(),
+            user_suffix: "</s>\n".to_string(),
+            assistant_prefix: "<|assistant|>\n".to_string(),
+            assistant_suffix: "</s>\n".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            zephyr.generate_prompt(&messages).full_prompt,
+            "<|system|>\nBe brief.</s>\n<|user|>\nHi</s>\n<|assistant|>\nHello</s>\n<|user|>\nBye</s>\n<|assistant|>\n"
+        );
+
+        let gemma = CustomTemplate {
+            bos_token: "<bos>".to_string(),
+            user_prefix: "<start_of_turn>user\n".to_string(),
+            user_suffix: "<end_of_turn>\n".to_string(),
+            assistant_prefix: "<start_of_turn>model\n".to_string(),
+            assistant_suffix: "<end_of_turn>\n".to_string(),
+            system_placement: SystemPlacement::FirstUser,
+            ..Default::default()
+        };
+        assert_eq!(
+            gemma.generate_prompt(&messages).full_prompt,
+            "<bos><start_of_turn>user\nBe brief.\n\nHi<end_of_turn>\n<start_of_turn>model\nHello<end_of_turn>\n<start_of_turn>user\nBye<end_of_turn>\n<start_of_turn>model\n"
+        );
+    }
+}
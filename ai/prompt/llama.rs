@@ -12,7 +12,7 @@ impl PromptTemplate for LlamaTemplate {
         let mut system_prompt = String::new();
         let mut prompt = "<s>".to_string();
         for message in messages {
-            match message.role {
+            match &message.role {
                 MessageRole::System => system_prompt.push_str(message.content.as_str()),
                 MessageRole::User => {
                     if !first_message {
@@ -25,6 +25,15 @@ impl PromptTemplate for LlamaTemplate {
                 MessageRole::Assistant => {
                     prompt.push_str(format!("{}</s>", message.content).as_str());
                 }
+                MessageRole::Tool(call) => {
+                    if !first_message {
+                        prompt.push_str("<s>");
+                    }
+                    prompt.push_str(
+                        format!("[INST] {} [/INST]", call.format_result(&message.content)).as_str(),
+                    );
+                    first_message = false;
+                }
             }
         }
 
@@ -10,7 +10,7 @@ impl PromptTemplate for MistralTemplate {
         let mut system_prompt = String::new();
         let mut prompt = "<s>".to_string();
         for message in messages {
-            match message.role {
+            match &message.role {
                 MessageRole::System => system_prompt.push_str(message.content.as_str()),
                 MessageRole::User => {
                     prompt
@@ -19,6 +19,11 @@ impl PromptTemplate for MistralTemplate {
                 MessageRole::Assistant => {
                     prompt.push_str(format!("{}</s>", message.content).as_str());
                 }
+                MessageRole::Tool(call) => {
+                    prompt.push_str(
+                        format!("[INST] {} [/INST]", call.format_result(&message.content)).as_str(),
+                    );
+                }
             }
         }
 
@@ -1,13 +1,17 @@
 mod chatml;
+mod custom;
 mod llama;
 mod mistral;
 use super::completion::Message;
+use super::config::ARCHER_CONFIG;
 use serde::{
     de::{self, Deserializer, Visitor},
-    Deserialize, Serialize,
+    Deserialize, Serialize, Serializer,
 };
 use std::fmt;
 
+pub use custom::{CustomTemplate, SystemPlacement};
+
 pub trait PromptTemplate {
     fn generate_prompt(&self, messages: &Vec<Message>) -> PromptResult;
 }
@@ -20,11 +24,14 @@ pub struct PromptResult {
     pub full_prompt: String,
 }
 
-#[derive(Serialize, PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
 pub enum PromptTemplateVariant {
+    #[default]
     ChatML,
     Mistral,
     Llama,
+    /// A template from the `templates` in config, by name.
+    Custom(String),
 }
 
 impl PromptTemplateVariant {
@@ -33,10 +40,37 @@ impl PromptTemplateVariant {
             PromptTemplateVariant::ChatML => Box::new(chatml::ChatMLTemplate::default()),
             PromptTemplateVariant::Mistral => Box::new(mistral::MistralTemplate::default()),
             PromptTemplateVariant::Llama => Box::new(llama::LlamaTemplate::default()),
+            PromptTemplateVariant::Custom(name) => {
+                match ARCHER_CONFIG.get_template(name) {
+                    Some(template) => Box::new(template.clone()),
+                    // Models can only reference configured templates, so this falls back to
+                    // the default for messages saved with a template which has since been removed
+                    None => Box::new(chatml::ChatMLTemplate::default()),
+                }
+            }
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        match self {
+            PromptTemplateVariant::ChatML => "ChatML",
+            PromptTemplateVariant::Mistral => "Mistral",
+            PromptTemplateVariant::Llama => "Llama",
+            PromptTemplateVariant::Custom(name) => name,
         }
     }
 }
 
+// Templates are written as their name, for both built in and custom templates
+impl Serialize for PromptTemplateVariant {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.get_name())
+    }
+}
+
 impl<'de> Deserialize<'de> for PromptTemplateVariant {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -59,10 +93,9 @@ impl<'de> Deserialize<'de> for PromptTemplateVariant {
                     "ChatML" => Ok(PromptTemplateVariant::ChatML),
                     "Mistral" => Ok(PromptTemplateVariant::Mistral),
                     "Llama" => Ok(PromptTemplateVariant::Llama),
-                    _ => Err(E::custom(format!(
-                        "Unknown Prompt Template variant: {}",
-                        value
-                    ))),
+                    "" => Err(E::custom("Prompt Template cannot be empty")),
+                    // Custom templates are checked against config when it is validated
+                    name => Ok(PromptTemplateVariant::Custom(name.to_string())),
                 }
             }
         }
@@ -0,0 +1,458 @@
+use crate::ai::completion::{
+    check_response, CompletionError, CompletionModel, CompletionProvider, CompletionResult,
+    CompletionStatus, Message, MessageRole, ERROR_EVENT,
+};
+use crate::ai::config::{merge, ModelConfig};
+use crate::ai::tools::{get_tool_calls, list_tools, ToolCall};
+use crate::ai::usage::CompletionUsage;
+use anyhow::anyhow;
+use async_stream::stream;
+use async_trait::async_trait;
+use eventsource_stream::Eventsource;
+use futures::Stream;
+use futures_lite::StreamExt;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::json;
+use std::env::var;
+use std::pin::Pin;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Default)]
+pub struct Anthropic {
+    api_key: Option<String>,
+    base_url: String,
+}
+
+impl CompletionProvider for Anthropic {
+    fn load() -> Self {
+        let base_url = "https://api.anthropic.com/v1".to_string();
+        Anthropic {
+            api_key: var("ANTHROPIC_API_KEY").ok(),
+            base_url,
+        }
+    }
+    fn has_credentials(&self) -> bool {
+        self.api_key.is_some()
+    }
+    fn get_model(&self, model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
+        if model_config.provider_id == self.get_id() {
+            return anyhow::Ok(Box::new(AnthropicCompletionModel::load(
+                model_config.clone(),
+            )));
+        }
+        Err(anyhow!("model_config provider does not match provider"))
+    }
+
+    fn get_id(&self) -> String {
+        "Anthropic".to_string()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct AnthropicCompletionModel {
+    model_config: ModelConfig,
+}
+
+impl AnthropicCompletionModel {
+    pub fn load(model_config: ModelConfig) -> Self {
+        AnthropicCompletionModel { model_config }
+    }
+
+    pub fn get_inputs(&self, messages: &[Message], stream: bool) -> serde_json::Value {
+        // The Messages API takes the system prompt as a top level field, and expects
+        // strictly alternating user/assistant turns, so consecutive roles are joined.
+        // Tool calls are content blocks of the assistant turn, and their results are
+        // content blocks of the following user turn.
+        let mut system_prompts = Vec::<&str>::new();
+        let mut turns = Vec::<(&str, Vec<serde_json::Value>)>::new();
+        for (index, message) in messages.iter().enumerate() {
+            let text = if message.content.trim().is_empty() {
+                Vec::new()
+            } else {
+                vec![json!({"type": "text", "text": message.content})]
+            };
+
+            let (role, blocks) = match &message.role {
+                MessageRole::System => {
+                    if !message.content.trim().is_empty() {
+                        system_prompts.push(message.content.as_str());
+                    }
+                    continue;
+                }
+                MessageRole::User => ("user", text),
+                MessageRole::Assistant => {
+                    let tool_uses = get_tool_calls(messages, index).into_iter().map(|call| {
+                        json!({"type": "tool_use", "id": call.id, "name": call.name, "input": call.arguments})
+                    });
+                    ("assistant", text.into_iter().chain(tool_uses).collect())
+                }
+                MessageRole::Tool(call) => (
+                    "user",
+                    vec![
+                        json!({"type": "tool_result", "tool_use_id": call.id, "content": message.content}),
+                    ],
+                ),
+            };
+
+            if blocks.is_empty() {
+                continue;
+            }
+
+            match turns.last_mut() {
+                Some((last_role, content)) if *last_role == role => content.extend(blocks),
+                _ => turns.push((role, blocks)),
+            }
+        }
+
+        // The first turn must be the user's, so leading responses are dropped, along with the
+        // results of any tools they called
+        loop {
+            match turns.first_mut() {
+                Some(("assistant", _)) => {
+                    turns.remove(0);
+                }
+                Some((_, content)) => {
+                    content.retain(|block| block["type"] != "tool_result");
+                    if !content.is_empty() {
+                        break;
+                    }
+                    turns.remove(0);
+                }
+                None => break,
+            }
+        }
+
+        let messages = turns
+            .into_iter()
+            .map(|(role, content)| json!({"role": role, "content": content}))
+            .collect::<Vec<serde_json::Value>>();
+
+        let mut inputs = json!({"model": self.model_config.model_id, "messages": messages, "max_tokens": 2000, "stream": stream});
+        if !system_prompts.is_empty() {
+            inputs["system"] = json!(system_prompts.join("\n\n"));
+        }
+        if self.model_config.tools {
+            inputs["tools"] = list_tools()
+                .into_iter()
+                .map(|tool| json!({"name": tool.name(), "description": tool.description(), "input_schema": tool.parameters()}))
+                .collect();
+        }
+
+        if let Some(extra_args) = self.model_config.extra_args.clone() {
+            merge(&inputs, &extra_args)
+        } else {
+            inputs
+        }
+    }
+
+    fn request(
+        &self,
+        provider: &Anthropic,
+        api_key: &str,
+        body: serde_json::Value,
+    ) -> reqwest::RequestBuilder {
+        let endpoint = format!("{}/messages", provider.base_url);
+        reqwest::Client::new()
+            .post(endpoint)
+            .header("x-api-key", api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+    }
+}
+
+#[async_trait]
+impl CompletionModel for AnthropicCompletionModel {
+    async fn get_completion(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let provider = Anthropic::load();
+        let api_key = provider
+            .api_key
+            .clone()
+            .ok_or(anyhow!("ANTHROPIC_API_KEY is not set"))?;
+
+        let body = self.get_inputs(&messages, false);
+        let result = check_response(self.request(&provider, &api_key, body).send().await?).await?;
+
+        let response: AnthropicResponse = serde_json::from_str(result.text().await?.as_str())?;
+        let content = response
+            .content
+            .into_iter()
+            .filter_map(|block| block.text)
+            .collect::<String>();
+
+        anyhow::Ok(Box::new(AnthropicCompletionResult {
+            status: CompletionStatus::Succeeded,
+            stream: None,
+            content: Some(content),
+        }))
+    }
+
+    async fn start_streaming(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let provider = Anthropic::load();
+        let api_key = provider
+            .api_key
+            .clone()
+            .ok_or(anyhow!("ANTHROPIC_API_KEY is not set"))?;
+
+        let body = self.get_inputs(&messages, true);
+        let response =
+            check_response(self.request(&provider, &api_key, body).send().await?).await?;
+        let mut event_stream = response.bytes_stream().eventsource();
+
+        let stream = stream! {
+            while let Some(event) = event_stream.next().await {
+                match event {
+                    Ok(event) => yield (event.event, event.id, event.data),
+                    Err(err) => {
+                        yield CompletionError::Network(err.to_string()).to_event(String::new());
+                        break;
+                    }
+                }
+            }
+        };
+
+        anyhow::Ok(Box::new(AnthropicCompletionResult {
+            status: CompletionStatus::Processing,
+            stream: Some(Box::pin(stream)),
+            content: None,
+        }))
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync>>;
+
+struct AnthropicCompletionResult {
+    status: CompletionStatus,
+    stream: Option<EventStream>,
+    content: Option<String>,
+}
+
+#[async_trait]
+impl CompletionResult for AnthropicCompletionResult {
+    async fn poll(&mut self) {}
+    async fn get_status(&mut self) -> CompletionStatus {
+        self.status.clone()
+    }
+    async fn get_stream<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
+    {
+        let event_stream = self
+            .stream
+            .as_mut()
+            .ok_or(anyhow!("stream is not available"))?;
+
+        let stream = stream! {
+            let mut id = 0;
+            // Tool calls are streamed as content blocks, with their input as partial json
+            let mut tool_calls = IndexMap::<usize, (String, String, String)>::new();
+            while let Some((event, _, data)) = event_stream.next().await {
+                id += 1;
+                match event.as_str() {
+                    "content_block_start" => {
+                        let obj: anyhow::Result<AnthropicBlockStartEvent> = serde_json::from_str(&data).map_err(|err| anyhow!(err));
+                        if let Ok(obj) = obj {
+                            if obj.content_block.r#type == "tool_use" {
+                                tool_calls.insert(obj.index, (obj.content_block.id.unwrap_or_default(), obj.content_block.name.unwrap_or_default(), String::new()));
+                            }
+                        }
+                    }
+                    "content_block_delta" => {
+                        let obj: anyhow::Result<AnthropicDeltaEvent> = serde_json::from_str(&data).map_err(|err| anyhow!(err));
+                        if let Ok(obj) = obj {
+                            if let Some(partial_json) = obj.delta.partial_json {
+                                if let Some((_, _, input)) = tool_calls.get_mut(&obj.index) {
+                                    input.push_str(&partial_json);
+                                }
+                            }
+                            if let Some(text) = obj.delta.text {
+                                yield (event, id.to_string(), text);
+                            }
+                        }
+                    }
+                    "content_block_stop" => {
+                        let obj: anyhow::Result<AnthropicBlockStopEvent> = serde_json::from_str(&data).map_err(|err| anyhow!(err));
+                        if let Some((call_id, name, input)) = obj.ok().and_then(|obj| tool_calls.shift_remove(&obj.index)) {
+                            let call = ToolCall {
+                                id: call_id,
+                                name,
+                                // Tools without arguments stream no input at all
+                                arguments: serde_json::from_str(&input).unwrap_or(json!({})),
+                            };
+                            yield call.to_event(id.to_string());
+                        }
+                    }
+                    // Both errors sent by Anthropic, and the connection dropping
+                    ERROR_EVENT => {
+                        let error = match serde_json::from_str::<AnthropicErrorEvent>(&data) {
+                            Ok(obj) => match obj.error.r#type.as_str() {
+                                "authentication_error" | "permission_error" => CompletionError::Auth(obj.error.message),
+                                "rate_limit_error" => CompletionError::RateLimit { message: obj.error.message, retry_after_secs: None },
+                                _ => CompletionError::Provider(obj.error.message),
+                            },
+                            Err(_) => serde_json::from_str(&data).unwrap_or(CompletionError::MalformedEvent(data)),
+                        };
+                        yield error.to_event(id.to_string());
+                        return;
+                    }
+                    // Input tokens are reported when the message starts, and output tokens
+                    // in the final message_delta
+                    "message_start" => {
+                        let obj: anyhow::Result<AnthropicMessageStartEvent> = serde_json::from_str(&data).map_err(|err| anyhow!(err));
+                        if let Ok(obj) = obj {
+                            yield CompletionUsage::from_tokens(obj.message.usage.input_tokens, obj.message.usage.output_tokens).to_event(id.to_string());
+                        }
+                    }
+                    "message_delta" => {
+                        let obj: anyhow::Result<AnthropicMessageDeltaEvent> = serde_json::from_str(&data).map_err(|err| anyhow!(err));
+                        if let Ok(obj) = obj {
+                            yield CompletionUsage::from_tokens(obj.usage.input_tokens, obj.usage.output_tokens).to_event(id.to_string());
+                        }
+                    }
+                    "message_stop" => break,
+                    // ping carries no content
+                    _ => {}
+                }
+            }
+
+            yield ("done".to_string(), id.to_string(), "".to_string());
+        };
+
+        anyhow::Ok(Box::pin(stream))
+    }
+    fn get_content(&mut self) -> anyhow::Result<String> {
+        self.content.clone().ok_or(anyhow!("content not available"))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicDelta {
+    text: Option<String>,
+    partial_json: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicDeltaEvent {
+    #[serde(default)]
+    index: usize,
+    delta: AnthropicDelta,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicStartedBlock {
+    r#type: String,
+    id: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicBlockStartEvent {
+    index: usize,
+    content_block: AnthropicStartedBlock,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicBlockStopEvent {
+    index: usize,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AnthropicUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicStartedMessage {
+    #[serde(default)]
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicMessageStartEvent {
+    message: AnthropicStartedMessage,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicMessageDeltaEvent {
+    #[serde(default)]
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicError {
+    #[serde(default)]
+    r#type: String,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicErrorEvent {
+    error: AnthropicError,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicContentBlock {
+    text: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContentBlock>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_inputs() {
+        let model = AnthropicCompletionModel::load(ModelConfig {
+            provider_id: "Anthropic".to_string(),
+            model_id: "claude-3-haiku-20240307".to_string(),
+            extra_args: None,
+            template: Default::default(),
+            fallbacks: Vec::new(),
+            context_length: None,
+            tools: false,
+        });
+
+        let inputs = model.get_inputs(
+            &[
+                Message::new(MessageRole::Assistant, "Hello! How can I help?"),
+                Message::new(MessageRole::User, "What is a monad?"),
+                Message::new(MessageRole::User, "In Rust?"),
+            ],
+            false,
+        );
+        assert!(inputs.get("system").is_none());
+        assert_eq!(
+            inputs["messages"],
+            json!([{"role": "user", "content": [
+                {"type": "text", "text": "What is a monad?"},
+                {"type": "text", "text": "In Rust?"}
+            ]}])
+        );
+
+        let inputs = model.get_inputs(
+            &[
+                Message::new(MessageRole::System, "You are a helpful assistant."),
+                Message::new(MessageRole::System, "Earlier, the user said hello."),
+                Message::new(MessageRole::User, "Hi"),
+            ],
+            false,
+        );
+        assert_eq!(
+            inputs["system"],
+            "You are a helpful assistant.\n\nEarlier, the user said hello."
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use super::{load_model, COMPLETION_PROVIDERS};
+use crate::ai::completion::{
+    CompletionError, CompletionModel, CompletionResult, CompletionStatus, Message,
+};
+use crate::ai::config::{ModelConfig, ARCHER_CONFIG};
+use crate::ai::context::fit_to_context;
+use async_trait::async_trait;
+use futures::Stream;
+use std::pin::Pin;
+
+/// Wraps a model with the models to answer instead, when its provider has no credentials or
+/// fails to start the completion.
+///
+/// Messages are shortened to fit whichever model answers, so this is used for every model, even
+/// one without fallbacks.
+///
+/// Once a response has started streaming it is not moved to another model, so a response which
+/// fails partway through is reported as failed.
+pub struct FallbackModel {
+    /// The requested model, followed by its fallbacks in order.
+    model_configs: Vec<ModelConfig>,
+}
+
+impl FallbackModel {
+    pub fn new(model_config: ModelConfig, fallbacks: Vec<ModelConfig>) -> Self {
+        let mut model_configs = vec![model_config];
+        model_configs.extend(fallbacks);
+        FallbackModel { model_configs }
+    }
+}
+
+/// The model for `model_config`, if its provider is able to answer.
+fn available_model(model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
+    let has_credentials = COMPLETION_PROVIDERS
+        .get_provider(&model_config.provider_id)
+        .is_some_and(|provider| provider.has_credentials());
+    if !has_credentials {
+        return Err(CompletionError::Auth(format!(
+            "no credentials are set for {}",
+            model_config.provider_id
+        ))
+        .into());
+    }
+
+    load_model(model_config)
+}
+
+/// Start streaming with the first model from `start` which is able to, returning its position.
+///
+/// Messages are fitted to each model in turn, as fallbacks can have a different context length.
+///
+/// When none of the models are able to, the error from the first is returned, as the later
+/// models are only fallbacks for it.
+async fn start_streaming_from(
+    model_configs: &[ModelConfig],
+    start: usize,
+    messages: &[Message],
+) -> Result<(usize, Box<dyn CompletionResult>), CompletionError> {
+    let mut first_error = None;
+    for (index, model_config) in model_configs.iter().enumerate().skip(start) {
+        let result = match available_model(model_config) {
+            Ok(model) => {
+                match fit_to_context(messages.to_vec(), model_config, &ARCHER_CONFIG.truncation)
+                    .await
+                {
+                    Ok(messages) => model.start_streaming(messages).await,
+                    Err(err) => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(result) => return Ok((index, result)),
+            Err(err) => {
+                let error = CompletionError::from(err);
+                log::warn!("{} is unavailable: {error}", model_config.model_id);
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+
+    Err(first_error.unwrap_or(CompletionError::Provider(
+        "no models are left to fall back to".to_string(),
+    )))
+}
+
+#[async_trait]
+impl CompletionModel for FallbackModel {
+    async fn get_completion(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let mut first_error = None;
+        for model_config in &self.model_configs {
+            let result = match available_model(model_config) {
+                Ok(model) => {
+                    match fit_to_context(messages.clone(), model_config, &ARCHER_CONFIG.truncation)
+                        .await
+                    {
+                        Ok(messages) => model.get_completion(messages).await,
+                        Err(err) => Err(err),
+                    }
+                }
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(result) => return anyhow::Ok(result),
+                Err(err) => {
+                    log::warn!("{} is unavailable: {err}", model_config.model_id);
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+
+        Err(first_error.unwrap_or(anyhow::anyhow!("no models are left to fall back to")))
+    }
+
+    async fn start_streaming(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let (index, result) = start_streaming_from(&self.model_configs, 0, &messages).await?;
+        anyhow::Ok(Box::new(FallbackCompletionResult {
+            model_configs: self.model_configs.clone(),
+            messages,
+            index,
+            result,
+            streaming: false,
+            exhausted: false,
+        }))
+    }
+}
+
+struct FallbackCompletionResult {
+    model_configs: Vec<ModelConfig>,
+    messages: Vec<Message>,
+    /// The position of the model answering in `model_configs`.
+    index: usize,
+    result: Box<dyn CompletionResult>,
+    /// Set once the response has started streaming, after which it stays with its model.
+    streaming: bool,
+    /// Set when every fallback has failed, leaving the last failed result.
+    exhausted: bool,
+}
+
+#[async_trait]
+impl CompletionResult for FallbackCompletionResult {
+    async fn poll(&mut self) {
+        self.result.poll().await;
+
+        // Completions can also fail before streaming, ie. once their retries run out
+        if self.streaming
+            || self.exhausted
+            || self.result.get_status().await != CompletionStatus::Failed
+        {
+            return;
+        }
+
+        if let Some(error) = self.result.get_error() {
+            log::warn!(
+                "{} is unavailable: {error}",
+                self.model_configs[self.index].model_id
+            );
+        }
+        match start_streaming_from(&self.model_configs, self.index + 1, &self.messages).await {
+            Ok((index, result)) => {
+                self.index = index;
+                self.result = result;
+            }
+            Err(_) => self.exhausted = true,
+        }
+    }
+    async fn get_status(&mut self) -> CompletionStatus {
+        self.result.get_status().await
+    }
+    async fn get_stream<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
+    {
+        self.streaming = true;
+        self.result.get_stream().await
+    }
+    fn get_content(&mut self) -> anyhow::Result<String> {
+        self.result.get_content()
+    }
+    async fn cancel(&mut self) -> anyhow::Result<()> {
+        self.result.cancel().await
+    }
+    fn get_error(&self) -> Option<CompletionError> {
+        self.result.get_error()
+    }
+    fn get_fallback(&self) -> Option<ModelConfig> {
+        (self.index > 0).then(|| self.model_configs[self.index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::completion::MessageRole;
+    use crate::ai::context::count_message_tokens;
+    use crate::ai::providers::Mock;
+    use crate::ai::usage::{CompletionUsage, USAGE_EVENT};
+    use futures_lite::StreamExt;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_fallback_fits_context() {
+        let model = FallbackModel::new(
+            Mock::model_config(json!({"error": "Incorrect API key", "error_status": 401})),
+            vec![ModelConfig {
+                context_length: Some(2100),
+                ..Mock::model_config(json!({"token_delay_ms": 0}))
+            }],
+        );
+        let messages = vec![
+            Message::new(MessageRole::User, "a long question ".repeat(200)),
+            Message::new(MessageRole::Assistant, "A long answer."),
+            Message::new(MessageRole::User, "And a short one?"),
+        ];
+        let mut result = model.start_streaming(messages.clone()).await.unwrap();
+        assert!(result.get_fallback().is_some());
+
+        // The earlier turn is dropped, to fit the fallback's shorter context
+        let events = result.get_stream().await.unwrap().collect::<Vec<_>>().await;
+        let usage = events
+            .iter()
+            .find(|(event, _, _)| event == USAGE_EVENT)
+            .map(|(_, _, data)| serde_json::from_str::<CompletionUsage>(data).unwrap())
+            .unwrap();
+        assert!(usage.prompt_tokens.unwrap() < count_message_tokens(&messages) as u64);
+    }
+}
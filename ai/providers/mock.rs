@@ -0,0 +1,407 @@
+use crate::ai::completion::{
+    CompletionError, CompletionModel, CompletionProvider, CompletionResult, CompletionStatus,
+    Message, MessageRole,
+};
+use crate::ai::config::{ModelConfig, ARCHER_CONFIG};
+use crate::ai::context::count_message_tokens;
+use crate::ai::tools::ToolCall;
+use crate::ai::usage::CompletionUsage;
+use anyhow::anyhow;
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::Stream;
+use serde::Deserialize;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The model which is always available from the mock provider, replying with the latest message.
+pub const ECHO_MODEL: &str = "echo";
+
+/// Provider which replays scripted responses, for tests and demos without api keys or a network.
+///
+/// It is only registered with `ARCHER_MOCK` set, or in tests.
+///
+/// Responses are set with the model's `extra_args`, and streamed token by token:
+///
+/// ```json
+/// { "provider_id": "Mock", "model_id": "scripted", "extra_args": { "responses": ["Hi!", "Bye!"], "token_delay_ms": 50 } }
+/// ```
+#[derive(Default)]
+pub struct Mock {}
+
+impl Mock {
+    /// The config for a scripted model, behaving as its `extra_args` set, ie. in tests.
+    pub fn model_config(extra_args: serde_json::Value) -> ModelConfig {
+        ModelConfig {
+            provider_id: "Mock".to_string(),
+            model_id: "scripted".to_string(),
+            extra_args: Some(
+                serde_json::from_value(extra_args).expect("extra_args should be an object"),
+            ),
+            context_length: None,
+            tools: false,
+            template: Default::default(),
+            fallbacks: Vec::new(),
+        }
+    }
+}
+
+impl CompletionProvider for Mock {
+    fn load() -> Self {
+        Mock {}
+    }
+    fn has_credentials(&self) -> bool {
+        true
+    }
+    fn list_models(&self) -> Vec<ModelConfig> {
+        let mut models = ARCHER_CONFIG
+            .models
+            .iter()
+            .filter(|model_config| model_config.provider_id == self.get_id())
+            .cloned()
+            .collect::<Vec<ModelConfig>>();
+
+        if !models.iter().any(|model| model.model_id == ECHO_MODEL) {
+            models.insert(
+                0,
+                ModelConfig {
+                    provider_id: self.get_id(),
+                    model_id: ECHO_MODEL.to_string(),
+                    extra_args: None,
+                    context_length: None,
+                    tools: false,
+                    template: Default::default(),
+                    fallbacks: Vec::new(),
+                },
+            );
+        }
+
+        models
+    }
+    fn get_model(&self, model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
+        if model_config.provider_id == self.get_id() {
+            return anyhow::Ok(Box::new(MockCompletionModel::load(model_config.clone())?));
+        }
+        Err(anyhow!("model_config provider does not match provider"))
+    }
+
+    fn get_id(&self) -> String {
+        "Mock".to_string()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct MockToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+/// The behaviour of a mock model, read from its `extra_args`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct MockOptions {
+    /// Replies for each turn of the conversation, repeating once they run out.
+    /// Without any responses, the latest message is echoed back.
+    responses: Vec<String>,
+    /// The time between each streamed token.
+    token_delay_ms: u64,
+    /// How long the completion reports `Starting`, before it begins streaming.
+    starting_ms: u64,
+    /// Fail to start the completion with this error, as a provider rejecting the request would.
+    error: Option<String>,
+    /// The http status the error is given with, ie. 401 or 429.
+    error_status: Option<u16>,
+    /// Only fail this many requests with the error, succeeding after, ie. to show retries.
+    fail_times: Option<usize>,
+    /// Fail the completion after streaming this many tokens.
+    fail_after: Option<usize>,
+    /// Tools called before replying, for models with `"tools": true`.
+    tool_calls: Vec<MockToolCall>,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        MockOptions {
+            responses: Vec::new(),
+            token_delay_ms: 20,
+            starting_ms: 0,
+            error: None,
+            error_status: None,
+            fail_times: None,
+            fail_after: None,
+            tool_calls: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MockCompletionModel {
+    model_config: ModelConfig,
+    options: MockOptions,
+    /// The number of requests made to the model, for `fail_times`.
+    requests: Arc<AtomicUsize>,
+}
+
+impl MockCompletionModel {
+    pub fn load(model_config: ModelConfig) -> anyhow::Result<Self> {
+        let options = match model_config.extra_args.clone() {
+            Some(extra_args) => {
+                serde_json::from_value(serde_json::Value::Object(extra_args.into_iter().collect()))
+                    .map_err(|err| anyhow!("invalid mock model options: {err}"))?
+            }
+            None => MockOptions::default(),
+        };
+        anyhow::Ok(MockCompletionModel {
+            model_config,
+            options,
+            requests: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    fn get_error(&self) -> Option<CompletionError> {
+        let message = self.options.error.clone()?;
+        let request = self.requests.fetch_add(1, Ordering::SeqCst);
+        if self
+            .options
+            .fail_times
+            .is_some_and(|fail_times| request >= fail_times)
+        {
+            return None;
+        }
+
+        Some(match self.options.error_status {
+            Some(status) => CompletionError::from_response(status, &message),
+            None => CompletionError::Provider(message),
+        })
+    }
+
+    fn get_reply(&self, messages: &[Message]) -> String {
+        if self.options.responses.is_empty() {
+            return messages
+                .iter()
+                .rev()
+                .find(|message| message.role != MessageRole::System)
+                .map(|message| message.content.clone())
+                .unwrap_or_default();
+        }
+
+        let turn = messages
+            .iter()
+            .filter(|message| message.role == MessageRole::User)
+            .count()
+            .saturating_sub(1);
+        self.options.responses[turn % self.options.responses.len()].clone()
+    }
+
+    /// Tools are only called once per reply, so the conversation continues after their results.
+    fn get_tool_calls(&self, messages: &[Message]) -> Vec<ToolCall> {
+        let answering_tool = matches!(
+            messages.last().map(|message| &message.role),
+            Some(MessageRole::Tool(_))
+        );
+        if !self.model_config.tools || answering_tool {
+            return Vec::new();
+        }
+
+        self.options
+            .tool_calls
+            .iter()
+            .map(|call| ToolCall {
+                id: Uuid::new_v4().to_string(),
+                name: call.name.clone(),
+                arguments: call.arguments.clone(),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl CompletionModel for MockCompletionModel {
+    async fn get_completion(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        if let Some(error) = self.get_error() {
+            return Err(error.into());
+        }
+
+        anyhow::Ok(Box::new(MockCompletionResult {
+            status: CompletionStatus::Succeeded,
+            content: Some(self.get_reply(&messages)),
+            ..MockCompletionResult::new(self.options.clone())
+        }))
+    }
+
+    async fn start_streaming(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        if let Some(error) = self.get_error() {
+            return Err(error.into());
+        }
+
+        let status = if self.options.starting_ms > 0 {
+            CompletionStatus::Starting
+        } else {
+            CompletionStatus::Processing
+        };
+        let tokens = self
+            .get_reply(&messages)
+            .split_inclusive(char::is_whitespace)
+            .map(|token| token.to_string())
+            .collect();
+
+        anyhow::Ok(Box::new(MockCompletionResult {
+            status,
+            tokens,
+            tool_calls: self.get_tool_calls(&messages),
+            prompt_tokens: count_message_tokens(&messages) as u64,
+            ..MockCompletionResult::new(self.options.clone())
+        }))
+    }
+}
+
+struct MockCompletionResult {
+    options: MockOptions,
+    status: CompletionStatus,
+    started: Instant,
+    tokens: Vec<String>,
+    tool_calls: Vec<ToolCall>,
+    prompt_tokens: u64,
+    content: Option<String>,
+}
+
+impl MockCompletionResult {
+    fn new(options: MockOptions) -> Self {
+        MockCompletionResult {
+            options,
+            status: CompletionStatus::Starting,
+            started: Instant::now(),
+            tokens: Vec::new(),
+            tool_calls: Vec::new(),
+            prompt_tokens: 0,
+            content: None,
+        }
+    }
+}
+
+#[async_trait]
+impl CompletionResult for MockCompletionResult {
+    async fn poll(&mut self) {
+        if self.status == CompletionStatus::Starting
+            && self.started.elapsed() >= Duration::from_millis(self.options.starting_ms)
+        {
+            self.status = CompletionStatus::Processing;
+        }
+    }
+    async fn get_status(&mut self) -> CompletionStatus {
+        self.status.clone()
+    }
+    async fn get_stream<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
+    {
+        if self.status != CompletionStatus::Processing {
+            return Err(anyhow!("stream is not available"));
+        }
+
+        let result = self;
+        let stream = stream! {
+            let delay = Duration::from_millis(result.options.token_delay_ms);
+            let mut id = 0;
+
+            for call in result.tool_calls.drain(..) {
+                id += 1;
+                yield call.to_event(id.to_string());
+            }
+
+            let tokens = result.tokens.drain(..).collect::<Vec<String>>();
+            let completion_tokens = tokens.len() as u64;
+            for (index, token) in tokens.into_iter().enumerate() {
+                if Some(index) == result.options.fail_after {
+                    result.status = CompletionStatus::Failed;
+                    yield CompletionError::Network("the connection was closed".to_string()).to_event(id.to_string());
+                    return;
+                }
+
+                tokio::time::sleep(delay).await;
+                id += 1;
+                yield ("message".to_string(), id.to_string(), token);
+            }
+
+            result.status = CompletionStatus::Succeeded;
+            yield CompletionUsage::from_tokens(Some(result.prompt_tokens), Some(completion_tokens)).to_event(id.to_string());
+            yield ("done".to_string(), id.to_string(), "".to_string());
+        };
+
+        anyhow::Ok(Box::pin(stream))
+    }
+    fn get_content(&mut self) -> anyhow::Result<String> {
+        self.content.clone().ok_or(anyhow!("content not available"))
+    }
+    async fn cancel(&mut self) -> anyhow::Result<()> {
+        self.status = CompletionStatus::Canceled;
+        anyhow::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::completion::ERROR_EVENT;
+    use futures_lite::StreamExt;
+    use serde_json::json;
+
+    fn mock_model(extra_args: serde_json::Value) -> Box<dyn CompletionModel> {
+        Mock::load()
+            .get_model(&Mock::model_config(extra_args))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream() {
+        let model = mock_model(
+            json!({"responses": ["Hello there"], "token_delay_ms": 0, "starting_ms": 10}),
+        );
+        let mut result = model
+            .start_streaming(vec![Message::new(MessageRole::User, "Hi")])
+            .await
+            .unwrap();
+
+        assert_eq!(result.get_status().await, CompletionStatus::Starting);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        result.poll().await;
+        assert_eq!(result.get_status().await, CompletionStatus::Processing);
+
+        let events = result.get_stream().await.unwrap().collect::<Vec<_>>().await;
+        let tokens = events
+            .iter()
+            .filter(|(event, _, _)| event == "message")
+            .map(|(_, _, data)| data.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(tokens, vec!["Hello ", "there"]);
+        assert_eq!(events.last().unwrap().0, "done");
+        assert_eq!(result.get_status().await, CompletionStatus::Succeeded);
+
+        let model = mock_model(json!({"token_delay_ms": 0, "fail_after": 1}));
+        let mut result = model
+            .start_streaming(vec![Message::new(MessageRole::User, "echo this back")])
+            .await
+            .unwrap();
+        let events = result.get_stream().await.unwrap().collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].2, "echo ");
+        assert_eq!(events[1].0, ERROR_EVENT);
+        assert_eq!(result.get_status().await, CompletionStatus::Failed);
+
+        let model = mock_model(json!({"error": "invalid api key"}));
+        assert!(model
+            .get_completion(vec![Message::new(MessageRole::User, "Hi")])
+            .await
+            .is_err());
+    }
+}
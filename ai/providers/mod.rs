@@ -1,14 +1,27 @@
+mod anthropic;
+mod fallback;
+mod mock;
+mod ollama;
+mod openai;
 mod replicate;
+mod retry;
 mod together;
 
+use anthropic::Anthropic;
 use anyhow::anyhow;
+pub use fallback::FallbackModel;
+pub use mock::{Mock, ECHO_MODEL};
+pub use ollama::Ollama;
+use openai::OpenAI;
 use replicate::Replicate;
+pub use retry::{RetryConfig, RetryPolicy, RetryingModel};
 
 use crate::ai::providers::together::TogetherAI;
 
 use super::completion::{CompletionModel, CompletionProvider, CompletionProviderID};
-use super::config::ModelConfig;
+use super::config::{ModelConfig, ModelReference, ARCHER_CONFIG};
 use std::collections::BTreeMap;
+use std::env::var;
 
 pub struct CompletionProviderLibrary {
     providers: BTreeMap<CompletionProviderID, Box<dyn CompletionProvider>>,
@@ -19,6 +32,12 @@ impl CompletionProviderLibrary {
         self.providers.get(provider_id)
     }
 
+    pub fn has_any_credentials(&self) -> bool {
+        self.providers
+            .values()
+            .any(|provider| provider.has_credentials())
+    }
+
     pub fn prev_provider(&self, provider_id: &CompletionProviderID) -> CompletionProviderID {
         let mut prev = false;
 
@@ -58,15 +77,45 @@ lazy_static! {
         let mut providers = BTreeMap::<CompletionProviderID, Box<dyn CompletionProvider>>::new();
         providers.insert("TogetherAI".to_string(), Box::new(TogetherAI::load()));
         providers.insert("Replicate".to_string(), Box::new(Replicate::load()));
+        providers.insert("OpenAI".to_string(), Box::new(OpenAI::load()));
+        providers.insert("Ollama".to_string(), Box::new(Ollama::load()));
+        providers.insert("Anthropic".to_string(), Box::new(Anthropic::load()));
+        // Mock models always answer, so they are only offered when asked for
+        if cfg!(test) || var("ARCHER_MOCK").is_ok() {
+            providers.insert("Mock".to_string(), Box::new(Mock::load()));
+        }
 
         CompletionProviderLibrary { providers }
     };
 }
 
+/// The model for `model_config`, which falls back to the models in its `fallbacks` when its
+/// provider is unavailable.
 pub fn get_model(model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
+    get_model_with_fallbacks(model_config, &[])
+}
+
+/// The model for `model_config`, which falls back to its own `fallbacks` and then to `fallbacks`,
+/// ie. those of the active profile.
+pub fn get_model_with_fallbacks(
+    model_config: &ModelConfig,
+    fallbacks: &[ModelReference],
+) -> anyhow::Result<Box<dyn CompletionModel>> {
+    // Even without fallbacks, this is where long conversations are fitted to the model
+    let fallbacks = ARCHER_CONFIG.get_fallbacks(model_config, fallbacks);
+    anyhow::Ok(Box::new(FallbackModel::new(
+        model_config.clone(),
+        fallbacks,
+    )))
+}
+
+/// The model for `model_config` alone, without its fallbacks.
+fn load_model(model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
     if let Some(provider) = COMPLETION_PROVIDERS.get_provider(&model_config.provider_id) {
-        return provider.get_model(model_config);
+        let model = provider.get_model(model_config)?;
+        let policy = ARCHER_CONFIG.retry.get_policy(&model_config.provider_id);
+        return anyhow::Ok(Box::new(RetryingModel::new(model, policy)));
     }
 
-    Err(anyhow!("model not found"))
+    Err(anyhow!("provider {} not found", model_config.provider_id))
 }
@@ -0,0 +1,367 @@
+use crate::ai::completion::{
+    check_response, CompletionError, CompletionModel, CompletionProvider, CompletionResult,
+    CompletionStatus, Message, MessageRole,
+};
+use crate::ai::config::{merge, ModelConfig, ARCHER_CONFIG};
+use crate::ai::tools::{get_tool_calls, list_tools, ToolCall};
+use crate::ai::usage::CompletionUsage;
+use anyhow::anyhow;
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::Stream;
+use futures_lite::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use std::env::var;
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::time::Duration;
+use uuid::Uuid;
+
+const DEFAULT_HOST: &str = "http://localhost:11434";
+
+/// Provider for a locally running Ollama server, configured with `OLLAMA_HOST`.
+#[derive(Default)]
+pub struct Ollama {
+    base_url: String,
+    /// The models pulled into Ollama, as of the last `refresh_models`.
+    local_models: RwLock<Vec<String>>,
+}
+
+impl Ollama {
+    /// Query `/api/tags` for the models currently pulled into Ollama.
+    async fn list_local_models(&self) -> anyhow::Result<Vec<String>> {
+        let endpoint = format!("{}/api/tags", self.base_url);
+        let response = reqwest::Client::builder()
+            .timeout(Duration::from_secs(1))
+            .build()?
+            .get(endpoint)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let tags: OllamaTagsResponse = serde_json::from_str(response.as_str())?;
+        anyhow::Ok(tags.models.into_iter().map(|model| model.name).collect())
+    }
+
+    /// Embed each of `inputs` with a local embedding model, ie. `nomic-embed-text`.
+    pub async fn get_embeddings(
+        &self,
+        model_id: &str,
+        inputs: &[String],
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let endpoint = format!("{}/api/embed", self.base_url);
+        let body = json!({"model": model_id, "input": inputs});
+        let response = reqwest::Client::new()
+            .post(endpoint)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let response: OllamaEmbedResponse = serde_json::from_str(response.text().await?.as_str())?;
+        if response.embeddings.len() != inputs.len() {
+            return Err(anyhow!("ollama returned the wrong number of embeddings"));
+        }
+        anyhow::Ok(response.embeddings)
+    }
+}
+
+#[async_trait]
+impl CompletionProvider for Ollama {
+    fn load() -> Self {
+        let base_url = var("OLLAMA_HOST")
+            .map(|host| host.trim_end_matches('/').to_string())
+            .unwrap_or(DEFAULT_HOST.to_string());
+        Ollama {
+            base_url,
+            local_models: RwLock::new(Vec::new()),
+        }
+    }
+    fn has_credentials(&self) -> bool {
+        // Ollama runs locally, and does not require an api key
+        true
+    }
+    fn list_models(&self) -> Vec<ModelConfig> {
+        let mut models = ARCHER_CONFIG
+            .models
+            .iter()
+            .filter(|model_config| model_config.provider_id == self.get_id())
+            .cloned()
+            .collect::<Vec<ModelConfig>>();
+
+        let local_models = self
+            .local_models
+            .read()
+            .map(|local_models| local_models.clone())
+            .unwrap_or_default();
+        for model_id in local_models {
+            if !models.iter().any(|model| model.model_id == model_id) {
+                models.push(ModelConfig {
+                    provider_id: self.get_id(),
+                    model_id,
+                    extra_args: None,
+                    context_length: None,
+                    tools: false,
+                    template: Default::default(),
+                    fallbacks: Vec::new(),
+                });
+            }
+        }
+
+        models
+    }
+    async fn refresh_models(&self) -> anyhow::Result<()> {
+        let local_models = self.list_local_models().await?;
+        if let Ok(mut cached) = self.local_models.write() {
+            *cached = local_models;
+        }
+        anyhow::Ok(())
+    }
+    fn get_model(&self, model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
+        if model_config.provider_id == self.get_id() {
+            return anyhow::Ok(Box::new(OllamaCompletionModel::load(model_config.clone())));
+        }
+        Err(anyhow!("model_config provider does not match provider"))
+    }
+
+    fn get_id(&self) -> String {
+        "Ollama".to_string()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OllamaCompletionModel {
+    model_config: ModelConfig,
+}
+
+impl OllamaCompletionModel {
+    pub fn load(model_config: ModelConfig) -> Self {
+        OllamaCompletionModel { model_config }
+    }
+
+    pub fn get_inputs(&self, messages: &[Message], stream: bool) -> serde_json::Value {
+        let inputs = messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| match &message.role {
+                MessageRole::System => json!({"role": "system", "content": message.content}),
+                MessageRole::User => json!({"role": "user", "content": message.content}),
+                MessageRole::Assistant => {
+                    let mut input = json!({"role": "assistant", "content": message.content});
+                    let tool_calls = get_tool_calls(messages, index);
+                    if !tool_calls.is_empty() {
+                        input["tool_calls"] = tool_calls
+                            .into_iter()
+                            .map(|call| json!({"function": {"name": call.name, "arguments": call.arguments}}))
+                            .collect();
+                    }
+                    input
+                }
+                MessageRole::Tool(_) => json!({"role": "tool", "content": message.content}),
+            })
+            .collect::<Vec<serde_json::Value>>();
+
+        let mut inputs =
+            json!({"model": self.model_config.model_id, "messages": inputs, "stream": stream});
+        if self.model_config.tools {
+            inputs["tools"] = list_tools()
+                .into_iter()
+                .map(|tool| json!({"type": "function", "function": {"name": tool.name(), "description": tool.description(), "parameters": tool.parameters()}}))
+                .collect();
+        }
+
+        if let Some(extra_args) = self.model_config.extra_args.clone() {
+            merge(&inputs, &extra_args)
+        } else {
+            inputs
+        }
+    }
+}
+
+#[async_trait]
+impl CompletionModel for OllamaCompletionModel {
+    async fn get_completion(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let provider = Ollama::load();
+        let endpoint = format!("{}/api/chat", provider.base_url);
+        let body = self.get_inputs(&messages, false);
+        let result = reqwest::Client::new()
+            .post(endpoint)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+        let result = check_response(result).await?;
+
+        let response: OllamaChatResponse = serde_json::from_str(result.text().await?.as_str())?;
+
+        anyhow::Ok(Box::new(OllamaCompletionResult {
+            status: CompletionStatus::Succeeded,
+            stream: None,
+            content: Some(response.message.content),
+        }))
+    }
+
+    async fn start_streaming(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let provider = Ollama::load();
+        let endpoint = format!("{}/api/chat", provider.base_url);
+        let body = self.get_inputs(&messages, true);
+        let response = reqwest::Client::new()
+            .post(endpoint)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+        let mut byte_stream = check_response(response).await?.bytes_stream();
+
+        // Ollama streams newline delimited json, which may be split across chunks
+        let stream = stream! {
+            let mut buffer = Vec::<u8>::new();
+            while let Some(chunk) = byte_stream.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        yield Err(CompletionError::Network(err.to_string()));
+                        break;
+                    }
+                };
+                buffer.extend_from_slice(&chunk);
+                while let Some(idx) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line = buffer.drain(..=idx).collect::<Vec<u8>>();
+                    let line = String::from_utf8_lossy(&line).trim().to_string();
+                    if !line.is_empty() {
+                        yield Ok(line);
+                    }
+                }
+            }
+        };
+
+        anyhow::Ok(Box::new(OllamaCompletionResult {
+            status: CompletionStatus::Processing,
+            stream: Some(Box::pin(stream)),
+            content: None,
+        }))
+    }
+}
+
+type LineStream = Pin<Box<dyn Stream<Item = Result<String, CompletionError>> + Send + Sync>>;
+
+struct OllamaCompletionResult {
+    status: CompletionStatus,
+    stream: Option<LineStream>,
+    content: Option<String>,
+}
+
+#[async_trait]
+impl CompletionResult for OllamaCompletionResult {
+    async fn poll(&mut self) {}
+    async fn get_status(&mut self) -> CompletionStatus {
+        self.status.clone()
+    }
+    async fn get_stream<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
+    {
+        let line_stream = self
+            .stream
+            .as_mut()
+            .ok_or(anyhow!("stream is not available"))?;
+
+        let stream = stream! {
+            let mut id = 0;
+            while let Some(line) = line_stream.next().await {
+                id += 1;
+
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => {
+                        yield error.to_event(id.to_string());
+                        return;
+                    }
+                };
+
+                // Errors partway through are sent as `{"error": ".."}`
+                let obj: anyhow::Result<OllamaChatResponse> = serde_json::from_str(&line).map_err(|err| anyhow!(err));
+                let Ok(obj) = obj else {
+                    yield CompletionError::from_event(&line).to_event(id.to_string());
+                    return;
+                };
+                // Ollama does not give tool calls an id, so one is made up to pair the call
+                // with its result
+                for tool_call in obj.message.tool_calls {
+                    let call = ToolCall {
+                        id: Uuid::new_v4().to_string(),
+                        name: tool_call.function.name,
+                        arguments: tool_call.function.arguments,
+                    };
+                    yield call.to_event(id.to_string());
+                }
+                yield ("message".to_string(), id.to_string(), obj.message.content);
+                // The final response can still have content, so it is sent before finishing
+                if obj.done {
+                    yield CompletionUsage::from_tokens(obj.prompt_eval_count, obj.eval_count).to_event(id.to_string());
+                    break;
+                }
+            }
+
+            yield ("done".to_string(), id.to_string(), "".to_string());
+        };
+
+        anyhow::Ok(Box::pin(stream))
+    }
+    fn get_content(&mut self) -> anyhow::Result<String> {
+        self.content.clone().ok_or(anyhow!("content not available"))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaChatMessage {
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaChatResponse {
+    message: OllamaChatMessage,
+    #[serde(default)]
+    done: bool,
+    /// Token counts, only reported on the final response
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaTag {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OllamaTagsResponse {
+    models: Vec<OllamaTag>,
+}
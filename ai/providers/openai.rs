@@ -0,0 +1,336 @@
+use crate::ai::completion::{
+    check_response, CompletionError, CompletionModel, CompletionProvider, CompletionResult,
+    CompletionStatus, Message, MessageRole, ERROR_EVENT,
+};
+use crate::ai::config::{merge, ModelConfig};
+use crate::ai::tools::{get_tool_calls, list_tools, ToolCall};
+use crate::ai::usage::CompletionUsage;
+use anyhow::anyhow;
+use async_stream::stream;
+use async_trait::async_trait;
+use eventsource_stream::Eventsource;
+use futures::Stream;
+use futures_lite::StreamExt;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_json::json;
+use std::env::var;
+use std::pin::Pin;
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Provider for any server speaking the OpenAI `/v1/chat/completions` protocol.
+///
+/// The base url can be pointed at a local vLLM or llama.cpp server with `OPENAI_BASE_URL`,
+/// in which case an api key is not required.
+#[derive(Default)]
+pub struct OpenAI {
+    api_key: Option<String>,
+    base_url: String,
+}
+
+impl OpenAI {
+    fn is_local(&self) -> bool {
+        self.base_url != DEFAULT_BASE_URL
+    }
+}
+
+impl CompletionProvider for OpenAI {
+    fn load() -> Self {
+        let base_url = var("OPENAI_BASE_URL")
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or(DEFAULT_BASE_URL.to_string());
+        OpenAI {
+            api_key: var("OPENAI_API_KEY").ok(),
+            base_url,
+        }
+    }
+    fn has_credentials(&self) -> bool {
+        self.api_key.is_some() || self.is_local()
+    }
+    fn get_model(&self, model_config: &ModelConfig) -> anyhow::Result<Box<dyn CompletionModel>> {
+        if model_config.provider_id == self.get_id() {
+            return anyhow::Ok(Box::new(OpenAICompletionModel::load(model_config.clone())));
+        }
+        Err(anyhow!("model_config provider does not match provider"))
+    }
+
+    fn get_id(&self) -> String {
+        "OpenAI".to_string()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OpenAICompletionModel {
+    model_config: ModelConfig,
+}
+
+impl OpenAICompletionModel {
+    pub fn load(model_config: ModelConfig) -> Self {
+        OpenAICompletionModel { model_config }
+    }
+
+    pub fn get_inputs(&self, messages: &[Message], stream: bool) -> serde_json::Value {
+        let inputs = messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| match &message.role {
+                MessageRole::System => json!({"role": "system", "content": message.content}),
+                MessageRole::User => json!({"role": "user", "content": message.content}),
+                MessageRole::Assistant => {
+                    let mut input = json!({"role": "assistant", "content": message.content});
+                    let tool_calls = get_tool_calls(messages, index);
+                    if !tool_calls.is_empty() {
+                        input["tool_calls"] = tool_calls
+                            .into_iter()
+                            .map(|call| json!({"id": call.id, "type": "function", "function": {"name": call.name, "arguments": call.arguments.to_string()}}))
+                            .collect();
+                    }
+                    input
+                }
+                MessageRole::Tool(call) => {
+                    json!({"role": "tool", "tool_call_id": call.id, "content": message.content})
+                }
+            })
+            .collect::<Vec<serde_json::Value>>();
+
+        let mut inputs = json!({"model": self.model_config.model_id, "messages": inputs, "temperature": 0.7, "max_tokens": 2000, "stream": stream});
+        if self.model_config.tools {
+            inputs["tools"] = list_tools()
+                .into_iter()
+                .map(|tool| json!({"type": "function", "function": {"name": tool.name(), "description": tool.description(), "parameters": tool.parameters()}}))
+                .collect();
+        }
+        if stream {
+            // Token counts are only reported for streams when asked for, in a final chunk
+            inputs["stream_options"] = json!({"include_usage": true});
+        }
+
+        if let Some(extra_args) = self.model_config.extra_args.clone() {
+            merge(&inputs, &extra_args)
+        } else {
+            inputs
+        }
+    }
+
+    fn request(&self, provider: &OpenAI, body: serde_json::Value) -> reqwest::RequestBuilder {
+        let endpoint = format!("{}/chat/completions", provider.base_url);
+        let request = reqwest::Client::new()
+            .post(endpoint)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+
+        if let Some(api_key) = &provider.api_key {
+            request.header("Authorization", format!("Bearer {api_key}"))
+        } else {
+            request
+        }
+    }
+}
+
+#[async_trait]
+impl CompletionModel for OpenAICompletionModel {
+    async fn get_completion(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let provider = OpenAI::load();
+        if !provider.has_credentials() {
+            return Err(anyhow!("OPENAI_API_KEY is not set"));
+        }
+
+        let body = self.get_inputs(&messages, false);
+        let result = check_response(self.request(&provider, body).send().await?).await?;
+
+        let response: OpenAICompletionResponse =
+            serde_json::from_str(result.text().await?.as_str())?;
+        let content = response
+            .choices
+            .first()
+            .ok_or(anyhow!("content not provided"))?
+            .message
+            .content
+            .clone()
+            .unwrap_or_default();
+
+        anyhow::Ok(Box::new(OpenAICompletionResult {
+            status: CompletionStatus::Succeeded,
+            stream: None,
+            content: Some(content),
+        }))
+    }
+
+    async fn start_streaming(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let provider = OpenAI::load();
+        if !provider.has_credentials() {
+            return Err(anyhow!("OPENAI_API_KEY is not set"));
+        }
+
+        let body = self.get_inputs(&messages, true);
+        let response = check_response(self.request(&provider, body).send().await?).await?;
+        let mut event_stream = response.bytes_stream().eventsource();
+
+        let stream = stream! {
+            while let Some(event) = event_stream.next().await {
+                match event {
+                    Ok(event) => yield (event.event, event.id, event.data),
+                    Err(err) => {
+                        yield CompletionError::Network(err.to_string()).to_event(String::new());
+                        break;
+                    }
+                }
+            }
+        };
+
+        anyhow::Ok(Box::new(OpenAICompletionResult {
+            status: CompletionStatus::Processing,
+            stream: Some(Box::pin(stream)),
+            content: None,
+        }))
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync>>;
+
+struct OpenAICompletionResult {
+    status: CompletionStatus,
+    stream: Option<EventStream>,
+    content: Option<String>,
+}
+
+#[async_trait]
+impl CompletionResult for OpenAICompletionResult {
+    async fn poll(&mut self) {}
+    async fn get_status(&mut self) -> CompletionStatus {
+        self.status.clone()
+    }
+    async fn get_stream<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
+    {
+        let event_stream = self
+            .stream
+            .as_mut()
+            .ok_or(anyhow!("stream is not available"))?;
+
+        let stream = stream! {
+            let mut id = 0;
+            // Tool calls are streamed in pieces, keyed on their index
+            let mut tool_calls = IndexMap::<usize, (String, String, String)>::new();
+            while let Some((event, _, data)) = event_stream.next().await {
+                id += 1;
+
+                if event == ERROR_EVENT {
+                    yield (event, id.to_string(), data);
+                    return;
+                }
+
+                // The stream is terminated with a literal `[DONE]` payload
+                if data.trim() == "[DONE]" {
+                    break;
+                }
+
+                let obj = match serde_json::from_str::<OpenAIStreamingEvent>(&data) {
+                    Ok(obj) if obj.error.is_none() => obj,
+                    _ => {
+                        yield CompletionError::from_event(&data).to_event(id.to_string());
+                        return;
+                    }
+                };
+                let delta = obj.choices.into_iter().next().map(|choice| choice.delta);
+                for tool_call in delta.as_ref().map(|delta| delta.tool_calls.as_slice()).unwrap_or_default() {
+                    let (call_id, name, arguments) = tool_calls.entry(tool_call.index).or_default();
+                    if let Some(id) = &tool_call.id {
+                        call_id.push_str(id);
+                    }
+                    if let Some(function) = &tool_call.function {
+                        name.push_str(function.name.as_deref().unwrap_or_default());
+                        arguments.push_str(function.arguments.as_deref().unwrap_or_default());
+                    }
+                }
+                if let Some(content) = delta.and_then(|delta| delta.content) {
+                    yield (event, id.to_string(), content);
+                }
+                if let Some(usage) = obj.usage {
+                    yield CompletionUsage::from_tokens(usage.prompt_tokens, usage.completion_tokens).to_event(id.to_string());
+                }
+            }
+
+            for (_, (call_id, name, arguments)) in tool_calls {
+                let call = ToolCall {
+                    id: call_id,
+                    name,
+                    arguments: serde_json::from_str(&arguments).unwrap_or(serde_json::Value::String(arguments)),
+                };
+                yield call.to_event(id.to_string());
+            }
+
+            yield ("done".to_string(), id.to_string(), "".to_string());
+        };
+
+        anyhow::Ok(Box::pin(stream))
+    }
+    fn get_content(&mut self) -> anyhow::Result<String> {
+        self.content.clone().ok_or(anyhow!("content not available"))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIFunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIToolCallDelta {
+    index: usize,
+    id: Option<String>,
+    function: Option<OpenAIFunctionDelta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAIToolCallDelta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIStreamingChoice {
+    delta: OpenAIDelta,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIUsage {
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIStreamingEvent {
+    #[serde(default)]
+    choices: Vec<OpenAIStreamingChoice>,
+    usage: Option<OpenAIUsage>,
+    /// Set instead of the choices when the completion fails partway through
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIResponseMessage {
+    /// Null for replies which only call tools, or refuse.
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAICompletionChoice {
+    message: OpenAIResponseMessage,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAICompletionResponse {
+    choices: Vec<OpenAICompletionChoice>,
+}
@@ -1,8 +1,9 @@
 use crate::ai::completion::{
-    CompletionModel, CompletionModelID, CompletionProvider, CompletionProviderID, CompletionResult,
-    CompletionStatus, Message, MessageRole,
+    CompletionError, CompletionModel, CompletionModelID, CompletionProvider, CompletionProviderID,
+    CompletionResult, CompletionStatus, Message, MessageRole, ERROR_EVENT,
 };
 use crate::ai::config::{merge, ModelConfig};
+use crate::ai::usage::CompletionUsage;
 use anyhow::anyhow;
 use async_stream::stream;
 use async_trait::async_trait;
@@ -80,6 +81,7 @@ impl ReplicateCompletionModel {
 struct ReplicateCompletionResult {
     prediction: Prediction,
     model_config: ModelConfig,
+    error: Option<CompletionError>,
 }
 
 impl ReplicateCompletionResult {
@@ -87,6 +89,7 @@ impl ReplicateCompletionResult {
         let result = ReplicateCompletionResult {
             prediction,
             model_config,
+            error: None,
         };
 
         return result;
@@ -96,8 +99,23 @@ impl ReplicateCompletionResult {
 #[async_trait]
 impl CompletionResult for ReplicateCompletionResult {
     async fn poll(&mut self) {
-        // TODO: There is a risk here for silent errors
-        let _ = self.prediction.reload().await;
+        if let Err(err) = self.prediction.reload().await {
+            self.error = Some(err.into());
+            return;
+        }
+
+        // The reason a prediction failed is only available from the prediction itself
+        if self.prediction.status == PredictionStatus::Failed && self.error.is_none() {
+            let message = fetch_prediction(&self.prediction.id)
+                .await
+                .ok()
+                .and_then(|prediction| prediction["error"].as_str().map(|x| x.to_string()))
+                .unwrap_or("the prediction failed".to_string());
+            self.error = Some(CompletionError::Provider(message));
+        }
+    }
+    fn get_error(&self) -> Option<CompletionError> {
+        self.error.clone()
     }
     async fn get_status(&mut self) -> CompletionStatus {
         let status = self.prediction.get_status().await;
@@ -116,7 +134,7 @@ impl CompletionResult for ReplicateCompletionResult {
                 .as_array()
                 .ok_or(anyhow!("output is unexpected"))?
                 .iter()
-                .map(|x| x.as_str().unwrap())
+                .filter_map(|x| x.as_str())
                 .collect::<String>();
             anyhow::Ok(content)
         } else {
@@ -124,10 +142,18 @@ impl CompletionResult for ReplicateCompletionResult {
         }
     }
 
+    async fn cancel(&mut self) -> anyhow::Result<()> {
+        let config = ReplicateConfig::new()?;
+        let client = PredictionClient::from(config);
+        self.prediction = client.cancel(self.prediction.id.clone()).await?;
+        anyhow::Ok(())
+    }
+
     async fn get_stream<'a>(
         &'a mut self,
     ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
     {
+        let prediction_id = self.prediction.id.clone();
         let event_stream = self.prediction.get_stream().await;
 
         match event_stream {
@@ -136,26 +162,62 @@ impl CompletionResult for ReplicateCompletionResult {
                     while let Some(event) = event_stream.next().await {
                         match event {
                             Ok(event) => {
+                                // Token counts are not streamed, and are only available from
+                                // the prediction's metrics once it has finished
+                                if event.event == "done" {
+                                    if let Ok(usage) = fetch_usage(&prediction_id).await {
+                                        yield usage.to_event(event.id.clone());
+                                    }
+                                }
+                                if event.event == ERROR_EVENT {
+                                    yield CompletionError::Provider(event.data).to_event(event.id);
+                                    break;
+                                }
                                 yield (event.event, event.id, event.data);
 
                             }
-                            _ => todo!(),
+                            Err(err) => {
+                                yield CompletionError::Network(err.to_string()).to_event(String::new());
+                                break;
+                            }
                         }
                     }
                 };
 
-                // pin_mut!(stream);
-
                 let boxed_stream: Pin<
                     Box<dyn Stream<Item = (String, String, String)> + Send + Sync>,
                 > = Box::pin(stream);
                 anyhow::Ok(boxed_stream)
             }
-            Err(err) => panic!("{err}"),
+            Err(err) => Err(err),
         }
     }
 }
 
+/// Fetch the full details of a prediction, including those `Prediction` does not keep.
+async fn fetch_prediction(prediction_id: &str) -> anyhow::Result<serde_json::Value> {
+    let api_key = var("REPLICATE_API_KEY")?;
+    let response = reqwest::Client::new()
+        .get(format!(
+            "https://api.replicate.com/v1/predictions/{prediction_id}"
+        ))
+        .header("Authorization", format!("Bearer {api_key}"))
+        .send()
+        .await?;
+    anyhow::Ok(serde_json::from_str(response.text().await?.as_str())?)
+}
+
+/// Read token counts from a finished prediction's metrics.
+async fn fetch_usage(prediction_id: &str) -> anyhow::Result<CompletionUsage> {
+    let response = fetch_prediction(prediction_id).await?;
+
+    let metrics = &response["metrics"];
+    anyhow::Ok(CompletionUsage::from_tokens(
+        metrics["input_token_count"].as_u64(),
+        metrics["output_token_count"].as_u64(),
+    ))
+}
+
 #[async_trait]
 impl CompletionModel for ReplicateCompletionModel {
     async fn get_completion(
@@ -198,15 +260,21 @@ impl CompletionModel for ReplicateCompletionModel {
         }
 
         while !is_completed(&prediction.status) {
-            // TODO: This error has to be accomodated for
-            let _ = prediction.reload().await;
+            prediction.reload().await?;
             tokio::time::sleep(tokio::time::Duration::from_millis(250)).await;
         }
 
-        anyhow::Ok(Box::new(ReplicateCompletionResult {
-            prediction,
-            model_config: self.model_config.clone(),
-        }))
+        let mut result = ReplicateCompletionResult::new(prediction, self.model_config.clone());
+        if result.prediction.status == PredictionStatus::Failed {
+            result.poll().await;
+            return Err(result
+                .get_error()
+                .unwrap_or(CompletionError::Provider(
+                    "the prediction failed".to_string(),
+                ))
+                .into());
+        }
+        anyhow::Ok(Box::new(result))
     }
 
     async fn start_streaming(
@@ -0,0 +1,339 @@
+use crate::ai::completion::{
+    CompletionError, CompletionModel, CompletionResult, CompletionStatus, Message,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::Stream;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often, and how patiently, requests which fail with a transient error are sent again.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// The wait before the first retry, doubling for each retry after it.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before sending the request again, for the given retry (from 1).
+    ///
+    /// A `Retry-After` from the provider is used instead of the backoff, when it was sent, though
+    /// never waiting longer than `max_backoff_ms`, as completions for titles and `archer ask`
+    /// can't be canceled while waiting.
+    pub fn get_delay(&self, attempt: u32, error: &CompletionError) -> Duration {
+        if let Some(retry_after) = error.get_retry_after() {
+            return retry_after.min(Duration::from_millis(self.max_backoff_ms));
+        }
+
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)));
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// A retry policy for a provider, where anything left unset is taken from the default policy.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderRetryPolicy {
+    pub max_retries: Option<u32>,
+    pub initial_backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Overrides for individual providers, by `provider_id`.
+    pub providers: HashMap<String, ProviderRetryPolicy>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        RetryConfig {
+            max_retries: policy.max_retries,
+            initial_backoff_ms: policy.initial_backoff_ms,
+            max_backoff_ms: policy.max_backoff_ms,
+            providers: HashMap::new(),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn get_policy(&self, provider_id: &str) -> RetryPolicy {
+        let overrides = self.providers.get(provider_id).cloned().unwrap_or_default();
+        RetryPolicy {
+            max_retries: overrides.max_retries.unwrap_or(self.max_retries),
+            initial_backoff_ms: overrides
+                .initial_backoff_ms
+                .unwrap_or(self.initial_backoff_ms),
+            max_backoff_ms: overrides.max_backoff_ms.unwrap_or(self.max_backoff_ms),
+        }
+    }
+}
+
+/// Wraps a model, sending requests again when they fail with a connection error, a rate limit or
+/// a server error.
+///
+/// Only starting a completion is retried, a response which fails partway through is not, as
+/// some of it has already been shown.
+pub struct RetryingModel {
+    model: Arc<dyn CompletionModel>,
+    policy: RetryPolicy,
+}
+
+impl RetryingModel {
+    pub fn new(model: Box<dyn CompletionModel>, policy: RetryPolicy) -> Self {
+        RetryingModel {
+            model: Arc::from(model),
+            policy,
+        }
+    }
+}
+
+#[async_trait]
+impl CompletionModel for RetryingModel {
+    async fn get_completion(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let mut attempt = 0;
+        loop {
+            let error: CompletionError = match self.model.get_completion(messages.clone()).await {
+                Ok(result) => return anyhow::Ok(result),
+                Err(err) => err.into(),
+            };
+
+            attempt += 1;
+            if !error.is_retryable() || attempt > self.policy.max_retries {
+                return Err(error.into());
+            }
+            log::warn!(
+                "Retrying completion ({attempt}/{}) after: {error}",
+                self.policy.max_retries
+            );
+            tokio::time::sleep(self.policy.get_delay(attempt, &error)).await;
+        }
+    }
+
+    async fn start_streaming(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Box<dyn CompletionResult>> {
+        let error: CompletionError = match self.model.start_streaming(messages.clone()).await {
+            Ok(result) => return anyhow::Ok(result),
+            Err(err) => err.into(),
+        };
+
+        if !error.is_retryable() || self.policy.max_retries == 0 {
+            return Err(error.into());
+        }
+
+        // Later attempts are made as the result is polled, so the wait can be shown, and canceled
+        log::warn!(
+            "Retrying completion (1/{}) after: {error}",
+            self.policy.max_retries
+        );
+        anyhow::Ok(Box::new(RetryingCompletionResult {
+            model: self.model.clone(),
+            policy: self.policy.clone(),
+            messages,
+            attempt: 1,
+            next_attempt: Instant::now() + self.policy.get_delay(1, &error),
+            error,
+            failed: false,
+            canceled: false,
+            result: None,
+        }))
+    }
+}
+
+struct RetryingCompletionResult {
+    model: Arc<dyn CompletionModel>,
+    policy: RetryPolicy,
+    messages: Vec<Message>,
+    /// The retry which is waiting to be sent, from 1.
+    attempt: u32,
+    next_attempt: Instant,
+    /// Why the last attempt failed.
+    error: CompletionError,
+    failed: bool,
+    canceled: bool,
+    /// The completion, once an attempt has succeeded.
+    result: Option<Box<dyn CompletionResult>>,
+}
+
+#[async_trait]
+impl CompletionResult for RetryingCompletionResult {
+    async fn poll(&mut self) {
+        if let Some(result) = self.result.as_mut() {
+            return result.poll().await;
+        }
+        if self.failed || self.canceled || Instant::now() < self.next_attempt {
+            return;
+        }
+
+        match self.model.start_streaming(self.messages.clone()).await {
+            Ok(result) => self.result = Some(result),
+            Err(err) => {
+                self.error = err.into();
+                if !self.error.is_retryable() || self.attempt >= self.policy.max_retries {
+                    self.failed = true;
+                    return;
+                }
+
+                self.attempt += 1;
+                self.next_attempt =
+                    Instant::now() + self.policy.get_delay(self.attempt, &self.error);
+                log::warn!(
+                    "Retrying completion ({}/{}) after: {}",
+                    self.attempt,
+                    self.policy.max_retries,
+                    self.error
+                );
+            }
+        }
+    }
+    async fn get_status(&mut self) -> CompletionStatus {
+        match self.result.as_mut() {
+            Some(result) => result.get_status().await,
+            None if self.canceled => CompletionStatus::Canceled,
+            None if self.failed => CompletionStatus::Failed,
+            None => CompletionStatus::Retrying {
+                attempt: self.attempt,
+                max_retries: self.policy.max_retries,
+            },
+        }
+    }
+    async fn get_stream<'a>(
+        &'a mut self,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
+    {
+        match self.result.as_mut() {
+            Some(result) => result.get_stream().await,
+            None => Err(anyhow!("stream is not available")),
+        }
+    }
+    fn get_content(&mut self) -> anyhow::Result<String> {
+        match self.result.as_mut() {
+            Some(result) => result.get_content(),
+            None => Err(anyhow!("content not available")),
+        }
+    }
+    async fn cancel(&mut self) -> anyhow::Result<()> {
+        match self.result.as_mut() {
+            Some(result) => result.cancel().await,
+            None => {
+                self.canceled = true;
+                anyhow::Ok(())
+            }
+        }
+    }
+    fn get_error(&self) -> Option<CompletionError> {
+        match self.result.as_ref() {
+            Some(result) => result.get_error(),
+            None if self.failed => Some(self.error.clone()),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::completion::{CompletionProvider, MessageRole};
+    use crate::ai::providers::Mock;
+    use serde_json::json;
+
+    fn failing_model(error_status: u16, fail_times: usize, policy: RetryPolicy) -> RetryingModel {
+        let model = Mock::load()
+            .get_model(&Mock::model_config(
+                json!({"error": "Overloaded", "error_status": error_status, "fail_times": fail_times}),
+            ))
+            .unwrap();
+        RetryingModel::new(model, policy)
+    }
+
+    #[tokio::test]
+    async fn test_retrying_model() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff_ms: 0,
+            max_backoff_ms: 0,
+        };
+        let messages = vec![Message::new(MessageRole::User, "Hi")];
+
+        // Retried until the request succeeds
+        let model = failing_model(503, 2, policy.clone());
+        let mut result = model.start_streaming(messages.clone()).await.unwrap();
+        assert_eq!(
+            result.get_status().await,
+            CompletionStatus::Retrying {
+                attempt: 1,
+                max_retries: 2
+            }
+        );
+        result.poll().await;
+        assert_eq!(
+            result.get_status().await,
+            CompletionStatus::Retrying {
+                attempt: 2,
+                max_retries: 2
+            }
+        );
+        result.poll().await;
+        assert_eq!(result.get_status().await, CompletionStatus::Processing);
+        assert!(model.get_completion(messages.clone()).await.is_ok());
+
+        // Failed once the retries run out, with the last error
+        let model = failing_model(429, 3, policy.clone());
+        let mut result = model.start_streaming(messages.clone()).await.unwrap();
+        result.poll().await;
+        result.poll().await;
+        assert_eq!(result.get_status().await, CompletionStatus::Failed);
+        assert!(matches!(
+            result.get_error(),
+            Some(CompletionError::RateLimit { .. })
+        ));
+
+        // Errors which would fail again are not retried
+        let model = failing_model(401, 1, policy);
+        assert!(model.start_streaming(messages).await.is_err());
+
+        assert_eq!(
+            RetryConfig::default()
+                .get_policy("OpenAI")
+                .get_delay(3, &CompletionError::Network("connection reset".to_string())),
+            Duration::from_secs(4)
+        );
+        let rate_limited = CompletionError::RateLimit {
+            message: "Slow down".to_string(),
+            retry_after_secs: Some(3600),
+        };
+        assert_eq!(
+            RetryConfig::default()
+                .get_policy("OpenAI")
+                .get_delay(1, &rate_limited),
+            Duration::from_secs(30)
+        );
+    }
+}
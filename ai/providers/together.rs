@@ -1,7 +1,9 @@
 use crate::ai::completion::{
-    CompletionModel, CompletionProvider, CompletionResult, CompletionStatus, Message,
+    check_response, CompletionError, CompletionModel, CompletionProvider, CompletionResult,
+    CompletionStatus, Message, ERROR_EVENT,
 };
 use crate::ai::config::{merge, ModelConfig, ARCHER_CONFIG};
+use crate::ai::usage::CompletionUsage;
 use anyhow::anyhow;
 use async_stream::stream;
 use async_trait::async_trait;
@@ -10,7 +12,6 @@ use futures::Stream;
 use futures_lite::StreamExt;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::collections::HashMap;
 use std::env::var;
 use std::pin::Pin;
 
@@ -95,6 +96,7 @@ impl CompletionModel for TogetherCompletionModel {
                 .body(body.to_string())
                 .send()
                 .await?;
+            let result = check_response(result).await?;
 
             let response: anyhow::Result<TogetherCompletionResponse> =
                 serde_json::from_str(result.text().await?.as_str()).map_err(|err| anyhow!(err));
@@ -113,7 +115,7 @@ impl CompletionModel for TogetherCompletionModel {
                 content: Some(content),
             }))
         } else {
-            Err(anyhow!("failed to run"))
+            Err(CompletionError::Auth("TOGETHER_API_KEY is not set".to_string()).into())
         }
     }
 
@@ -126,24 +128,23 @@ impl CompletionModel for TogetherCompletionModel {
         if let Some(api_key) = provider.api_key {
             let body = self.get_inputs(&messages, true);
             let client = reqwest::Client::new();
-            let mut event_stream = client
+            let response = client
                 .post(endpoint)
                 .header("Authorization", format!("Bearer {api_key}"))
                 .header("Content-Type", "application/json")
                 .body(body.to_string())
                 .send()
-                .await?
-                .bytes_stream()
-                .eventsource();
+                .await?;
+            let mut event_stream = check_response(response).await?.bytes_stream().eventsource();
 
             let stream = stream! {
                 while let Some(event) = event_stream.next().await {
                     match event {
-                        Ok(event) => {
-                            yield (event.event, event.id, event.data);
-
+                        Ok(event) => yield (event.event, event.id, event.data),
+                        Err(err) => {
+                            yield CompletionError::Network(err.to_string()).to_event(String::new());
+                            break;
                         }
-                        _ => {},
                     }
                 }
             };
@@ -157,7 +158,7 @@ impl CompletionModel for TogetherCompletionModel {
                 content: None,
             }))
         } else {
-            Err(anyhow!("togetherai api request failed"))
+            Err(CompletionError::Auth("TOGETHER_API_KEY is not set".to_string()).into())
         }
     }
 }
@@ -178,27 +179,43 @@ impl CompletionResult for TogetherCompletionResult {
         &'a mut self,
     ) -> anyhow::Result<Pin<Box<dyn Stream<Item = (String, String, String)> + Send + Sync + 'a>>>
     {
-        if self.stream.is_none() {
-            return Err(anyhow!("stream it not available"));
-        }
+        let event_stream = self
+            .stream
+            .as_mut()
+            .ok_or(anyhow!("stream is not available"))?;
 
         let stream = stream! {
-
             let mut id = 0;
-            while let Some(event) = self.stream.as_mut().unwrap().next().await {
+            while let Some(event) = event_stream.next().await {
 
                 let (event_str, _, data) = &event;
                 id += 1;
 
+                if event_str == ERROR_EVENT {
+                    yield event;
+                    return;
+                }
+
+                // The stream is terminated with a literal `[DONE]` payload
+                if data.trim() == "[DONE]" {
+                    yield ("done".to_string(), id.to_string(), "".to_string());
+                    return;
+                }
+
                 let obj: anyhow::Result<TogetherStreamingEvent> = serde_json::from_str(data).map_err(|err| anyhow!(err.to_string()));
-                match obj {
-                    Ok(obj) => {
-                        let data = obj.choices.get(0).map(|x| x.get("text")).unwrap().unwrap();
-                        yield (event_str.clone(), id.to_string().clone(), data.clone())
-                    }
+                let obj = match obj {
+                    Ok(obj) if obj.error.is_none() => obj,
                     _ => {
-                        yield ("done".to_string(), id.to_string(), "".to_string())
+                        yield CompletionError::from_event(data).to_event(id.to_string());
+                        return;
                     }
+                };
+                if let Some(choice) = obj.choices.first() {
+                    yield (event_str.clone(), id.to_string(), choice.text.clone());
+                }
+                // Token counts are reported on the last event before `[DONE]`
+                if let Some(usage) = obj.usage {
+                    yield CompletionUsage::from_tokens(usage.prompt_tokens, usage.completion_tokens).to_event(id.to_string());
                 }
             }
         };
@@ -208,11 +225,33 @@ impl CompletionResult for TogetherCompletionResult {
     fn get_content(&mut self) -> anyhow::Result<String> {
         self.content.clone().ok_or(anyhow!("content not available"))
     }
+    async fn cancel(&mut self) -> anyhow::Result<()> {
+        // Dropping the event stream closes the underlying request
+        self.stream = None;
+        self.status = CompletionStatus::Canceled;
+        anyhow::Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TogetherStreamingChoice {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TogetherUsage {
+    prompt_tokens: Option<u64>,
+    completion_tokens: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
 struct TogetherStreamingEvent {
-    choices: Vec<HashMap<String, String>>,
+    #[serde(default)]
+    choices: Vec<TogetherStreamingChoice>,
+    usage: Option<TogetherUsage>,
+    /// Set instead of the choices when the completion fails partway through
+    error: Option<Value>,
 }
 
 #[derive(Deserialize, Debug)]
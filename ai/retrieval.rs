@@ -0,0 +1,497 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use super::completion::{CompletionProvider, Message, MessageRole};
+use super::config::get_data_dir;
+use super::conversation::search::tokenize;
+use super::providers::Ollama;
+
+/// Directories which are skipped while indexing, alongside any hidden directory.
+const SKIPPED_DIRECTORIES: [&str; 4] = ["target", "node_modules", "dist", "build"];
+/// Larger files are assumed to be generated, and are not indexed.
+const MAX_FILE_BYTES: u64 = 512_000;
+/// Chunks overlap by this many lines, so matches spanning a boundary are not lost.
+const CHUNK_OVERLAP_LINES: usize = 10;
+/// Chunks are embedded in batches, to keep requests to the embedding model small.
+const EMBEDDING_BATCH_SIZE: usize = 32;
+
+// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrievalConfig {
+    /// The directory to index, defaults to the directory archer is started in.
+    pub directory: Option<PathBuf>,
+    /// How many chunks are added to the prompt.
+    pub top_k: usize,
+    pub chunk_lines: usize,
+    /// An Ollama embedding model, ie. `nomic-embed-text`, to rank chunks with alongside BM25.
+    pub embedding_model: Option<String>,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        RetrievalConfig {
+            directory: None,
+            top_k: 5,
+            chunk_lines: 40,
+            embedding_model: None,
+        }
+    }
+}
+
+impl RetrievalConfig {
+    pub fn get_directory(&self) -> anyhow::Result<PathBuf> {
+        match &self.directory {
+            Some(directory) => anyhow::Ok(directory.clone()),
+            None => anyhow::Ok(std::env::current_dir()?),
+        }
+    }
+}
+
+/// Whether retrieved chunks are being added to prompts, shown alongside the input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum RetrievalStatus {
+    #[default]
+    Disabled,
+    Indexing,
+    /// Enabled, with the number of chunks indexed.
+    Enabled(usize),
+    Failed(String),
+}
+
+/// A range of lines from an indexed file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Chunk {
+    /// The path of the file, relative to the indexed directory.
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    #[serde(default)]
+    pub embedding: Option<Vec<f32>>,
+}
+
+impl Chunk {
+    /// The file and lines the chunk was taken from, ie. `src/main.rs:1-40`.
+    pub fn citation(&self) -> String {
+        format!("{}:{}-{}", self.path, self.start_line, self.end_line)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedFile {
+    modified: u64,
+    chunks: Vec<Chunk>,
+}
+
+/// Term statistics for BM25, built when the index is loaded rather than persisted.
+#[derive(Debug, Default)]
+struct TermStats {
+    document_frequency: HashMap<String, usize>,
+    term_frequencies: Vec<HashMap<String, usize>>,
+    lengths: Vec<usize>,
+    average_length: f64,
+}
+
+/// An index of the text files in a directory, persisted under the data directory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetrievalIndex {
+    root: PathBuf,
+    #[serde(default)]
+    embedding_model: Option<String>,
+    #[serde(default)]
+    chunk_lines: usize,
+    files: BTreeMap<String, IndexedFile>,
+    #[serde(skip)]
+    stats: TermStats,
+}
+
+impl RetrievalIndex {
+    /// Load the index for a directory, updating it for any files changed since it was saved.
+    pub async fn open(root: &Path, config: &RetrievalConfig) -> anyhow::Result<Self> {
+        let root = root.canonicalize()?;
+        let index_path = get_index_path(&root);
+
+        let mut index = std::fs::read_to_string(&index_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<RetrievalIndex>(&contents).ok())
+            // Embeddings from a different model cannot be compared, and chunks of a different
+            // size would be ranked unevenly, so everything is re-indexed
+            .filter(|index| {
+                index.embedding_model == config.embedding_model
+                    && index.chunk_lines == config.chunk_lines
+            })
+            .unwrap_or(RetrievalIndex {
+                root: root.clone(),
+                embedding_model: config.embedding_model.clone(),
+                chunk_lines: config.chunk_lines,
+                files: BTreeMap::new(),
+                stats: TermStats::default(),
+            });
+
+        if index.refresh(config).await? {
+            if let Some(parent) = index_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&index_path, serde_json::to_string(&index)?)?;
+        }
+
+        index.build_stats();
+        anyhow::Ok(index)
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.stats.lengths.len()
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.files.values().flat_map(|file| file.chunks.iter())
+    }
+
+    /// Re-chunk new and modified files, and drop deleted ones, returning whether anything changed.
+    async fn refresh(&mut self, config: &RetrievalConfig) -> anyhow::Result<bool> {
+        let mut changed = false;
+        let mut seen = HashSet::new();
+        let mut pending = Vec::<(String, IndexedFile)>::new();
+
+        let entries = WalkDir::new(&self.root)
+            .into_iter()
+            .filter_entry(|entry| {
+                let name = entry.file_name().to_string_lossy();
+                entry.depth() == 0
+                    || !(name.starts_with('.')
+                        || (entry.file_type().is_dir()
+                            && SKIPPED_DIRECTORIES.contains(&name.as_ref())))
+            })
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file());
+
+        for entry in entries {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.len() > MAX_FILE_BYTES {
+                continue;
+            }
+
+            let relative_path = entry
+                .path()
+                .strip_prefix(&self.root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            seen.insert(relative_path.clone());
+
+            if self
+                .files
+                .get(&relative_path)
+                .is_some_and(|file| file.modified == modified)
+            {
+                continue;
+            }
+
+            // Binary files are skipped, by only indexing valid utf-8
+            let Ok(contents) = std::fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            let chunks = chunk_file(&relative_path, &contents, config.chunk_lines);
+            pending.push((relative_path, IndexedFile { modified, chunks }));
+        }
+
+        if let Some(embedding_model) = &config.embedding_model {
+            embed_chunks(embedding_model, &mut pending).await?;
+        }
+
+        for (path, file) in pending {
+            self.files.insert(path, file);
+            changed = true;
+        }
+
+        let before = self.files.len();
+        self.files.retain(|path, _| seen.contains(path));
+        changed |= self.files.len() != before;
+
+        anyhow::Ok(changed)
+    }
+
+    fn build_stats(&mut self) {
+        let mut stats = TermStats::default();
+        for chunk in self.chunks() {
+            let mut frequencies = HashMap::<String, usize>::new();
+            let mut length = 0;
+            for term in tokenize(&chunk.text) {
+                *frequencies.entry(term).or_default() += 1;
+                length += 1;
+            }
+            for term in frequencies.keys() {
+                *stats.document_frequency.entry(term.clone()).or_default() += 1;
+            }
+            stats.term_frequencies.push(frequencies);
+            stats.lengths.push(length);
+        }
+
+        stats.average_length =
+            stats.lengths.iter().sum::<usize>() as f64 / stats.lengths.len().max(1) as f64;
+        self.stats = stats;
+    }
+
+    /// Rank chunks against the query with BM25.
+    fn bm25_scores(&self, query: &str) -> Vec<f64> {
+        let stats = &self.stats;
+        let document_count = stats.lengths.len() as f64;
+        let terms = tokenize(query).collect::<Vec<String>>();
+
+        stats
+            .term_frequencies
+            .iter()
+            .zip(stats.lengths.iter())
+            .map(|(frequencies, length)| {
+                terms
+                    .iter()
+                    .map(|term| {
+                        let frequency = *frequencies.get(term).unwrap_or(&0) as f64;
+                        if frequency == 0.0 {
+                            return 0.0;
+                        }
+
+                        let document_frequency =
+                            *stats.document_frequency.get(term).unwrap_or(&0) as f64;
+                        let idf = ((document_count - document_frequency + 0.5)
+                            / (document_frequency + 0.5)
+                            + 1.0)
+                            .ln();
+                        let normalization =
+                            1.0 - B + B * *length as f64 / stats.average_length.max(1.0);
+                        idf * frequency * (K1 + 1.0) / (frequency + K1 * normalization)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// The chunks most relevant to the query.
+    ///
+    /// When chunks have been embedded, the BM25 and embedding rankings are combined with
+    /// reciprocal rank fusion.
+    pub async fn search(&self, query: &str, top_k: usize) -> anyhow::Result<Vec<&Chunk>> {
+        let chunks = self.chunks().collect::<Vec<&Chunk>>();
+        let bm25_scores = self.bm25_scores(query);
+        let mut rankings = vec![bm25_scores];
+
+        if let Some(embedding_model) = &self.embedding_model {
+            let query_embedding = Ollama::load()
+                .get_embeddings(embedding_model, &[query.to_string()])
+                .await?
+                .pop()
+                .ok_or(anyhow!("query was not embedded"))?;
+            rankings.push(
+                chunks
+                    .iter()
+                    .map(|chunk| match &chunk.embedding {
+                        Some(embedding) => cosine_similarity(&query_embedding, embedding),
+                        None => 0.0,
+                    })
+                    .collect(),
+            );
+        }
+
+        let mut fused = vec![0.0; chunks.len()];
+        for scores in &rankings {
+            let mut order = (0..chunks.len())
+                .filter(|idx| scores[*idx] > 0.0)
+                .collect::<Vec<usize>>();
+            order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+            for (rank, idx) in order.into_iter().enumerate() {
+                fused[idx] += 1.0 / (60.0 + rank as f64);
+            }
+        }
+
+        let mut order = (0..chunks.len())
+            .filter(|idx| fused[*idx] > 0.0)
+            .collect::<Vec<usize>>();
+        order.sort_by(|a, b| fused[*b].total_cmp(&fused[*a]));
+
+        anyhow::Ok(
+            order
+                .into_iter()
+                .take(top_k)
+                .map(|idx| chunks[idx])
+                .collect(),
+        )
+    }
+
+    /// Add the chunks most relevant to the latest user message to it, with citations.
+    ///
+    /// This only changes the messages sent to the model, and not the saved conversation.
+    pub async fn augment_messages(
+        &self,
+        mut messages: Vec<Message>,
+        top_k: usize,
+    ) -> anyhow::Result<Vec<Message>> {
+        let Some(message) = messages
+            .iter_mut()
+            .rev()
+            .find(|message| message.role == MessageRole::User)
+        else {
+            return anyhow::Ok(messages);
+        };
+
+        let chunks = self.search(&message.content, top_k).await?;
+        if chunks.is_empty() {
+            return anyhow::Ok(messages);
+        }
+
+        let mut context = format!(
+            "The following excerpts from files in {} may be relevant. When using them, cite the file and lines, ie. `{}`.\n",
+            self.root.display(),
+            chunks[0].citation()
+        );
+        for chunk in chunks {
+            context.push_str(&format!(
+                "\n[{}]\n```\n{}\n```\n",
+                chunk.citation(),
+                chunk.text
+            ));
+        }
+
+        message.content = format!("{context}\n{}", message.content);
+        anyhow::Ok(messages)
+    }
+}
+
+/// Indexes are stored by the path of the directory, so each directory has its own.
+fn get_index_path(root: &Path) -> PathBuf {
+    let name = root
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    get_data_dir()
+        .join("retrieval")
+        .join(format!("{}.json", name.trim_matches('_')))
+}
+
+/// Split a file into overlapping chunks of lines.
+fn chunk_file(path: &str, contents: &str, chunk_lines: usize) -> Vec<Chunk> {
+    let lines = contents.lines().collect::<Vec<&str>>();
+    let chunk_lines = chunk_lines.max(CHUNK_OVERLAP_LINES + 1);
+    let mut chunks = Vec::new();
+
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + chunk_lines).min(lines.len());
+        let text = lines[start..end].join("\n");
+        if !text.trim().is_empty() {
+            chunks.push(Chunk {
+                path: path.to_string(),
+                start_line: start + 1,
+                end_line: end,
+                text,
+                embedding: None,
+            });
+        }
+
+        if end == lines.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP_LINES;
+    }
+
+    chunks
+}
+
+async fn embed_chunks(
+    embedding_model: &str,
+    files: &mut [(String, IndexedFile)],
+) -> anyhow::Result<()> {
+    let provider = Ollama::load();
+    let mut chunks = files
+        .iter_mut()
+        .flat_map(|(_, file)| file.chunks.iter_mut())
+        .collect::<Vec<&mut Chunk>>();
+
+    for batch in chunks.chunks_mut(EMBEDDING_BATCH_SIZE) {
+        let inputs = batch
+            .iter()
+            .map(|chunk| format!("{}\n{}", chunk.path, chunk.text))
+            .collect::<Vec<String>>();
+        let embeddings = provider.get_embeddings(embedding_model, &inputs).await?;
+        for (chunk, embedding) in batch.iter_mut().zip(embeddings) {
+            chunk.embedding = Some(embedding);
+        }
+    }
+
+    anyhow::Ok(())
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    let dot = a.iter().zip(b).map(|(a, b)| (a * b) as f64).sum::<f64>();
+    let norm = |v: &[f32]| v.iter().map(|x| (x * x) as f64).sum::<f64>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        0.0
+    } else {
+        dot / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_search_directory() {
+        let directory =
+            std::env::temp_dir().join(format!("archer-retrieval-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(directory.join("src")).unwrap();
+        std::fs::create_dir_all(directory.join("target")).unwrap();
+        let parser = (1..=60)
+            .map(|line| format!("line {line}"))
+            .chain(["fn parse_config() -> Config {}".to_string()])
+            .collect::<Vec<String>>()
+            .join("\n");
+        std::fs::write(directory.join("src/parser.rs"), parser).unwrap();
+        std::fs::write(directory.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(directory.join("target/parser.rs"), "parse config").unwrap();
+
+        let mut index = RetrievalIndex {
+            root: directory.clone(),
+            embedding_model: None,
+            chunk_lines: RetrievalConfig::default().chunk_lines,
+            files: BTreeMap::new(),
+            stats: TermStats::default(),
+        };
+        index.refresh(&RetrievalConfig::default()).await.unwrap();
+        index.build_stats();
+
+        // Build directories are skipped, and the overlapping chunks of parser.rs are indexed
+        assert_eq!(index.chunk_count(), 3);
+
+        let results = index.search("where is the config parsed", 5).await.unwrap();
+        assert_eq!(
+            results
+                .iter()
+                .map(|chunk| chunk.citation())
+                .collect::<Vec<String>>(),
+            vec!["src/parser.rs:31-61"]
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::completion::{Message, MessageRole};
+use super::config::ARCHER_CONFIG;
+use super::usage::format_day;
+
+/// The event yielded by completion streams when the model calls a tool, with a json `ToolCall`
+/// as its data.
+pub const TOOL_CALL_EVENT: &str = "tool_call";
+
+/// The most times a completion can call tools before it is stopped.
+pub const MAX_TOOL_ROUNDS: usize = 8;
+
+/// Files larger than this are truncated before being returned to the model.
+const MAX_FILE_BYTES: usize = 100_000;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolsConfig {
+    /// The directory file tools are limited to, defaults to the directory archer is started in.
+    pub root: Option<PathBuf>,
+}
+
+impl ToolsConfig {
+    pub fn get_root(&self) -> anyhow::Result<PathBuf> {
+        match &self.root {
+            Some(root) => anyhow::Ok(root.clone()),
+            None => anyhow::Ok(std::env::current_dir()?),
+        }
+    }
+}
+
+lazy_static! {
+    static ref TOOL_REGISTRY: RwLock<ToolRegistry> = RwLock::new(ToolRegistry::default());
+}
+
+/// A request from the model to run a tool.
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl ToolCall {
+    /// A stream item requesting this tool call.
+    pub fn to_event(&self, id: String) -> (String, String, String) {
+        (
+            TOOL_CALL_EVENT.to_string(),
+            id,
+            serde_json::to_string(self).unwrap_or_default(),
+        )
+    }
+
+    /// Describe the call and its result as plain text, for models without tool support.
+    pub fn format_result(&self, result: &str) -> String {
+        format!("Result of {}({}):\n{result}", self.name, self.arguments)
+    }
+}
+
+/// A function which models can call during a completion.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> String;
+    fn description(&self) -> String;
+    /// A json schema for the arguments passed to `invoke`.
+    fn parameters(&self) -> Value;
+    async fn invoke(&self, arguments: Value) -> anyhow::Result<String>;
+}
+
+pub struct ToolRegistry {
+    tools: IndexMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        ToolRegistry {
+            tools: IndexMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.insert(tool.name(), tool);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.get(name).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Arc<dyn Tool>> {
+        self.tools.values().cloned().collect()
+    }
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        let mut registry = ToolRegistry::new();
+        registry.register(Arc::new(CurrentTime));
+        match ARCHER_CONFIG.tools.get_root() {
+            Ok(root) => {
+                registry.register(Arc::new(ReadFile { root: root.clone() }));
+                registry.register(Arc::new(ListDirectory { root }));
+            }
+            Err(err) => log::error!("File tools are unavailable: {err}"),
+        }
+        registry
+    }
+}
+
+/// Make a tool available to models with `tools` enabled, replacing any tool with the same name.
+pub fn register_tool(tool: Arc<dyn Tool>) {
+    if let Ok(mut registry) = TOOL_REGISTRY.write() {
+        registry.register(tool);
+    }
+}
+
+pub fn list_tools() -> Vec<Arc<dyn Tool>> {
+    TOOL_REGISTRY
+        .read()
+        .map(|registry| registry.list())
+        .unwrap_or_default()
+}
+
+/// Run a tool call, returning the result to send back to the model.
+///
+/// Errors are returned as the result, so the model can recover from them.
+pub async fn invoke_tool(call: &ToolCall) -> String {
+    let tool = TOOL_REGISTRY
+        .read()
+        .ok()
+        .and_then(|registry| registry.get(&call.name));
+
+    let result = match tool {
+        Some(tool) => tool.invoke(call.arguments.clone()).await,
+        None => Err(anyhow!("unknown tool '{}'", call.name)),
+    };
+
+    result.unwrap_or_else(|err| format!("Error: {err}"))
+}
+
+/// The tool calls made by the assistant message at `index`, which follow it as tool messages.
+pub fn get_tool_calls(messages: &[Message], index: usize) -> Vec<&ToolCall> {
+    messages
+        .iter()
+        .skip(index + 1)
+        .map_while(|message| match &message.role {
+            MessageRole::Tool(call) => Some(call),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The path requested by the model, which must be inside `root`.
+///
+/// Paths are canonicalized so `..` and symlinks can't escape the root, and hidden files are
+/// refused, as `.env` files and the like tend to hold secrets which would be sent to the provider.
+fn get_path_argument(arguments: &Value, root: &Path) -> anyhow::Result<PathBuf> {
+    let path = arguments["path"]
+        .as_str()
+        .ok_or(anyhow!("'path' must be provided"))?;
+
+    // Expand the home directory, as models tend to use it
+    let requested = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()
+            .ok_or(anyhow!("home directory not found"))?
+            .join(rest),
+        None => PathBuf::from(path),
+    };
+
+    let root = root.canonicalize()?;
+    let resolved = root
+        .join(requested)
+        .canonicalize()
+        .map_err(|err| anyhow!("{path}: {err}"))?;
+    let relative = resolved.strip_prefix(&root).map_err(|_| {
+        anyhow!(
+            "{path} is outside of {}, which tools are limited to",
+            root.display()
+        )
+    })?;
+    if relative
+        .components()
+        .any(|component| is_hidden(component.as_os_str()))
+    {
+        return Err(anyhow!("{path} is hidden, and can not be read by tools"));
+    }
+
+    anyhow::Ok(resolved)
+}
+
+fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> String {
+        "current_time".to_string()
+    }
+    fn description(&self) -> String {
+        "Get the current date and time, in UTC.".to_string()
+    }
+    fn parameters(&self) -> Value {
+        json!({"type": "object", "properties": {}})
+    }
+    async fn invoke(&self, _arguments: Value) -> anyhow::Result<String> {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let time = seconds % 86_400;
+        anyhow::Ok(format!(
+            "{}T{:02}:{:02}:{:02}Z",
+            format_day(seconds / 86_400),
+            time / 3_600,
+            time % 3_600 / 60,
+            time % 60
+        ))
+    }
+}
+
+struct ReadFile {
+    root: PathBuf,
+}
+
+#[async_trait]
+impl Tool for ReadFile {
+    fn name(&self) -> String {
+        "read_file".to_string()
+    }
+    fn description(&self) -> String {
+        "Read the contents of a local text file.".to_string()
+    }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {"path": {"type": "string", "description": "The path of the file to read"}},
+            "required": ["path"]
+        })
+    }
+    async fn invoke(&self, arguments: Value) -> anyhow::Result<String> {
+        let path = get_path_argument(&arguments, &self.root)?;
+        let contents = tokio::fs::read(&path).await?;
+        let truncated = contents.len() > MAX_FILE_BYTES;
+        let mut contents =
+            String::from_utf8_lossy(&contents[..contents.len().min(MAX_FILE_BYTES)]).to_string();
+        if truncated {
+            contents.push_str("\n[truncated]");
+        }
+        anyhow::Ok(contents)
+    }
+}
+
+struct ListDirectory {
+    root: PathBuf,
+}
+
+#[async_trait]
+impl Tool for ListDirectory {
+    fn name(&self) -> String {
+        "list_directory".to_string()
+    }
+    fn description(&self) -> String {
+        "List the files and directories in a local directory.".to_string()
+    }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {"path": {"type": "string", "description": "The path of the directory to list"}},
+            "required": ["path"]
+        })
+    }
+    async fn invoke(&self, arguments: Value) -> anyhow::Result<String> {
+        let path = get_path_argument(&arguments, &self.root)?;
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&path).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            if is_hidden(&entry.file_name()) {
+                continue;
+            }
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().await?.is_dir() {
+                name.push('/');
+            }
+            entries.push(name);
+        }
+        entries.sort();
+        anyhow::Ok(entries.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invoke_tool() {
+        let directory = std::env::temp_dir().join(format!("archer-tools-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(directory.join("nested")).unwrap();
+        std::fs::write(directory.join("notes.txt"), "hello").unwrap();
+        std::fs::write(directory.join(".env"), "API_KEY=secret").unwrap();
+
+        let read_file = ReadFile {
+            root: directory.join("nested"),
+        };
+        let list_directory = ListDirectory {
+            root: directory.clone(),
+        };
+        let read = |path: PathBuf| read_file.invoke(json!({ "path": path }));
+        let notes = directory.join("notes.txt");
+        assert!(read(notes.clone()).await.is_err());
+        assert!(read(directory.join("nested/../notes.txt")).await.is_err());
+
+        let read_file = ReadFile {
+            root: directory.clone(),
+        };
+        assert_eq!(
+            read_file.invoke(json!({ "path": notes })).await.unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            read_file
+                .invoke(json!({"path": "notes.txt"}))
+                .await
+                .unwrap(),
+            "hello"
+        );
+        assert!(read_file.invoke(json!({"path": ".env"})).await.is_err());
+        assert_eq!(
+            list_directory.invoke(json!({"path": "."})).await.unwrap(),
+            "nested/\nnotes.txt"
+        );
+
+        let missing = ToolCall {
+            id: "call".to_string(),
+            name: "missing".to_string(),
+            arguments: json!({}),
+        };
+        assert!(invoke_tool(&missing).await.starts_with("Error:"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
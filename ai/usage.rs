@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The event yielded by completion streams to report token counts, with a json
+/// `CompletionUsage` as its data.
+pub const USAGE_EVENT: &str = "usage";
+
+/// Token counts and timings for a single completion.
+///
+/// Token counts are only available for providers which report them.
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug, Default)]
+pub struct CompletionUsage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    pub time_to_first_token_ms: Option<u64>,
+    pub latency_ms: Option<u64>,
+}
+
+impl CompletionUsage {
+    pub fn from_tokens(prompt_tokens: Option<u64>, completion_tokens: Option<u64>) -> Self {
+        CompletionUsage {
+            prompt_tokens,
+            completion_tokens,
+            ..Default::default()
+        }
+    }
+
+    /// A stream item reporting these token counts.
+    pub fn to_event(&self, id: String) -> (String, String, String) {
+        (
+            USAGE_EVENT.to_string(),
+            id,
+            serde_json::to_string(self).unwrap_or_default(),
+        )
+    }
+
+    /// Update with any counts reported in a later usage event.
+    ///
+    /// Some providers report prompt and completion tokens in separate events.
+    pub fn merge(&mut self, other: &CompletionUsage) {
+        self.prompt_tokens = other.prompt_tokens.or(self.prompt_tokens);
+        self.completion_tokens = other.completion_tokens.or(self.completion_tokens);
+        self.time_to_first_token_ms = other.time_to_first_token_ms.or(self.time_to_first_token_ms);
+        self.latency_ms = other.latency_ms.or(self.latency_ms);
+    }
+}
+
+/// Running totals across many completions.
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug, Default)]
+pub struct UsageTotals {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub completions: u64,
+}
+
+impl UsageTotals {
+    pub fn add(&mut self, usage: &CompletionUsage) {
+        self.prompt_tokens += usage.prompt_tokens.unwrap_or_default();
+        self.completion_tokens += usage.completion_tokens.unwrap_or_default();
+        self.completions += 1;
+    }
+}
+
+/// The current UTC date, formatted as `YYYY-MM-DD`, which daily totals are keyed on.
+pub fn current_day() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    format_day(seconds / 86_400)
+}
+
+/// Convert days since the unix epoch to a civil date.
+pub(crate) fn format_day(days: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_day() {
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(19_782), "2024-02-29");
+        assert_eq!(format_day(20_744), "2026-10-18");
+    }
+
+    #[test]
+    fn test_merge_usage() {
+        let mut usage = CompletionUsage::from_tokens(Some(10), None);
+        usage.merge(&CompletionUsage::from_tokens(None, Some(5)));
+        assert_eq!(usage, CompletionUsage::from_tokens(Some(10), Some(5)));
+    }
+}
@@ -1,6 +1,9 @@
+use archer::ai::attachments::AttachmentPreview;
 use archer::ai::completion::Message as CompletionMessage;
 use archer::ai::completion::{CompletionModelID, CompletionProviderID, CompletionStatus};
 use archer::ai::config::{ModelConfig, Profile};
+use archer::ai::retrieval::RetrievalStatus;
+use archer::ai::usage::CompletionUsage;
 use std::fmt;
 use uuid::Uuid;
 
@@ -9,7 +12,9 @@ use serde::{
     Deserialize, Serialize,
 };
 
+use crate::markdown::CodeBlock;
 use crate::mode::Mode;
+use archer::ai::conversation::export::ExportFormat;
 use archer::ai::conversation::Conversation;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -22,20 +27,53 @@ pub enum Action {
     Quit,
     Refresh,
     Error(String),
+    /// Tell the user something has happened, ie. where a file was written.
+    Notify(String),
     Help,
     SendMessage(CompletionMessage, Profile),
+    /// Send the message to each of the models at once, to compare their replies side by side.
+    CompareMessage(CompletionMessage, Profile, Vec<ModelConfig>),
+    ResendMessage(Uuid, CompletionMessage, Profile),
+    EditMessage(Uuid, String),
     ReceiveMessage(Uuid, CompletionMessage),
     StreamMessage(Uuid, CompletionMessage),
+    RecordUsage(CompletionUsage),
+    CancelGeneration,
     SelectNextMessage,
     SelectPreviousMessage,
     DeleteSelectedMessage,
     CopySelectedMessage,
+    SelectNextCodeBlock,
+    CopySelectedCodeBlock,
+    SaveSelectedCodeBlock,
+    CopyText(String),
+    SaveCodeBlock(CodeBlock),
+    RegenerateSelectedMessage,
+    EditSelectedMessage,
+    SelectNextBranch,
+    SelectPreviousBranch,
+    ToggleSelectedToolCall,
+    ToggleRetrieval,
+    UpdateRetrievalStatus(RetrievalStatus),
+    /// The files attached by the `@path` references given, once resolved in the background.
+    UpdateAttachmentPreview(Vec<String>, AttachmentPreview),
+    ToggleCompare,
+    ToggleCompareModel,
+    SetCompareModels(Vec<ModelConfig>),
+    StartComparison(Vec<(Uuid, CompletionMessage)>),
+    SelectNextResponse,
+    SelectPreviousResponse,
+    KeepSelectedResponse,
+    KeepResponse(Uuid, CompletionMessage),
+    DiscardComparison,
     ToggleMaximized,
     RevertMode,
     SwitchMode(Mode),
     SelectNextInConfigList,
     SelectPreviousInConfigList,
     SwitchModel(ModelConfig),
+    /// The models listed by a provider, once refreshed in the background.
+    UpdateModels(CompletionProviderID, Vec<ModelConfig>),
     SwitchProfile(Profile),
     SwitchToSelectedItem,
     SwitchKeymap(String),
@@ -47,6 +85,9 @@ pub enum Action {
     NewConversation,
     SaveConversation,
     DeleteSelectedConversation,
+    ExportSelectedConversation(ExportFormat),
+    UpdateConversationSearch(String),
+    ClearConversationSearch,
     SetTitle(String),
     UpdateTitle(String),
     ScrollUp,
@@ -81,10 +122,26 @@ impl<'de> Deserialize<'de> for Action {
                     "Quit" => Ok(Action::Quit),
                     "Refresh" => Ok(Action::Refresh),
                     "Help" => Ok(Action::Help),
+                    "CancelGeneration" => Ok(Action::CancelGeneration),
                     "SelectPreviousMessage" => Ok(Action::SelectPreviousMessage),
                     "SelectNextMessage" => Ok(Action::SelectNextMessage),
                     "DeleteSelectedMessage" => Ok(Action::DeleteSelectedMessage),
                     "CopySelectedMessage" => Ok(Action::CopySelectedMessage),
+                    "SelectNextCodeBlock" => Ok(Action::SelectNextCodeBlock),
+                    "CopySelectedCodeBlock" => Ok(Action::CopySelectedCodeBlock),
+                    "SaveSelectedCodeBlock" => Ok(Action::SaveSelectedCodeBlock),
+                    "RegenerateSelectedMessage" => Ok(Action::RegenerateSelectedMessage),
+                    "EditSelectedMessage" => Ok(Action::EditSelectedMessage),
+                    "SelectNextBranch" => Ok(Action::SelectNextBranch),
+                    "SelectPreviousBranch" => Ok(Action::SelectPreviousBranch),
+                    "ToggleSelectedToolCall" => Ok(Action::ToggleSelectedToolCall),
+                    "ToggleRetrieval" => Ok(Action::ToggleRetrieval),
+                    "ToggleCompare" => Ok(Action::ToggleCompare),
+                    "ToggleCompareModel" => Ok(Action::ToggleCompareModel),
+                    "SelectNextResponse" => Ok(Action::SelectNextResponse),
+                    "SelectPreviousResponse" => Ok(Action::SelectPreviousResponse),
+                    "KeepSelectedResponse" => Ok(Action::KeepSelectedResponse),
+                    "DiscardComparison" => Ok(Action::DiscardComparison),
                     "RevertMode" => Ok(Action::RevertMode),
                     "SwitchToSelectedItem" => Ok(Action::SwitchToSelectedItem),
                     "SelectPreviousInConfigList" => Ok(Action::SelectPreviousInConfigList),
@@ -94,6 +151,7 @@ impl<'de> Deserialize<'de> for Action {
                     "LoadSelectedConversation" => Ok(Action::LoadSelectedConversation),
                     "DeleteSelectedConversation" => Ok(Action::DeleteSelectedConversation),
                     "NewConversation" => Ok(Action::NewConversation),
+                    "ClearConversationSearch" => Ok(Action::ClearConversationSearch),
                     "ScrollUp" => Ok(Action::ScrollUp),
                     "ScrollDown" => Ok(Action::ScrollDown),
                     "NextProvider" => Ok(Action::NextProvider),
@@ -106,13 +164,31 @@ impl<'de> Deserialize<'de> for Action {
                             "ActiveInput" => Ok(Action::SwitchMode(Mode::ActiveInput)),
                             "ActiveViewer" => Ok(Action::SwitchMode(Mode::ActiveViewer)),
                             "ModelSelector" => Ok(Action::SwitchMode(Mode::ModelSelector)),
+                            "Compare" => Ok(Action::SwitchMode(Mode::Compare)),
                             "ConversationManager" => {
                                 Ok(Action::SwitchMode(Mode::ConversationManager))
                             }
+                            "ConversationSearch" => {
+                                Ok(Action::SwitchMode(Mode::ConversationSearch))
+                            }
                             _ => Err(E::custom(format!("invalid Action Variant: {:?}", mode))),
                         }
                     }
 
+                    "ExportSelectedConversation" => {
+                        Ok(Action::ExportSelectedConversation(ExportFormat::default()))
+                    }
+                    data if data.starts_with("ExportSelectedConversation(") => {
+                        let format = data
+                            .trim_start_matches("ExportSelectedConversation(")
+                            .trim_end_matches(")");
+                        match format.parse::<ExportFormat>() {
+                            Ok(format) => Ok(Action::ExportSelectedConversation(format)),
+                            Err(_) => {
+                                Err(E::custom(format!("invalid Action Variant: {:?}", format)))
+                            }
+                        }
+                    }
                     data if data.starts_with("Error(") => {
                         let error_msg = data.trim_start_matches("Error(").trim_end_matches(")");
                         Ok(Action::Error(error_msg.to_string()))
@@ -1,13 +1,21 @@
 use arboard::{Clipboard, LinuxClipboardKind, SetExtLinux};
 use archer::ai::{
+    attachments::split_attachments,
     completion::{
-        CompletionModelID, CompletionProvider, CompletionProviderID, CompletionStatus, Message,
-        MessageMetadata, MessageRole,
+        CompletionError, CompletionModel, CompletionModelID, CompletionProvider,
+        CompletionProviderID, CompletionStatus, Message, MessageMetadata, MessageRole, ERROR_EVENT,
     },
-    config::{Profile, ARCHER_CONFIG},
-    providers::{get_model, COMPLETION_PROVIDERS},
+    config::{ModelConfig, ModelReference, Profile, ARCHER_CONFIG},
+    providers::{get_model, get_model_with_fallbacks, COMPLETION_PROVIDERS},
+    retrieval::{RetrievalIndex, RetrievalStatus},
+    tools::{invoke_tool, ToolCall, MAX_TOOL_ROUNDS, TOOL_CALL_EVENT},
+    usage::{CompletionUsage, USAGE_EVENT},
 };
+use base64::Engine;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_channel::Sender;
 use color_eyre::eyre::Result;
@@ -20,6 +28,7 @@ use replicate_rs::predictions::PredictionStatus;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use crate::{
@@ -29,9 +38,12 @@ use crate::{
         model_selector::ModelSelector, viewer::Viewer, Component,
     },
     config::Config,
+    markdown::CodeBlock,
     mode::Mode,
     tui::{self, Frame, Tui},
+    utils::get_data_dir,
 };
+use archer::ai::conversation::export::ExportFormat;
 use archer::ai::conversation::{Conversation, ConversationManager};
 
 #[derive(PartialEq, Eq, Ord, PartialOrd, Hash)]
@@ -56,6 +68,11 @@ pub struct App {
     pub conversation: Conversation,
     pub manager: ConversationManager,
     pub active_profile: Profile,
+    pub active_model: ModelConfig,
+    pub generation_token: CancellationToken,
+    pub retrieval_enabled: bool,
+    /// Loaded in the background when retrieval is first enabled, as indexing can be slow.
+    pub retrieval_index: Arc<std::sync::Mutex<Option<Arc<RetrievalIndex>>>>,
 }
 
 impl App {
@@ -78,7 +95,7 @@ impl App {
         );
         let config = Config::new()?;
         let mode = Mode::Input;
-        let conversation_manager = ConversationManager::default();
+        let conversation_manager = ConversationManager::load()?;
 
         Ok(Self {
             tick_rate,
@@ -94,28 +111,42 @@ impl App {
             conversation,
             manager: conversation_manager,
             active_profile: profile.clone(),
+            active_model: ARCHER_CONFIG.default_completion_model.clone(),
+            generation_token: CancellationToken::new(),
+            retrieval_enabled: false,
+            retrieval_index: Arc::new(std::sync::Mutex::new(None)),
         })
     }
 
     pub fn set_keymap(&mut self) {
         self.keymap = match self.mode {
-            Mode::Input => " i: insert; v: focus viewer; j: scroll down; k: scroll up; m: change model; c: change convo; q: quit; ",
-            Mode::ActiveInput => " enter: send message; ctrl+n: new line; esc: exit input mode; ",
+            Mode::Input => " i: insert; v: focus viewer; j: scroll down; k: scroll up; m: change model; c: change convo; r: toggle retrieval; s: toggle compare; ctrl+x: cancel; q: quit; ",
+            Mode::ActiveInput => " enter: send message; ctrl+n: new line; ctrl+x: cancel; esc: exit input mode; ",
             Mode::ActiveViewer => {
-                " j: select next; k: select prev; c: copy; esc: exit scroll mode; "
+                " j: select next; k: select prev; c: copy; b: next code block; y: copy code; w: write code; e: edit; r: regenerate; h/l: prev/next branch; esc: exit scroll mode; "
             }
             Mode::ModelSelector => {
-                " j: select next; k: select prev; enter: select model; m: close; "
+                " j: select next; k: select prev; enter: select model; space: compare model; m: close; "
             }
             Mode::ConversationManager => {
-                " j: select next; k: select prev; n: new convo; enter: load convo; d: delete convo; esc: close panel; "
+                " j: select next; k: select prev; n: new convo; enter: load convo; d: delete convo; e: export convo; /: search; esc: close panel; "
+            }
+            Mode::ConversationSearch => {
+                " type to search; up: select prev; down: select next; enter: finish search; esc: clear search; "
+            }
+            Mode::Compare => {
+                " h/l: prev/next reply; j: scroll down; k: scroll up; enter: keep reply; d: discard replies; ctrl+x: cancel; "
             }
         }
         .to_string();
     }
 
     pub fn set_mode(&mut self, mode: Mode) {
-        self.last_mode = self.mode;
+        // Searching is part of the conversation manager, so reverting skips over it
+        let managing = |mode| matches!(mode, Mode::ConversationManager | Mode::ConversationSearch);
+        if !(managing(mode) && managing(self.mode)) {
+            self.last_mode = self.mode;
+        }
         self.mode = mode;
         self.set_keymap();
     }
@@ -130,63 +161,9 @@ impl App {
 
     fn update_title(&mut self, action_tx: Sender<Action>, first_message: String) {
         let model_config = ARCHER_CONFIG.default_title_model.clone();
-
-        let system_prompt = "You are a helpful assistant, who title user queries.";
-        let prompt = format!(
-            "Given a message, from the user, please produce a short title for the message.
-
-For example if the user asked:
-What are the 3 hardest parts to learning rust.
-
-You should respond with 'Hardest parts of Rust'
-
-Another example is, if the user asked:
-What is the most popular car color?
-
-You should response with 'White'
-
-Please do not respond with anything else except the title.
-
-The users message is:
-
-{}
-
-Please provide a title for the user message above.
-Please keep the answer succinct, less than ten words long.",
-            first_message
-        );
-
-        let messages = vec![
-            Message {
-                role: MessageRole::System,
-                content: system_prompt.to_string(),
-                metadata: Some(MessageMetadata {
-                    model_config: model_config.clone(),
-                    status: CompletionStatus::Succeeded,
-                }),
-            },
-            Message {
-                role: MessageRole::User,
-                content: prompt.to_string(),
-                metadata: Some(MessageMetadata {
-                    model_config: model_config.clone(),
-                    status: CompletionStatus::Succeeded,
-                }),
-            },
-        ];
-
         tokio::spawn(async move {
-            if let Some(model) = get_model(&model_config).ok() {
-                if let Some(Some(mut result)) = model
-                    .get_completion(messages)
-                    .await
-                    .ok()
-                    .map(|mut x| x.get_content().ok())
-                {
-                    result = result.trim_matches('"').trim_end_matches('"').to_string();
-
-                    action_tx.send(Action::SetTitle(result)).await.ok();
-                }
+            if let Some(title) = generate_title(model_config, first_message).await {
+                action_tx.send(Action::SetTitle(title)).await.ok();
             }
         });
     }
@@ -200,23 +177,75 @@ Please keep the answer succinct, less than ten words long.",
         self.conversation = convo;
     }
 
+    /// Enable or disable adding retrieved chunks to prompts, (re)indexing the directory when
+    /// enabled so changed files are picked up.
+    fn toggle_retrieval(&mut self, action_tx: Sender<Action>) {
+        self.retrieval_enabled = !self.retrieval_enabled;
+        if !self.retrieval_enabled {
+            action_tx
+                .try_send(Action::UpdateRetrievalStatus(RetrievalStatus::Disabled))
+                .ok();
+            return;
+        }
+
+        let retrieval_index = self.retrieval_index.clone();
+        tokio::spawn(async move {
+            action_tx
+                .send(Action::UpdateRetrievalStatus(RetrievalStatus::Indexing))
+                .await
+                .ok();
+
+            // Indexing walks and reads the whole directory, so it is kept off the runtime's workers
+            let runtime = tokio::runtime::Handle::current();
+            let index = tokio::task::spawn_blocking(move || {
+                let config = &ARCHER_CONFIG.retrieval;
+                let directory = config.get_directory()?;
+                runtime.block_on(RetrievalIndex::open(&directory, config))
+            })
+            .await
+            .unwrap_or_else(|err| Err(err.into()));
+            let status = match index {
+                Ok(index) => {
+                    let status = RetrievalStatus::Enabled(index.chunk_count());
+                    if let Ok(mut retrieval_index) = retrieval_index.lock() {
+                        *retrieval_index = Some(Arc::new(index));
+                    }
+                    status
+                }
+                Err(err) => RetrievalStatus::Failed(err.to_string()),
+            };
+            action_tx
+                .send(Action::UpdateRetrievalStatus(status))
+                .await
+                .ok();
+        });
+    }
+
+    fn get_retrieval_index(&self) -> Option<Arc<RetrievalIndex>> {
+        if !self.retrieval_enabled {
+            return None;
+        }
+        self.retrieval_index.lock().ok()?.clone()
+    }
+
     fn send_message(&mut self, message: Message, profile: Profile, action_tx: Sender<Action>) {
         let first_message = self.conversation.has_no_user_messages();
-        let provider = COMPLETION_PROVIDERS
-            .get_provider(&message.clone().metadata.unwrap().model_config.provider_id)
-            .unwrap();
-        let model = provider
-            .get_model(&message.clone().metadata.unwrap().model_config)
-            .ok();
+        let model_config = match &message.metadata {
+            Some(metadata) => metadata.model_config.clone(),
+            None => self.active_model.clone(),
+        };
+        let model = get_model_with_fallbacks(&model_config, &profile.fallbacks);
         let mut messages = self
             .conversation
-            .messages
-            .values()
-            .map(|x| x.clone())
+            .get_messages()
+            .into_iter()
+            .map(|(_, message)| message.clone())
             .collect::<Vec<Message>>();
 
         let input_uuid = self.conversation.generate_message_id();
         let recv_uuid = self.conversation.generate_message_id();
+        let cancel_token = self.generation_token.child_token();
+        let retrieval = self.get_retrieval_index();
 
         tokio::spawn(async move {
             action_tx
@@ -231,147 +260,193 @@ Please keep the answer succinct, less than ten words long.",
                     .ok();
             }
 
-            if let Some(model) = model {
-                let mut content_map = IndexMap::<String, String>::new();
-                action_tx
-                    .send(Action::ReceiveMessage(
+            action_tx
+                .send(Action::ReceiveMessage(
+                    recv_uuid,
+                    Message {
+                        role: MessageRole::Assistant,
+                        content: "".to_string(),
+                        metadata: Some(MessageMetadata {
+                            model_config: model_config.clone(),
+                            status: CompletionStatus::Starting,
+                            usage: Default::default(),
+                            error: None,
+                            fallback: None,
+                        }),
+                    },
+                ))
+                .await
+                .ok();
+
+            match model {
+                Ok(model) => {
+                    messages.push(message.clone());
+
+                    stream_completion(
+                        model,
+                        model_config,
+                        messages,
+                        retrieval,
                         recv_uuid,
-                        Message {
-                            role: MessageRole::Assistant,
-                            content: "".to_string(),
-                            metadata: Some(MessageMetadata {
-                                model_config: message
-                                    .metadata
-                                    .as_ref()
-                                    .unwrap()
-                                    .model_config
-                                    .clone(),
-                                status: CompletionStatus::Starting,
-                            }),
-                        },
-                    ))
+                        cancel_token,
+                        action_tx,
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    fail_response(
+                        &action_tx,
+                        recv_uuid,
+                        &model_config,
+                        None,
+                        String::new(),
+                        Default::default(),
+                        err.into(),
+                    )
+                    .await;
+                }
+            }
+        });
+    }
+
+    /// Send a message to several models at once, streaming their replies side by side in the
+    /// viewer until one is kept.
+    ///
+    /// Tools are not offered to the models, as only a single reply from each is compared.
+    fn compare_message(
+        &mut self,
+        message: Message,
+        profile: Profile,
+        model_configs: Vec<ModelConfig>,
+        action_tx: Sender<Action>,
+    ) {
+        let first_message = self.conversation.has_no_user_messages();
+        let mut messages = self
+            .conversation
+            .get_messages()
+            .into_iter()
+            .map(|(_, message)| message.clone())
+            .collect::<Vec<Message>>();
+        messages.push(message.clone());
+
+        let input_uuid = self.conversation.generate_message_id();
+        let cancel_token = self.generation_token.child_token();
+        let retrieval = self.get_retrieval_index();
+        let responses = model_configs
+            .into_iter()
+            .map(|mut model_config| {
+                model_config.tools = false;
+                (self.conversation.generate_message_id(), model_config)
+            })
+            .collect::<Vec<(Uuid, ModelConfig)>>();
+
+        tokio::spawn(async move {
+            action_tx
+                .send(Action::ReceiveMessage(input_uuid, message.clone()))
+                .await
+                .ok();
+
+            if first_message {
+                action_tx
+                    .send(Action::UpdateTitle(message.content.clone()))
                     .await
                     .ok();
+            }
 
-                messages.push(message.clone());
+            let placeholders = responses
+                .iter()
+                .map(|(recv_uuid, model_config)| {
+                    let placeholder = Message {
+                        role: MessageRole::Assistant,
+                        content: "".to_string(),
+                        metadata: Some(MessageMetadata {
+                            model_config: model_config.clone(),
+                            status: CompletionStatus::Starting,
+                            usage: Default::default(),
+                            error: None,
+                            fallback: None,
+                        }),
+                    };
+                    (*recv_uuid, placeholder)
+                })
+                .collect();
+            action_tx
+                .send(Action::StartComparison(placeholders))
+                .await
+                .ok();
+            action_tx.send(Action::SwitchMode(Mode::Compare)).await.ok();
+
+            let messages = retrieve_context(messages, retrieval).await;
+            stream_comparison(
+                &responses,
+                messages,
+                &profile.fallbacks,
+                &cancel_token,
+                &action_tx,
+            )
+            .await;
+        });
+    }
 
-                let completion_result = model.start_streaming(messages).await;
+    fn regenerate_selected_message(&mut self, action_tx: Sender<Action>) {
+        let (Some(recv_uuid), Ok(selected_message)) = (
+            self.conversation.get_selected_uuid(),
+            self.conversation.get_selected_message(),
+        ) else {
+            return;
+        };
 
-                match completion_result {
-                    Ok(mut result) => 'outer: loop {
-                        result.poll().await;
-                        let status = result.get_status().await;
-                        match status {
-                            CompletionStatus::Starting => {
-                                tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-                            }
-                            CompletionStatus::Failed | CompletionStatus::Canceled => {
-                                let content = content_map
-                                    .values()
-                                    .into_iter()
-                                    .map(|x| x.as_str())
-                                    .collect::<Vec<&str>>()
-                                    .join("");
+        if selected_message.role != MessageRole::Assistant {
+            return;
+        }
+        let selected_uuid = recv_uuid;
+
+        // Regenerate with the active model, so a different model can be picked in the selector
+        let model_config = self.active_model.clone();
+        let fallbacks = &self.active_profile.fallbacks;
+        let model = match get_model_with_fallbacks(&model_config, fallbacks) {
+            Ok(model) => model,
+            Err(err) => {
+                action_tx
+                    .try_send(Action::Error(format!("{}: {err}", model_config.model_id)))
+                    .ok();
+                return;
+            }
+        };
 
-                                action_tx
-                                    .send(Action::StreamMessage(
-                                        recv_uuid,
-                                        Message {
-                                            role: MessageRole::Assistant,
-                                            content: content.clone(),
-                                            metadata: message.clone().metadata,
-                                        },
-                                    ))
-                                    .await
-                                    .ok();
-                            }
-                            CompletionStatus::Succeeded | CompletionStatus::Processing => {
-                                let stream = result.get_stream().await;
-                                match stream {
-                                    Ok(mut stream) => {
-                                        while let Some((event, id, data)) = stream.next().await {
-                                            if event == "done" {
-                                                let content = content_map
-                                                    .values()
-                                                    .into_iter()
-                                                    .map(|x| x.as_str())
-                                                    .collect::<Vec<&str>>()
-                                                    .join("");
-
-                                                action_tx
-                                                    .send(Action::StreamMessage(
-                                                        recv_uuid,
-                                                        Message {
-                                                            role: MessageRole::Assistant,
-                                                            content,
-                                                            metadata: Some(MessageMetadata {
-                                                                model_config: message
-                                                                    .metadata
-                                                                    .clone()
-                                                                    .unwrap()
-                                                                    .model_config,
-                                                                status: CompletionStatus::Succeeded,
-                                                            }),
-                                                        },
-                                                    ))
-                                                    .await
-                                                    .ok();
-
-                                                action_tx.send(Action::SaveConversation).await.ok();
-                                                break 'outer;
-                                            }
+        let messages = self.conversation.messages_before(&selected_uuid);
+        let cancel_token = self.generation_token.child_token();
+        let retrieval = self.get_retrieval_index();
 
-                                            content_map.insert(id, data);
-                                            let content = content_map
-                                                .values()
-                                                .into_iter()
-                                                .map(|x| x.as_str())
-                                                .collect::<Vec<&str>>()
-                                                .join("");
-                                            action_tx
-                                                .send(Action::StreamMessage(
-                                                    recv_uuid,
-                                                    Message {
-                                                        role: MessageRole::Assistant,
-                                                        content,
-                                                        metadata: Some(MessageMetadata {
-                                                            model_config: message
-                                                                .metadata
-                                                                .as_ref()
-                                                                .unwrap()
-                                                                .model_config
-                                                                .clone(),
-                                                            status: CompletionStatus::Processing,
-                                                        }),
-                                                    },
-                                                ))
-                                                .await
-                                                .ok();
-                                        }
-                                    }
-                                    Err(err) => {
-                                        action_tx
-                                            .send(Action::StreamMessage(
-                                                recv_uuid,
-                                                Message {
-                                                    role: MessageRole::Assistant,
-                                                    content: err.to_string(),
-                                                    metadata: message.clone().metadata,
-                                                },
-                                            ))
-                                            .await
-                                            .ok();
-                                    }
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        todo!();
-                    }
-                }
-            } else {
-            }
+        // The new generation is added as a sibling branch of the selected message
+        let recv_uuid = self.conversation.generate_message_id();
+        self.conversation.branch_from(&selected_uuid);
+        self.conversation.add_message(
+            recv_uuid,
+            Message {
+                role: MessageRole::Assistant,
+                content: "".to_string(),
+                metadata: Some(MessageMetadata {
+                    model_config: model_config.clone(),
+                    status: CompletionStatus::Starting,
+                    usage: Default::default(),
+                    error: None,
+                    fallback: None,
+                }),
+            },
+        );
+
+        tokio::spawn(async move {
+            stream_completion(
+                model,
+                model_config,
+                messages,
+                retrieval,
+                recv_uuid,
+                cancel_token,
+                action_tx,
+            )
+            .await;
         });
     }
 
@@ -445,8 +520,73 @@ Please keep the answer succinct, less than ten words long.",
                     Action::SendMessage(message, profile) => {
                         self.send_message(message, profile, action_tx.clone())
                     }
+                    Action::CompareMessage(message, profile, model_configs) => {
+                        self.compare_message(message, profile, model_configs, action_tx.clone())
+                    }
+                    Action::KeepResponse(uuid, message) => {
+                        self.receive_message(uuid, message);
+                        action_tx.send(Action::SaveConversation).await?;
+                        action_tx.send(Action::SwitchMode(Mode::Input)).await?;
+                    }
+                    Action::DiscardComparison => {
+                        // Any replies still streaming are stopped, and the message is left unanswered
+                        self.generation_token.cancel();
+                        self.generation_token = CancellationToken::new();
+                        action_tx.send(Action::SwitchMode(Mode::Input)).await?;
+                    }
+                    Action::CancelGeneration => {
+                        // Cancels every in flight generation, as each is spawned with a child token
+                        self.generation_token.cancel();
+                        self.generation_token = CancellationToken::new();
+                    }
                     Action::ReceiveMessage(uuid, message) => self.receive_message(uuid, message),
                     Action::StreamMessage(uuid, message) => self.stream_message(uuid, message),
+                    Action::ResendMessage(uuid, message, profile) => {
+                        // The edited message starts a new branch alongside the original
+                        self.conversation.branch_from(&uuid);
+                        self.send_message(message, profile, action_tx.clone())
+                    }
+                    Action::EditSelectedMessage => {
+                        if let (Some(uuid), Ok(message)) = (
+                            self.conversation.get_selected_uuid(),
+                            self.conversation.get_selected_message(),
+                        ) {
+                            if message.role == MessageRole::User {
+                                // Attached files are read again when the edit is sent
+                                let (text, _) = split_attachments(&message.content);
+                                action_tx
+                                    .send(Action::EditMessage(uuid, text.to_string()))
+                                    .await?;
+                                action_tx
+                                    .send(Action::SwitchMode(Mode::ActiveInput))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Action::RegenerateSelectedMessage => {
+                        self.regenerate_selected_message(action_tx.clone())
+                    }
+                    Action::SelectNextBranch => {
+                        if let Some(uuid) = self.conversation.get_selected_uuid() {
+                            self.conversation.select_next_branch(&uuid);
+                        }
+                    }
+                    Action::SelectPreviousBranch => {
+                        if let Some(uuid) = self.conversation.get_selected_uuid() {
+                            self.conversation.select_prev_branch(&uuid);
+                        }
+                    }
+                    Action::ToggleRetrieval => self.toggle_retrieval(action_tx.clone()),
+                    Action::UpdateRetrievalStatus(status) => match status {
+                        RetrievalStatus::Failed(_) => self.retrieval_enabled = false,
+                        // Retrieval was disabled again while the directory was being indexed
+                        RetrievalStatus::Enabled(_) if !self.retrieval_enabled => {
+                            action_tx
+                                .send(Action::UpdateRetrievalStatus(RetrievalStatus::Disabled))
+                                .await?;
+                        }
+                        _ => {}
+                    },
                     Action::SelectNextMessage => self.conversation.select_next_message(),
                     Action::SelectPreviousMessage => self.conversation.select_prev_message(),
                     Action::SetTitle(title) => {
@@ -461,26 +601,38 @@ Please keep the answer succinct, less than ten words long.",
                         self.conversation.delete_selected_message();
                     }
                     Action::CopySelectedMessage => {
-                        let selected_message = self.conversation.get_selected_message().unwrap();
-
-                        let content = selected_message.content.clone();
-
-                        #[cfg(any(target_os = "linux"))]
-                        tokio::spawn(async move {
-                            let mut ctx = Clipboard::new().unwrap();
-                            let _ = ctx
-                                .set()
-                                .wait()
-                                .clipboard(LinuxClipboardKind::Clipboard)
-                                .text(content.clone());
-                        });
-
-                        let content = selected_message.content.clone();
-                        let mut ctx = Clipboard::new()?;
-                        let _ = ctx.set().text(content);
+                        if let Ok(selected_message) = self.conversation.get_selected_message() {
+                            if let Err(err) = copy_to_clipboard(selected_message.content) {
+                                action_tx
+                                    .send(Action::Error(format!("Failed to copy: {err}")))
+                                    .await?;
+                            }
+                        }
+                    }
+                    Action::CopyText(content) => {
+                        if let Err(err) = copy_to_clipboard(content) {
+                            action_tx
+                                .send(Action::Error(format!("Failed to copy: {err}")))
+                                .await?;
+                        }
+                    }
+                    Action::SaveCodeBlock(code_block) => {
+                        let directory = get_data_dir().join("exports");
+                        let action = match save_code_block(&code_block, &directory) {
+                            Ok(path) => Action::Notify(format!("Saved to {}", path.display())),
+                            Err(err) => Action::Error(format!("Failed to save code block: {err}")),
+                        };
+                        action_tx.send(action).await?;
+                    }
+                    Action::RecordUsage(usage) => {
+                        if let Err(err) = self.manager.record_usage(&usage) {
+                            log::error!("Failed to record usage: {err}");
+                        }
                     }
                     Action::SaveConversation => {
-                        self.conversation.save().ok();
+                        if let Err(err) = self.manager.save_conversation(&self.conversation) {
+                            log::error!("Failed to save conversation: {err}");
+                        }
                     }
                     Action::SelectNextConversation => {
                         self.manager.select_next_conversation();
@@ -492,15 +644,47 @@ Please keep the answer succinct, less than ten words long.",
                         self.manager.activate_selected_conversation();
                         if let Some(convo) = self.manager.load_selected_conversation().ok() {
                             self.load_conversation(convo);
+
+                            // Jump to the first message matching the search
+                            let first_match = self
+                                .manager
+                                .get_search_matches(&self.conversation.id)
+                                .and_then(|matches| {
+                                    matches.iter().find(|id| {
+                                        self.conversation.nodes.get(*id).is_some_and(|node| {
+                                            node.message.role != MessageRole::System
+                                        })
+                                    })
+                                })
+                                .copied();
+                            if let Some(id) = first_match {
+                                self.conversation.activate_message(&id);
+                            }
                         }
                     }
+                    Action::UpdateConversationSearch(query) => {
+                        self.manager.set_search_query(query);
+                    }
+                    Action::ClearConversationSearch => {
+                        self.manager.set_search_query(String::new());
+                        action_tx
+                            .send(Action::SwitchMode(Mode::ConversationManager))
+                            .await?;
+                    }
                     Action::DeleteSelectedConversation => {
                         if let Some(id) = self.manager.get_selected_uuid().ok() {
-                            let file_path = self.manager.get_file_path(&id);
-                            self.manager.remove_conversation(&id);
-                            tokio::spawn(async move {
-                                tokio::fs::remove_file(&file_path).await.ok();
-                            });
+                            if let Err(err) = self.manager.delete_conversation(&id) {
+                                log::error!("Failed to delete conversation: {err}");
+                            }
+                        }
+                    }
+                    Action::ExportSelectedConversation(format) => {
+                        if let Ok(convo) = self
+                            .manager
+                            .get_selected_uuid()
+                            .and_then(|id| self.manager.load_conversation(&id))
+                        {
+                            action_tx.send(export_conversation(&convo, format)).await?;
                         }
                     }
                     Action::AddConversationToManager(convo) => {
@@ -530,7 +714,10 @@ Please keep the answer succinct, less than ten words long.",
                             // If ModelSelector or ConversationSelector is not the current mode
                             // the ViewerComponent makes up the entire top half
                             match self.mode {
-                                Mode::Input | Mode::ActiveInput | Mode::ActiveViewer => {
+                                Mode::Input
+                                | Mode::ActiveInput
+                                | Mode::ActiveViewer
+                                | Mode::Compare => {
                                     layouts.insert(AppPanel::Viewer, vertical_panels[0]);
                                 }
                                 _ => {
@@ -549,7 +736,8 @@ Please keep the answer succinct, less than ten words long.",
                                                     vertical_panels[0],
                                                 );
                                             }
-                                            Mode::ConversationManager => {
+                                            Mode::ConversationManager
+                                            | Mode::ConversationSearch => {
                                                 layouts.insert(
                                                     AppPanel::ConversationManager,
                                                     vertical_panels[0],
@@ -568,7 +756,8 @@ Please keep the answer succinct, less than ten words long.",
 
                                         layouts.insert(AppPanel::Viewer, horizontal_panels[0]);
                                         match self.mode {
-                                            Mode::ConversationManager => {
+                                            Mode::ConversationManager
+                                            | Mode::ConversationSearch => {
                                                 layouts.insert(
                                                     AppPanel::ConversationManager,
                                                     horizontal_panels[1],
@@ -628,7 +817,10 @@ Please keep the answer succinct, less than ten words long.",
                             // If ModelSelector or ConversationSelector is not the current mode
                             // the ViewerComponent makes up the entire top half
                             match self.mode {
-                                Mode::Input | Mode::ActiveInput | Mode::ActiveViewer => {
+                                Mode::Input
+                                | Mode::ActiveInput
+                                | Mode::ActiveViewer
+                                | Mode::Compare => {
                                     layouts.insert(AppPanel::Viewer, vertical_panels[0]);
                                 }
                                 _ => {
@@ -647,7 +839,8 @@ Please keep the answer succinct, less than ten words long.",
                                                     vertical_panels[0],
                                                 );
                                             }
-                                            Mode::ConversationManager => {
+                                            Mode::ConversationManager
+                                            | Mode::ConversationSearch => {
                                                 layouts.insert(
                                                     AppPanel::ConversationManager,
                                                     vertical_panels[0],
@@ -666,7 +859,8 @@ Please keep the answer succinct, less than ten words long.",
 
                                         layouts.insert(AppPanel::Viewer, horizontal_panels[0]);
                                         match self.mode {
-                                            Mode::ConversationManager => {
+                                            Mode::ConversationManager
+                                            | Mode::ConversationSearch => {
                                                 layouts.insert(
                                                     AppPanel::ConversationManager,
                                                     horizontal_panels[1],
@@ -709,6 +903,9 @@ Please keep the answer succinct, less than ten words long.",
                             .await
                             .ok();
                     }
+                    Action::SwitchModel(model_config) => {
+                        self.active_model = model_config;
+                    }
                     Action::SwitchProfile(profile) => {
                         self.active_profile = profile.clone();
                         self.conversation.set_profile(profile);
@@ -744,3 +941,936 @@ Please keep the answer succinct, less than ten words long.",
         Ok(())
     }
 }
+
+/// Write a conversation to the exports in the data dir, and copy it for pasting elsewhere,
+/// returning the action telling the user where it was written or why it failed.
+fn export_conversation(conversation: &Conversation, format: ExportFormat) -> Action {
+    let file_path =
+        get_data_dir()
+            .join("exports")
+            .join(format!("{}.{}", conversation.id, format.extension()));
+    let written = conversation.export(format).and_then(|exported| {
+        if let Some(directory) = file_path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(&file_path, &exported)?;
+        anyhow::Ok(exported)
+    });
+
+    match written.map(copy_to_clipboard) {
+        Ok(Ok(())) => Action::Notify(format!(
+            "Exported to {} and copied to the clipboard",
+            file_path.display()
+        )),
+        Ok(Err(err)) => Action::Error(format!(
+            "Exported to {}, but failed to copy it: {err}",
+            file_path.display()
+        )),
+        Err(err) => Action::Error(format!("Failed to export conversation: {err}")),
+    }
+}
+
+/// Copy to the system clipboard, falling back to the terminal's clipboard with OSC 52 when the
+/// system clipboard cannot be reached.
+fn copy_to_clipboard(content: String) -> anyhow::Result<()> {
+    // Over ssh the clipboard wanted is the local one, which only the terminal can reach
+    if std::env::var("SSH_CONNECTION").is_ok() || std::env::var("SSH_TTY").is_ok() {
+        return copy_with_osc52(&content);
+    }
+
+    let mut ctx = match Clipboard::new() {
+        Ok(ctx) => ctx,
+        Err(err) => {
+            log::warn!("System clipboard unavailable, copying with OSC 52: {err}");
+            return copy_with_osc52(&content);
+        }
+    };
+
+    // X11 clipboards are served by the application which owns them, so a clipboard is kept
+    // alive until something else is copied
+    #[cfg(any(target_os = "linux"))]
+    {
+        let content = content.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Ok(mut ctx) = Clipboard::new() {
+                let _ = ctx
+                    .set()
+                    .wait()
+                    .clipboard(LinuxClipboardKind::Clipboard)
+                    .text(content);
+            }
+        });
+    }
+
+    if let Err(err) = ctx.set().text(content.clone()) {
+        log::warn!("Failed to set the system clipboard, copying with OSC 52: {err}");
+        return copy_with_osc52(&content);
+    }
+    anyhow::Ok(())
+}
+
+/// Ask the terminal to set the clipboard, which works through ssh in most terminals.
+fn copy_with_osc52(content: &str) -> anyhow::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(content);
+    let mut sequence = format!("\x1b]52;c;{encoded}\x07");
+
+    // tmux only passes escape sequences on to the terminal when they are wrapped
+    if std::env::var("TMUX").is_ok() {
+        sequence = format!("\x1bPtmux;\x1b{sequence}\x1b\\");
+    }
+
+    let mut stdout = std::io::stdout();
+    stdout.write_all(sequence.as_bytes())?;
+    stdout.flush()?;
+    anyhow::Ok(())
+}
+
+/// Write a code block to a new file in `directory`, named for its language.
+fn save_code_block(code_block: &CodeBlock, directory: &Path) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let extension = code_block.extension();
+    let mut path = directory.join(format!("snippet.{extension}"));
+    let mut count = 1;
+    while path.exists() {
+        path = directory.join(format!("snippet-{count}.{extension}"));
+        count += 1;
+    }
+
+    std::fs::write(&path, &code_block.code)?;
+    anyhow::Ok(path)
+}
+
+/// Ask the title model for a short title for a conversation, from its first message.
+async fn generate_title(model_config: ModelConfig, first_message: String) -> Option<String> {
+    let system_prompt = "You are a helpful assistant, who title user queries.";
+    let prompt = format!(
+        "Given a message, from the user, please produce a short title for the message.
+
+For example if the user asked:
+What are the 3 hardest parts to learning rust.
+
+You should respond with 'Hardest parts of Rust'
+
+Another example is, if the user asked:
+What is the most popular car color?
+
+You should response with 'White'
+
+Please do not respond with anything else except the title.
+
+The users message is:
+
+{}
+
+Please provide a title for the user message above.
+Please keep the answer succinct, less than ten words long.",
+        first_message
+    );
+
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: system_prompt.to_string(),
+            metadata: Some(MessageMetadata {
+                model_config: model_config.clone(),
+                status: CompletionStatus::Succeeded,
+                usage: Default::default(),
+                error: None,
+                fallback: None,
+            }),
+        },
+        Message {
+            role: MessageRole::User,
+            content: prompt.to_string(),
+            metadata: Some(MessageMetadata {
+                model_config: model_config.clone(),
+                status: CompletionStatus::Succeeded,
+                usage: Default::default(),
+                error: None,
+                fallback: None,
+            }),
+        },
+    ];
+
+    let model = get_model(&model_config).ok()?;
+    let title = model
+        .get_completion(messages)
+        .await
+        .ok()?
+        .get_content()
+        .ok()?;
+    Some(title.trim_matches('"').trim_end_matches('"').to_string())
+}
+
+/// Add relevant chunks from the retrieval index to the latest user message, if there is an index.
+///
+/// The messages are sent without them if retrieval fails.
+async fn retrieve_context(
+    messages: Vec<Message>,
+    retrieval: Option<Arc<RetrievalIndex>>,
+) -> Vec<Message> {
+    let Some(index) = retrieval else {
+        return messages;
+    };
+
+    match index
+        .augment_messages(messages.clone(), ARCHER_CONFIG.retrieval.top_k)
+        .await
+    {
+        Ok(augmented) => augmented,
+        Err(err) => {
+            log::error!("Failed to retrieve context: {err}");
+            messages
+        }
+    }
+}
+
+/// Stream a completion for `messages` into the assistant message at `recv_uuid`.
+///
+/// When the model calls tools, they are run and their results added to the conversation, before
+/// the completion continues in a new assistant message.
+///
+/// With a retrieval index, relevant chunks are added to the latest user message before it is sent.
+async fn stream_completion(
+    model: Box<dyn CompletionModel>,
+    model_config: ModelConfig,
+    mut messages: Vec<Message>,
+    retrieval: Option<Arc<RetrievalIndex>>,
+    mut recv_uuid: Uuid,
+    cancel_token: CancellationToken,
+    action_tx: Sender<Action>,
+) {
+    messages = retrieve_context(messages, retrieval).await;
+
+    for round in 0..=MAX_TOOL_ROUNDS {
+        let Some((content, tool_calls)) = stream_response(
+            model.as_ref(),
+            &model_config,
+            messages.clone(),
+            recv_uuid,
+            &cancel_token,
+            &action_tx,
+        )
+        .await
+        else {
+            return;
+        };
+
+        if tool_calls.is_empty() {
+            return;
+        }
+
+        // Models which keep calling tools are stopped, rather than looping forever
+        if round == MAX_TOOL_ROUNDS {
+            action_tx
+                .send(Action::Error(format!(
+                    "stopped after {MAX_TOOL_ROUNDS} rounds of tool calls, leaving {} unrun",
+                    tool_calls.len()
+                )))
+                .await
+                .ok();
+            return;
+        }
+
+        messages.push(Message {
+            role: MessageRole::Assistant,
+            content,
+            metadata: None,
+        });
+
+        for call in tool_calls {
+            let content = tokio::select! {
+                content = invoke_tool(&call) => content,
+                _ = cancel_token.cancelled() => return,
+            };
+
+            let message = Message {
+                role: MessageRole::Tool(call),
+                content,
+                metadata: None,
+            };
+            action_tx
+                .send(Action::ReceiveMessage(Uuid::new_v4(), message.clone()))
+                .await
+                .ok();
+            messages.push(message);
+        }
+
+        recv_uuid = Uuid::new_v4();
+        action_tx
+            .send(Action::ReceiveMessage(
+                recv_uuid,
+                Message {
+                    role: MessageRole::Assistant,
+                    content: "".to_string(),
+                    metadata: Some(MessageMetadata {
+                        model_config: model_config.clone(),
+                        status: CompletionStatus::Starting,
+                        usage: Default::default(),
+                        error: None,
+                        fallback: None,
+                    }),
+                },
+            ))
+            .await
+            .ok();
+    }
+}
+
+/// The content streamed so far, from events keyed on their id.
+fn join_content(content_map: &IndexMap<String, String>) -> String {
+    content_map
+        .values()
+        .map(|x| x.as_str())
+        .collect::<Vec<&str>>()
+        .join("")
+}
+
+/// Mark the assistant message at `recv_uuid` as failed, keeping any partial content, and show the
+/// reason in the status bar.
+async fn fail_response(
+    action_tx: &Sender<Action>,
+    recv_uuid: Uuid,
+    model_config: &ModelConfig,
+    fallback: Option<ModelConfig>,
+    content: String,
+    usage: CompletionUsage,
+    error: CompletionError,
+) {
+    log::error!("Completion with {} failed: {error}", model_config.model_id);
+    action_tx
+        .send(Action::Error(format!("{}: {error}", model_config.model_id)))
+        .await
+        .ok();
+    action_tx
+        .send(Action::StreamMessage(
+            recv_uuid,
+            Message {
+                role: MessageRole::Assistant,
+                content,
+                metadata: Some(MessageMetadata {
+                    model_config: model_config.clone(),
+                    status: CompletionStatus::Failed,
+                    usage,
+                    error: Some(error),
+                    fallback,
+                }),
+            },
+        ))
+        .await
+        .ok();
+    action_tx.send(Action::SaveConversation).await.ok();
+}
+
+/// Stream replies to the same messages from each model being compared, all at once.
+async fn stream_comparison(
+    responses: &[(Uuid, ModelConfig)],
+    messages: Vec<Message>,
+    fallbacks: &[ModelReference],
+    cancel_token: &CancellationToken,
+    action_tx: &Sender<Action>,
+) {
+    let streams = responses.iter().map(|(recv_uuid, model_config)| {
+        let messages = messages.clone();
+        async move {
+            match get_model_with_fallbacks(model_config, fallbacks) {
+                Ok(model) => {
+                    stream_response(
+                        model.as_ref(),
+                        model_config,
+                        messages,
+                        *recv_uuid,
+                        cancel_token,
+                        action_tx,
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    fail_response(
+                        action_tx,
+                        *recv_uuid,
+                        model_config,
+                        None,
+                        String::new(),
+                        Default::default(),
+                        err.into(),
+                    )
+                    .await;
+                }
+            }
+        }
+    });
+    futures::future::join_all(streams).await;
+}
+
+/// Stream a single response into the assistant message at `recv_uuid`, returning its content
+/// and any tool calls once it is done.
+async fn stream_response(
+    model: &dyn CompletionModel,
+    model_config: &ModelConfig,
+    messages: Vec<Message>,
+    recv_uuid: Uuid,
+    cancel_token: &CancellationToken,
+    action_tx: &Sender<Action>,
+) -> Option<(String, Vec<ToolCall>)> {
+    let mut content_map = IndexMap::<String, String>::new();
+    let mut tool_calls = Vec::<ToolCall>::new();
+    let mut usage = CompletionUsage::default();
+    let started = Instant::now();
+
+    let mut result = match model.start_streaming(messages).await {
+        Ok(result) => result,
+        Err(err) => {
+            fail_response(
+                action_tx,
+                recv_uuid,
+                model_config,
+                None,
+                String::new(),
+                usage,
+                err.into(),
+            )
+            .await;
+            return None;
+        }
+    };
+
+    let mut retrying = None;
+    loop {
+        result.poll().await;
+        let status = result.get_status().await;
+        let fallback = result.get_fallback();
+        let mut canceled = false;
+        let mut finished = false;
+        match status {
+            CompletionStatus::Starting => {
+                tokio::select! {
+                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(500)) => {}
+                    _ = cancel_token.cancelled() => canceled = true,
+                }
+            }
+            // Show the attempt in the viewer, while waiting for the provider to be retried
+            CompletionStatus::Retrying { .. } => {
+                if retrying.as_ref() != Some(&status) {
+                    action_tx
+                        .send(Action::StreamMessage(
+                            recv_uuid,
+                            Message {
+                                role: MessageRole::Assistant,
+                                content: String::new(),
+                                metadata: Some(MessageMetadata {
+                                    model_config: model_config.clone(),
+                                    status: status.clone(),
+                                    usage: usage.clone(),
+                                    error: None,
+                                    fallback: fallback.clone(),
+                                }),
+                            },
+                        ))
+                        .await
+                        .ok();
+                    retrying = Some(status);
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(500)) => {}
+                    _ = cancel_token.cancelled() => canceled = true,
+                }
+            }
+            CompletionStatus::Canceled => canceled = true,
+            CompletionStatus::Failed => {
+                let error = result.get_error().unwrap_or(CompletionError::Provider(
+                    "the completion failed".to_string(),
+                ));
+                fail_response(
+                    action_tx,
+                    recv_uuid,
+                    model_config,
+                    fallback.clone(),
+                    join_content(&content_map),
+                    usage,
+                    error,
+                )
+                .await;
+                return None;
+            }
+            CompletionStatus::Succeeded | CompletionStatus::Processing => {
+                let mut stream = match result.get_stream().await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        fail_response(
+                            action_tx,
+                            recv_uuid,
+                            model_config,
+                            fallback.clone(),
+                            join_content(&content_map),
+                            usage,
+                            err.into(),
+                        )
+                        .await;
+                        return None;
+                    }
+                };
+
+                loop {
+                    let next = tokio::select! {
+                        next = stream.next() => next,
+                        _ = cancel_token.cancelled() => {
+                            canceled = true;
+                            break;
+                        }
+                    };
+
+                    let Some((event, id, data)) = next else {
+                        break;
+                    };
+
+                    if event == TOOL_CALL_EVENT {
+                        if let Ok(call) = serde_json::from_str(&data) {
+                            tool_calls.push(call);
+                        }
+                        continue;
+                    }
+
+                    if event == USAGE_EVENT {
+                        if let Ok(reported) = serde_json::from_str(&data) {
+                            usage.merge(&reported);
+                        }
+                        continue;
+                    }
+
+                    if event == ERROR_EVENT {
+                        let error = serde_json::from_str(&data)
+                            .unwrap_or(CompletionError::MalformedEvent(data));
+                        fail_response(
+                            action_tx,
+                            recv_uuid,
+                            model_config,
+                            fallback.clone(),
+                            join_content(&content_map),
+                            usage,
+                            error,
+                        )
+                        .await;
+                        return None;
+                    }
+
+                    if event == "done" {
+                        finished = true;
+                        break;
+                    }
+
+                    usage
+                        .time_to_first_token_ms
+                        .get_or_insert(started.elapsed().as_millis() as u64);
+                    content_map.insert(id, data);
+                    action_tx
+                        .send(Action::StreamMessage(
+                            recv_uuid,
+                            Message {
+                                role: MessageRole::Assistant,
+                                content: join_content(&content_map),
+                                metadata: Some(MessageMetadata {
+                                    model_config: model_config.clone(),
+                                    status: CompletionStatus::Processing,
+                                    usage: usage.clone(),
+                                    error: None,
+                                    fallback: fallback.clone(),
+                                }),
+                            },
+                        ))
+                        .await
+                        .ok();
+                }
+
+                // A stream which ends without finishing has either failed, or was cut off
+                if !finished && !canceled {
+                    drop(stream);
+                    result.poll().await;
+                    let error = result.get_error().unwrap_or(CompletionError::Network(
+                        "the response ended before it was finished".to_string(),
+                    ));
+                    fail_response(
+                        action_tx,
+                        recv_uuid,
+                        model_config,
+                        fallback.clone(),
+                        join_content(&content_map),
+                        usage,
+                        error,
+                    )
+                    .await;
+                    return None;
+                }
+            }
+        }
+
+        if finished {
+            usage.latency_ms = Some(started.elapsed().as_millis() as u64);
+            let content = join_content(&content_map);
+
+            action_tx
+                .send(Action::StreamMessage(
+                    recv_uuid,
+                    Message {
+                        role: MessageRole::Assistant,
+                        content: content.clone(),
+                        metadata: Some(MessageMetadata {
+                            model_config: model_config.clone(),
+                            status: CompletionStatus::Succeeded,
+                            usage: usage.clone(),
+                            error: None,
+                            fallback: fallback.clone(),
+                        }),
+                    },
+                ))
+                .await
+                .ok();
+
+            action_tx.send(Action::RecordUsage(usage)).await.ok();
+            action_tx.send(Action::SaveConversation).await.ok();
+            return Some((content, tool_calls));
+        }
+
+        // Keep the partial content, and stop the completion with the provider
+        if canceled {
+            result.cancel().await.ok();
+            usage.latency_ms = Some(started.elapsed().as_millis() as u64);
+
+            action_tx
+                .send(Action::StreamMessage(
+                    recv_uuid,
+                    Message {
+                        role: MessageRole::Assistant,
+                        content: join_content(&content_map),
+                        metadata: Some(MessageMetadata {
+                            model_config: model_config.clone(),
+                            status: CompletionStatus::Canceled,
+                            usage: usage.clone(),
+                            error: None,
+                            fallback: fallback.clone(),
+                        }),
+                    },
+                ))
+                .await
+                .ok();
+
+            action_tx.send(Action::RecordUsage(usage)).await.ok();
+            action_tx.send(Action::SaveConversation).await.ok();
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archer::ai::completion::CompletionProvider;
+    use archer::ai::conversation::store::JsonFileStore;
+    use archer::ai::providers::{Mock, RetryPolicy, RetryingModel, ECHO_MODEL};
+    use ratatui::{backend::TestBackend, Terminal};
+    use serde_json::json;
+
+    fn mock_model_config(extra_args: serde_json::Value, tools: bool) -> ModelConfig {
+        // The mock provider is only registered when asked for
+        std::env::set_var("ARCHER_MOCK", "1");
+        ModelConfig {
+            tools,
+            ..Mock::model_config(extra_args)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_completion() {
+        let model_config = mock_model_config(
+            json!({"responses": ["It is **noon**."], "token_delay_ms": 0, "tool_calls": [{"name": "current_time"}]}),
+            true,
+        );
+        let mut conversation = Conversation::new(ARCHER_CONFIG.profiles[0].clone());
+        let message = Message::new(MessageRole::User, "What time is it?");
+        conversation.add_message(conversation.generate_message_id(), message.clone());
+
+        let (action_tx, action_rx) = async_channel::unbounded();
+        stream_completion(
+            get_model(&model_config).unwrap(),
+            model_config.clone(),
+            vec![message],
+            None,
+            conversation.generate_message_id(),
+            CancellationToken::new(),
+            action_tx,
+        )
+        .await;
+
+        // Actions are applied as the app would, with the viewer drawn as the response streams
+        let directory = std::env::temp_dir().join(format!("archer-app-{}", Uuid::new_v4()));
+        let manager =
+            ConversationManager::with_store(Box::new(JsonFileStore::new(directory))).unwrap();
+        let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+        let mut viewer = Viewer::new();
+        let mut statuses = Vec::new();
+        while let Ok(action) = action_rx.try_recv() {
+            viewer.update(action.clone()).unwrap();
+            match action {
+                Action::ReceiveMessage(uuid, message) => conversation.add_message(uuid, message),
+                Action::StreamMessage(uuid, message) => {
+                    statuses.extend(message.metadata.clone().map(|metadata| metadata.status));
+                    conversation.replace_message(uuid, message);
+                }
+                _ => {}
+            }
+            terminal
+                .draw(|f| {
+                    viewer.draw(f, f.size(), &conversation, &manager).unwrap();
+                })
+                .unwrap();
+        }
+        assert!(statuses.contains(&CompletionStatus::Processing));
+
+        // The tool call is answered, before the scripted response is streamed in a new message
+        let messages = conversation.get_messages();
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(messages[2].1.role, MessageRole::Tool(_)));
+        let response = messages[3].1;
+        assert_eq!(response.content, "It is **noon**.");
+        let metadata = response.metadata.clone().unwrap();
+        assert_eq!(metadata.status, CompletionStatus::Succeeded);
+        assert_eq!(metadata.usage.completion_tokens, Some(3));
+
+        let rendered = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect::<String>();
+        assert!(rendered.contains("It is noon."));
+    }
+
+    #[tokio::test]
+    async fn test_stream_completion_errors() {
+        // The last update to the response, and the status bar message, for a failing model
+        async fn fail(extra_args: serde_json::Value) -> (Message, Option<String>) {
+            let model_config = mock_model_config(extra_args, false);
+            let (action_tx, action_rx) = async_channel::unbounded();
+            stream_completion(
+                get_model(&model_config).unwrap(),
+                model_config.clone(),
+                vec![Message::new(MessageRole::User, "Hello there")],
+                None,
+                Uuid::new_v4(),
+                CancellationToken::new(),
+                action_tx,
+            )
+            .await;
+
+            let (mut response, mut status_bar) = (None, None);
+            while let Ok(action) = action_rx.try_recv() {
+                match action {
+                    Action::StreamMessage(_, message) => response = Some(message),
+                    Action::Error(error) => status_bar = Some(error),
+                    _ => {}
+                }
+            }
+            (response.unwrap(), status_bar)
+        }
+
+        let (response, status_bar) =
+            fail(json!({"error": "Incorrect API key", "error_status": 401})).await;
+        let metadata = response.metadata.unwrap();
+        assert_eq!(metadata.status, CompletionStatus::Failed);
+        assert_eq!(
+            metadata.error,
+            Some(CompletionError::Auth("Incorrect API key".to_string()))
+        );
+        assert_eq!(
+            status_bar,
+            Some("scripted: authentication failed: Incorrect API key".to_string())
+        );
+
+        // Content streamed before the failure is kept
+        let (response, _) = fail(json!({"token_delay_ms": 0, "fail_after": 1})).await;
+        assert_eq!(response.content, "Hello ");
+        let metadata = response.metadata.unwrap();
+        assert_eq!(metadata.status, CompletionStatus::Failed);
+        assert!(matches!(metadata.error, Some(CompletionError::Network(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stream_completion_retries() {
+        let model_config = mock_model_config(
+            json!({"error": "Overloaded", "error_status": 503, "fail_times": 1, "token_delay_ms": 0}),
+            false,
+        );
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+        };
+        let model = Mock::load().get_model(&model_config).unwrap();
+        let (action_tx, action_rx) = async_channel::unbounded();
+        stream_completion(
+            Box::new(RetryingModel::new(model, policy)),
+            model_config.clone(),
+            vec![Message::new(MessageRole::User, "Hello there")],
+            None,
+            Uuid::new_v4(),
+            CancellationToken::new(),
+            action_tx,
+        )
+        .await;
+
+        // The retry is shown in the response, before it succeeds
+        let mut responses = Vec::new();
+        while let Ok(action) = action_rx.try_recv() {
+            if let Action::StreamMessage(_, message) = action {
+                responses.push(message);
+            }
+        }
+        let statuses = responses
+            .iter()
+            .map(|message| message.metadata.clone().unwrap().status)
+            .collect::<Vec<CompletionStatus>>();
+        assert_eq!(
+            statuses[0],
+            CompletionStatus::Retrying {
+                attempt: 1,
+                max_retries: 2
+            }
+        );
+        assert_eq!(statuses.last(), Some(&CompletionStatus::Succeeded));
+        assert_eq!(responses.last().unwrap().content, "Hello there");
+    }
+
+    #[tokio::test]
+    async fn test_stream_completion_fallback() {
+        let mut model_config = mock_model_config(
+            json!({"error": "Incorrect API key", "error_status": 401}),
+            false,
+        );
+        model_config.fallbacks = vec![ModelReference {
+            provider_id: "Mock".to_string(),
+            model_id: ECHO_MODEL.to_string(),
+        }];
+        let (action_tx, action_rx) = async_channel::unbounded();
+        stream_completion(
+            get_model(&model_config).unwrap(),
+            model_config.clone(),
+            vec![Message::new(MessageRole::User, "Hello there")],
+            None,
+            Uuid::new_v4(),
+            CancellationToken::new(),
+            action_tx,
+        )
+        .await;
+
+        // The fallback answers, and is recorded alongside the requested model
+        let mut response = None;
+        while let Ok(action) = action_rx.try_recv() {
+            if let Action::StreamMessage(_, message) = action {
+                response = Some(message);
+            }
+        }
+        let response = response.unwrap();
+        assert_eq!(response.content, "Hello there");
+        let metadata = response.metadata.unwrap();
+        assert_eq!(metadata.status, CompletionStatus::Succeeded);
+        assert_eq!(metadata.model_config, model_config);
+        assert_eq!(metadata.fallback.unwrap().model_id, ECHO_MODEL);
+    }
+
+    #[tokio::test]
+    async fn test_stream_comparison() {
+        let responses = vec![
+            (
+                Uuid::new_v4(),
+                mock_model_config(
+                    json!({"responses": ["Use a Vec."], "token_delay_ms": 0}),
+                    false,
+                ),
+            ),
+            (
+                Uuid::new_v4(),
+                mock_model_config(
+                    json!({"responses": ["Use a VecDeque."], "token_delay_ms": 0}),
+                    false,
+                ),
+            ),
+        ];
+        let (action_tx, action_rx) = async_channel::unbounded();
+        stream_comparison(
+            &responses,
+            vec![Message::new(MessageRole::User, "Which queue should I use?")],
+            &[],
+            &CancellationToken::new(),
+            &action_tx,
+        )
+        .await;
+
+        // Both replies are streamed into the viewer, side by side
+        let directory = std::env::temp_dir().join(format!("archer-app-{}", Uuid::new_v4()));
+        let manager =
+            ConversationManager::with_store(Box::new(JsonFileStore::new(directory))).unwrap();
+        let conversation = Conversation::new(ARCHER_CONFIG.profiles[0].clone());
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        let mut viewer = Viewer::new();
+        let placeholders = responses
+            .iter()
+            .map(|(uuid, model_config)| {
+                let message = Message {
+                    role: MessageRole::Assistant,
+                    content: "".to_string(),
+                    metadata: Some(MessageMetadata {
+                        model_config: model_config.clone(),
+                        status: CompletionStatus::Starting,
+                        usage: Default::default(),
+                        error: None,
+                        fallback: None,
+                    }),
+                };
+                (*uuid, message)
+            })
+            .collect();
+        viewer
+            .update(Action::StartComparison(placeholders))
+            .unwrap();
+        while let Ok(action) = action_rx.try_recv() {
+            viewer.update(action).unwrap();
+        }
+        terminal
+            .draw(|f| {
+                viewer.draw(f, f.size(), &conversation, &manager).unwrap();
+            })
+            .unwrap();
+        let rendered = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect::<String>();
+        assert!(rendered.contains("Use a Vec."));
+        assert!(rendered.contains("Use a VecDeque."));
+
+        // The selected reply is the one kept in the conversation
+        viewer.update(Action::SelectNextResponse).unwrap();
+        match viewer.update(Action::KeepSelectedResponse).unwrap() {
+            Some(Action::KeepResponse(uuid, message)) => {
+                assert_eq!(uuid, responses[1].0);
+                assert_eq!(message.content, "Use a VecDeque.");
+            }
+            _ => panic!("the selected reply should be kept"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_title() {
+        let model_config =
+            mock_model_config(json!({"responses": ["\"Hardest parts of Rust\""]}), false);
+        let title = generate_title(model_config, "What is hard about Rust?".to_string()).await;
+        assert_eq!(title, Some("Hardest parts of Rust".to_string()));
+
+        let model_config = mock_model_config(json!({"error": "rate limited"}), false);
+        assert_eq!(generate_title(model_config, "Hi".to_string()).await, None);
+    }
+}
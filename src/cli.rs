@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use archer::ai::conversation::export::ExportFormat;
+use clap::{Args, Parser, Subcommand};
 
 use crate::utils::version;
 
@@ -24,4 +25,90 @@ pub struct Cli {
         default_value_t = 45.0
     )]
     pub frame_rate: f64,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Stream a single completion to stdout, without launching the TUI
+    Ask(AskArgs),
+    /// Export a saved conversation to Markdown, HTML, plain text or JSON
+    Export(ExportArgs),
+    /// Build or refresh the retrieval index for a directory
+    Index(IndexArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct AskArgs {
+    #[arg(
+        value_name = "PROMPT",
+        help = "Prompt to send, read from stdin if omitted or '-'"
+    )]
+    pub prompt: Option<String>,
+
+    #[arg(
+        short = 'i',
+        long,
+        help = "Append stdin to the prompt, ie. to ask about piped output"
+    )]
+    pub stdin: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "MODEL_ID",
+        help = "Model to complete with, defaults to the configured default completion model"
+    )]
+    pub model: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PROVIDER_ID",
+        help = "Provider of the model, required for models which are not configured"
+    )]
+    pub provider: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Profile providing the system prompt, defaults to the first configured profile"
+    )]
+    pub profile: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[arg(
+        value_name = "CONVERSATION",
+        help = "Id or title of the conversation to export"
+    )]
+    pub conversation: String,
+
+    #[arg(
+        short,
+        long,
+        value_name = "FORMAT",
+        help = "One of markdown, html, text or json",
+        default_value_t = ExportFormat::Markdown
+    )]
+    pub format: ExportFormat,
+
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "File to write the export to, written to stdout if omitted"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    #[arg(
+        value_name = "DIR",
+        help = "Directory to index, defaults to the configured retrieval directory"
+    )]
+    pub directory: Option<PathBuf>,
 }
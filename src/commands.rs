@@ -0,0 +1,288 @@
+use std::io::{Read, Write};
+
+use anyhow::anyhow;
+use archer::ai::completion::{
+    CompletionError, CompletionStatus, Message, MessageMetadata, MessageRole, ERROR_EVENT,
+};
+use archer::ai::config::{ModelConfig, Profile, ARCHER_CONFIG};
+use archer::ai::conversation::{open_store, ConversationManager};
+use archer::ai::providers::{get_model_with_fallbacks, COMPLETION_PROVIDERS};
+use archer::ai::retrieval::RetrievalIndex;
+use archer::ai::tools::{invoke_tool, ToolCall, MAX_TOOL_ROUNDS, TOOL_CALL_EVENT};
+use archer::ai::usage::{current_day, CompletionUsage, USAGE_EVENT};
+use futures::StreamExt;
+use indexmap::IndexMap;
+
+use crate::cli::{AskArgs, ExportArgs, IndexArgs};
+
+/// Stream a one shot completion for the prompt provided to stdout.
+pub async fn ask(args: AskArgs) -> anyhow::Result<()> {
+    let prompt = read_prompt(args.prompt, args.stdin)?;
+    let profile = select_profile(args.profile.as_deref())?;
+    let model_config = select_model(args.model.as_deref(), args.provider.as_deref())?;
+    let model = get_model_with_fallbacks(&model_config, &profile.fallbacks)?;
+
+    let mut messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: profile.system_prompt,
+            metadata: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: prompt,
+            metadata: Some(MessageMetadata {
+                model_config: model_config.clone(),
+                status: CompletionStatus::Succeeded,
+                usage: Default::default(),
+                error: None,
+                fallback: None,
+            }),
+        },
+    ];
+
+    let mut stdout = std::io::stdout();
+    let mut last_char = None;
+
+    // Any tools called are run, and the completion continued with their results
+    for round in 0..=MAX_TOOL_ROUNDS {
+        let mut result = model.start_streaming(messages.clone()).await?;
+        let mut content_map = IndexMap::<String, String>::new();
+        let mut tool_calls = Vec::<ToolCall>::new();
+        let mut retrying = None;
+
+        loop {
+            result.poll().await;
+            match result.get_status().await {
+                CompletionStatus::Starting => {
+                    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+                }
+                CompletionStatus::Retrying {
+                    attempt,
+                    max_retries,
+                } => {
+                    if retrying != Some(attempt) {
+                        eprintln!("retrying ({attempt}/{max_retries})...");
+                        retrying = Some(attempt);
+                    }
+                    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+                }
+                CompletionStatus::Failed => {
+                    return Err(result
+                        .get_error()
+                        .unwrap_or(CompletionError::Provider(
+                            "the completion failed".to_string(),
+                        ))
+                        .into())
+                }
+                CompletionStatus::Canceled => return Err(anyhow!("completion was canceled")),
+                CompletionStatus::Succeeded | CompletionStatus::Processing => {
+                    if let Some(fallback) = result.get_fallback() {
+                        eprintln!(
+                            "{} is unavailable, answering with {}",
+                            model_config.model_id, fallback.model_id
+                        );
+                    }
+                    let mut stream = result.get_stream().await?;
+                    let mut finished = false;
+                    while let Some((event, id, data)) = stream.next().await {
+                        if event == "done" {
+                            finished = true;
+                            break;
+                        }
+                        if event == ERROR_EVENT {
+                            let error = serde_json::from_str(&data)
+                                .unwrap_or(CompletionError::MalformedEvent(data));
+                            return Err(error.into());
+                        }
+                        if event == USAGE_EVENT {
+                            if let Ok(usage) = serde_json::from_str::<CompletionUsage>(&data) {
+                                record_usage(&usage);
+                            }
+                            continue;
+                        }
+                        if event == TOOL_CALL_EVENT {
+                            if let Ok(call) = serde_json::from_str(&data) {
+                                tool_calls.push(call);
+                            }
+                            continue;
+                        }
+
+                        // Events may replace earlier events with the same id, in which case only
+                        // the newly appended text can still be written
+                        let previous = content_map.insert(id, data.clone()).unwrap_or_default();
+                        let delta = data.strip_prefix(previous.as_str()).unwrap_or_default();
+                        if let Some(c) = delta.chars().last() {
+                            last_char = Some(c);
+                        }
+
+                        stdout.write_all(delta.as_bytes())?;
+                        stdout.flush()?;
+                    }
+                    if !finished {
+                        return Err(CompletionError::Network(
+                            "the response ended before it was finished".to_string(),
+                        )
+                        .into());
+                    }
+                    break;
+                }
+            }
+        }
+
+        if tool_calls.is_empty() {
+            break;
+        }
+        if round == MAX_TOOL_ROUNDS {
+            eprintln!(
+                "stopped after {MAX_TOOL_ROUNDS} rounds of tool calls, leaving {} unrun",
+                tool_calls.len()
+            );
+            break;
+        }
+
+        messages.push(Message {
+            role: MessageRole::Assistant,
+            content: content_map.into_values().collect(),
+            metadata: None,
+        });
+        for call in tool_calls {
+            let content = invoke_tool(&call).await;
+            messages.push(Message {
+                role: MessageRole::Tool(call),
+                content,
+                metadata: None,
+            });
+        }
+    }
+
+    if last_char.is_some_and(|c| c != '\n') {
+        writeln!(stdout)?;
+    }
+
+    anyhow::Ok(())
+}
+
+/// Headless completions still count towards the daily totals, though failing to record them
+/// should not fail the command.
+fn record_usage(usage: &CompletionUsage) {
+    let recorded = open_store().and_then(|store| store.record_usage(&current_day(), usage));
+    if let Err(err) = recorded {
+        log::error!("Failed to record usage: {err}");
+    }
+}
+
+/// Write a saved conversation to a file, or stdout.
+pub fn export(args: ExportArgs) -> anyhow::Result<()> {
+    let mut manager = ConversationManager::load()?;
+    let id = manager
+        .find_conversation(args.conversation.as_str())
+        .ok_or(anyhow!("conversation '{}' not found", args.conversation))?;
+    let exported = manager.load_conversation(&id)?.export(args.format)?;
+
+    match args.output {
+        Some(path) => std::fs::write(path, exported)?,
+        None => std::io::stdout().write_all(exported.as_bytes())?,
+    }
+
+    anyhow::Ok(())
+}
+
+/// Index a directory for retrieval, only re-reading files which changed since it was last indexed.
+pub async fn index(args: IndexArgs) -> anyhow::Result<()> {
+    let config = &ARCHER_CONFIG.retrieval;
+    let directory = match args.directory {
+        Some(directory) => directory,
+        None => config.get_directory()?,
+    };
+
+    let index = RetrievalIndex::open(&directory, config).await?;
+    println!(
+        "Indexed {} chunks from {}",
+        index.chunk_count(),
+        index.get_root().display()
+    );
+
+    anyhow::Ok(())
+}
+
+/// The prompt argument, or stdin when it is omitted or '-'.
+///
+/// Stdin is otherwise only read when asked to append it, as it can be left open without any
+/// input, ie. under cron or in a git hook, which would hang waiting for it.
+fn read_prompt(prompt: Option<String>, append_stdin: bool) -> anyhow::Result<String> {
+    let prompt = prompt.filter(|prompt| prompt != "-");
+
+    let mut input = String::new();
+    if prompt.is_none() || append_stdin {
+        std::io::stdin().read_to_string(&mut input)?;
+    }
+
+    let prompt = match prompt {
+        Some(prompt) if input.trim().is_empty() => prompt,
+        Some(prompt) => format!("{prompt}\n\n{}", input.trim_end()),
+        None => input.trim_end().to_string(),
+    };
+
+    if prompt.trim().is_empty() {
+        return Err(anyhow!("no prompt provided"));
+    }
+
+    anyhow::Ok(prompt)
+}
+
+fn select_profile(name: Option<&str>) -> anyhow::Result<Profile> {
+    let profile = match name {
+        Some(name) => ARCHER_CONFIG
+            .profiles
+            .iter()
+            .find(|profile| profile.name == name),
+        None => ARCHER_CONFIG.profiles.first(),
+    };
+
+    profile
+        .cloned()
+        .ok_or(anyhow!("profile '{}' not found", name.unwrap_or_default()))
+}
+
+fn select_model(model_id: Option<&str>, provider_id: Option<&str>) -> anyhow::Result<ModelConfig> {
+    let Some(model_id) = model_id else {
+        return anyhow::Ok(ARCHER_CONFIG.default_completion_model.clone());
+    };
+
+    let configured = [&ARCHER_CONFIG.default_completion_model]
+        .into_iter()
+        .chain(ARCHER_CONFIG.models.iter())
+        .find(|model_config| {
+            model_config.model_id == model_id
+                && provider_id.is_none_or(|provider_id| model_config.provider_id == provider_id)
+        });
+
+    if let Some(model_config) = configured {
+        return anyhow::Ok(model_config.clone());
+    }
+
+    // Models which are not configured, ie. those pulled into Ollama, need their provider
+    let provider_id = provider_id.ok_or(anyhow!(
+        "model '{model_id}' is not configured, pass --provider to use it"
+    ))?;
+    let provider = COMPLETION_PROVIDERS
+        .get_provider(&provider_id.to_string())
+        .ok_or(anyhow!("unknown provider '{provider_id}'"))?;
+
+    let model_config = provider
+        .list_models()
+        .into_iter()
+        .find(|model_config| model_config.model_id == model_id)
+        .unwrap_or(ModelConfig {
+            provider_id: provider.get_id(),
+            model_id: model_id.to_string(),
+            extra_args: None,
+            context_length: None,
+            tools: false,
+            template: Default::default(),
+            fallbacks: Vec::new(),
+        });
+
+    anyhow::Ok(model_config)
+}
@@ -20,10 +20,12 @@ use crate::styles::{
     ACTIVE_COLOR, ASSISTANT_COLOR, FOCUSED_COLOR, SYSTEM_COLOR, UNFOCUSED_COLOR, USER_COLOR,
 };
 use crate::{action::Action, tui::Frame};
-use archer::ai::conversation::{Conversation, ConversationManager, CONVERSATION_DIR};
+use archer::ai::conversation::{Conversation, ConversationManager};
 use async_channel::Sender;
 
 use crate::config::{Config, KeyBindings};
+use crate::mode::Mode;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 #[derive(Default)]
 pub struct ConversationMeta {
@@ -34,6 +36,8 @@ pub struct ConversationMeta {
 pub struct ConversationSelector {
     command_tx: Option<Sender<Action>>,
     config: Config,
+    searching: bool,
+    search_query: String,
 }
 
 impl Component for ConversationSelector {
@@ -47,8 +51,32 @@ impl Component for ConversationSelector {
         Ok(())
     }
 
+    fn handle_key_events(&mut self, key: KeyEvent) -> anyhow::Result<Option<Action>> {
+        if !self.searching || key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(None);
+        }
+
+        match key.code {
+            KeyCode::Char(char) => self.search_query.push(char),
+            KeyCode::Backspace => {
+                self.search_query.pop();
+            }
+            _ => return Ok(None),
+        }
+
+        Ok(Some(Action::UpdateConversationSearch(
+            self.search_query.clone(),
+        )))
+    }
+
     fn update(&mut self, action: Action) -> anyhow::Result<Option<Action>> {
         match action {
+            Action::SwitchMode(mode) => {
+                self.searching = mode == Mode::ConversationSearch;
+            }
+            Action::ClearConversationSearch => {
+                self.search_query.clear();
+            }
             _ => {}
         }
         Ok(None)
@@ -66,10 +94,26 @@ impl Component for ConversationSelector {
             items.push(ListItem::new(title));
         }
 
+        let query = manager.get_search_query();
+        let title = if self.searching {
+            format!(" Search: {query}_ ")
+        } else if !query.is_empty() {
+            format!(" Load Conversation (search: {query}) ")
+        } else {
+            " Load Conversation ".to_string()
+        };
+
+        if items.is_empty() && !query.is_empty() {
+            items.push(ListItem::new(Line::styled(
+                "No matching conversations",
+                Style::default().fg(UNFOCUSED_COLOR),
+            )));
+        }
+
         let paragraph = List::new(items)
             .block(
                 Block::default()
-                    .title(" Load Conversation ")
+                    .title(title)
                     .title_alignment(Alignment::Left)
                     .borders(Borders::ALL)
                     .border_type(BorderType::Thick)
@@ -1,4 +1,6 @@
-use std::time::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use archer::ai::config::{ModelConfig, Profile, ARCHER_CONFIG};
 use color_eyre::eyre::Result;
@@ -13,12 +15,20 @@ use crate::config::{Config, KeyBindings};
 use crate::mode::Mode;
 use crate::styles::{ACTIVE_COLOR, FOCUSED_COLOR, UNFOCUSED_COLOR};
 use crate::{action::Action, tui::Frame};
+use archer::ai::attachments::{
+    attach_files, find_references, resolve_attachments, AttachmentPreview,
+};
 use archer::ai::completion::{CompletionModel, CompletionStatus, MessageMetadata};
 use archer::ai::completion::{Message, MessageRole};
 use archer::ai::conversation::{Conversation, ConversationManager};
 use archer::ai::providers::COMPLETION_PROVIDERS;
+use archer::ai::retrieval::RetrievalStatus;
 
 use async_channel::Sender;
+use uuid::Uuid;
+
+/// How long typing pauses for before `@path` references are resolved.
+const ATTACHMENT_DEBOUNCE: Duration = Duration::from_millis(300);
 
 #[derive(Default, Eq, PartialEq)]
 enum InputState {
@@ -37,6 +47,22 @@ pub struct MessageInput<'a> {
     active_profile: Profile,
     keymap: String,
     textarea: TextArea<'a>,
+    editing: Option<Uuid>,
+    /// The unsent message put aside while editing, restored once the edit is sent or abandoned.
+    draft: Vec<String>,
+    retrieval_status: RetrievalStatus,
+    attachment_references: Vec<String>,
+    attachment_preview: AttachmentPreview,
+    /// The references the preview was resolved for, behind the input while it is resolving.
+    preview_references: Vec<String>,
+    /// Counts changes to the references, so only the latest is resolved once typing pauses.
+    attachment_changes: Arc<AtomicUsize>,
+    /// The latest error or notice, and its color, shown in the bottom border until the next
+    /// message is sent.
+    status: Option<(String, Color)>,
+    /// Models marked in the model selector, which messages are also sent to while comparing.
+    compare_models: Vec<ModelConfig>,
+    comparing: bool,
 }
 
 impl MessageInput<'static> {
@@ -58,7 +84,106 @@ impl MessageInput<'static> {
             active_model,
             active_profile,
             textarea: TextArea::default(),
+            editing: None,
+            draft: Vec::new(),
+            retrieval_status: RetrievalStatus::default(),
+            attachment_references: Vec::new(),
+            attachment_preview: AttachmentPreview::default(),
+            preview_references: Vec::new(),
+            attachment_changes: Arc::new(AtomicUsize::new(0)),
+            status: None,
+            compare_models: Vec::new(),
+            comparing: false,
+        }
+    }
+}
+
+impl MessageInput<'static> {
+    /// Refresh the preview of attached files, when the `@path` references in the input change.
+    ///
+    /// References are resolved in the background once typing pauses, as expanding a glob such
+    /// as `@**/*.rs` while it is typed could walk the whole tree on every key.
+    fn update_attachment_preview(&mut self) {
+        let content = self.textarea.lines().join("\n");
+        let references = find_references(&content)
+            .into_iter()
+            .map(|reference| reference.to_string())
+            .collect::<Vec<String>>();
+        if references == self.attachment_references {
+            return;
+        }
+
+        self.attachment_references = references.clone();
+        let change = self.attachment_changes.fetch_add(1, Ordering::SeqCst) + 1;
+        let Some(action_tx) = self.command_tx.clone().filter(|_| !references.is_empty()) else {
+            self.attachment_preview = AttachmentPreview::default();
+            self.preview_references = references;
+            return;
+        };
+
+        let attachment_changes = self.attachment_changes.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(ATTACHMENT_DEBOUNCE).await;
+            if attachment_changes.load(Ordering::SeqCst) != change {
+                return;
+            }
+
+            let resolved = tokio::task::spawn_blocking(move || resolve_attachments(&content));
+            if let Ok(preview) = resolved.await {
+                action_tx
+                    .send(Action::UpdateAttachmentPreview(references, preview))
+                    .await
+                    .ok();
+            }
+        });
+    }
+
+    fn get_attachment_title(&self) -> String {
+        let preview = &self.attachment_preview;
+        if preview.is_empty() {
+            return String::new();
+        }
+
+        let mut parts = Vec::new();
+        match preview.attachments.len() {
+            0 => {}
+            1..=3 => parts.push(format!(
+                "Attaching {} ({:.1} KB)",
+                preview
+                    .attachments
+                    .iter()
+                    .map(|attachment| attachment.path.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                preview.total_size() as f64 / 1000.0
+            )),
+            count => parts.push(format!(
+                "Attaching {count} files ({:.1} KB)",
+                preview.total_size() as f64 / 1000.0
+            )),
         }
+        if !preview.skipped.is_empty() {
+            parts.push(format!("{} skipped", preview.skipped.len()));
+        }
+        if !preview.unmatched.is_empty() {
+            parts.push(format!("no match for @{}", preview.unmatched.join(", @")));
+        }
+
+        format!(" {} ", parts.join("; "))
+    }
+
+    /// The active model, followed by the other models marked for comparison.
+    fn get_compared_models(&self) -> Vec<ModelConfig> {
+        let mut models = vec![self.active_model.clone()];
+        for model_config in &self.compare_models {
+            if !models.iter().any(|model| {
+                model.provider_id == model_config.provider_id
+                    && model.model_id == model_config.model_id
+            }) {
+                models.push(model_config.clone());
+            }
+        }
+        models
     }
 }
 
@@ -82,19 +207,59 @@ impl Component for MessageInput<'static> {
                 KeyCode::Enter => {
                     let content = self.textarea.lines().join("\n");
                     if content.len() > 0 {
-                        let action = Action::SendMessage(
-                            Message {
-                                role: MessageRole::User,
-                                content,
-                                metadata: Some(MessageMetadata {
-                                    model_config: self.active_model.clone(),
-                                    status: CompletionStatus::Succeeded,
-                                }),
-                            },
-                            self.active_profile.clone(),
-                        );
-                        self.textarea = TextArea::default();
-                        return Ok(Some(action));
+                        let metadata = MessageMetadata {
+                            model_config: self.active_model.clone(),
+                            status: CompletionStatus::Succeeded,
+                            usage: Default::default(),
+                            error: None,
+                            fallback: None,
+                        };
+                        let profile = self.active_profile.clone();
+                        let editing = self.editing.take();
+                        let compared_models = Some(self.get_compared_models())
+                            .filter(|models| self.comparing && models.len() > 1);
+                        // The files shown in the preview are attached, unless it is still
+                        // being resolved for the latest references
+                        let preview = (self.preview_references == self.attachment_references)
+                            .then(|| self.attachment_preview.clone());
+
+                        self.textarea = TextArea::new(std::mem::take(&mut self.draft));
+                        self.update_attachment_preview();
+
+                        let Some(action_tx) = self.command_tx.clone() else {
+                            return Ok(None);
+                        };
+                        // Attached files are read in the background, as they can be large
+                        tokio::spawn(async move {
+                            let attached = tokio::task::spawn_blocking(move || {
+                                let preview =
+                                    preview.unwrap_or_else(|| resolve_attachments(&content));
+                                attach_files(&content, &preview)
+                            })
+                            .await;
+
+                            let action = match attached {
+                                Ok(content) => {
+                                    let message = Message {
+                                        role: MessageRole::User,
+                                        content,
+                                        metadata: Some(metadata),
+                                    };
+                                    match (editing, compared_models) {
+                                        (Some(uuid), _) => {
+                                            Action::ResendMessage(uuid, message, profile)
+                                        }
+                                        (None, Some(models)) => {
+                                            Action::CompareMessage(message, profile, models)
+                                        }
+                                        (None, None) => Action::SendMessage(message, profile),
+                                    }
+                                }
+                                Err(err) => Action::Error(format!("Failed to attach files: {err}")),
+                            };
+                            action_tx.send(action).await.ok();
+                        });
+                        return Ok(None);
                     }
                 }
                 _ => {
@@ -112,6 +277,7 @@ impl Component for MessageInput<'static> {
                     } else {
                         self.textarea.input(key);
                     }
+                    self.update_attachment_preview();
                 }
             }
         }
@@ -124,23 +290,73 @@ impl Component for MessageInput<'static> {
             Action::SwitchKeymap(keymap) => {
                 self.keymap = keymap;
             }
-            Action::SwitchMode(mode) => match mode {
-                Mode::ActiveViewer | Mode::ModelSelector | Mode::ConversationManager => {
-                    self.state = InputState::Unfocused;
+            Action::EditMessage(uuid, content) => {
+                if self.editing.is_none() {
+                    self.draft = self.textarea.lines().to_vec();
                 }
-                Mode::Input => {
-                    self.state = InputState::Focused;
+                self.textarea = TextArea::new(content.lines().map(|x| x.to_string()).collect());
+                self.editing = Some(uuid);
+                self.update_attachment_preview();
+            }
+            Action::SwitchMode(mode) => {
+                // Leaving the input abandons an edit, returning to the draft message
+                if mode != Mode::ActiveInput && self.editing.take().is_some() {
+                    self.textarea = TextArea::new(std::mem::take(&mut self.draft));
+                    self.update_attachment_preview();
                 }
-                Mode::ActiveInput => {
-                    self.state = InputState::Active;
+
+                match mode {
+                    Mode::ActiveViewer
+                    | Mode::ModelSelector
+                    | Mode::ConversationManager
+                    | Mode::ConversationSearch
+                    | Mode::Compare => {
+                        self.state = InputState::Unfocused;
+                    }
+                    Mode::Input => {
+                        self.state = InputState::Focused;
+                    }
+                    Mode::ActiveInput => {
+                        self.state = InputState::Active;
+                    }
                 }
-            },
+            }
             Action::SwitchModel(model_config) => {
                 self.active_model = model_config;
             }
             Action::SwitchProfile(profile) => {
                 self.active_profile = profile;
             }
+            Action::UpdateRetrievalStatus(status) => {
+                self.retrieval_status = status;
+            }
+            // Previews resolved for references which have since changed are out of date
+            Action::UpdateAttachmentPreview(references, preview)
+                if references == self.attachment_references =>
+            {
+                self.attachment_preview = preview;
+                self.preview_references = references;
+            }
+            Action::Error(error) => {
+                self.status = Some((error, Color::LightRed));
+            }
+            Action::Notify(notice) => {
+                self.status = Some((notice, Color::LightGreen));
+            }
+            Action::SendMessage(..) | Action::ResendMessage(..) | Action::CompareMessage(..) => {
+                self.status = None;
+            }
+            Action::SetCompareModels(model_configs) => {
+                self.compare_models = model_configs;
+            }
+            Action::ToggleCompare => {
+                if self.get_compared_models().len() < 2 {
+                    return Ok(Some(Action::Error(
+                        "mark models to compare with space in the model selector".to_string(),
+                    )));
+                }
+                self.comparing = !self.comparing;
+            }
 
             _ => {}
         }
@@ -154,18 +370,48 @@ impl Component for MessageInput<'static> {
         conversation: &Conversation,
         manager: &ConversationManager,
     ) -> Result<()> {
-        let display_name = self.active_model.model_id.clone();
+        let compared_models = self.get_compared_models();
+        let comparing = self.comparing && compared_models.len() > 1;
+        let display_name = if comparing {
+            compared_models
+                .iter()
+                .map(|model_config| model_config.model_id.as_str())
+                .collect::<Vec<&str>>()
+                .join(" vs ")
+        } else {
+            self.active_model.model_id.clone()
+        };
         let profile_name = self.active_profile.name.clone();
+        let title = if self.editing.is_some() {
+            "Edit Message"
+        } else if comparing {
+            "Compare"
+        } else {
+            "Message"
+        };
+        let retrieval_title = match &self.retrieval_status {
+            RetrievalStatus::Disabled => String::new(),
+            RetrievalStatus::Indexing => " Retrieval: indexing ".to_string(),
+            RetrievalStatus::Enabled(chunks) => format!(" Retrieval: {chunks} chunks "),
+            RetrievalStatus::Failed(err) => format!(" Retrieval failed: {err} "),
+        };
+        // Errors and notices replace the keymap, until the next message is sent
+        let status_title = match &self.status {
+            Some((status, color)) => Title::from(Span::styled(
+                format!(" {status} "),
+                Style::default().fg(*color),
+            ))
+            .alignment(Alignment::Left),
+            None => Title::from(self.keymap.clone()).alignment(Alignment::Center),
+        };
         let block = Block::default()
             .title(
-                Title::from(format!(" Message ({profile_name}: {display_name}) "))
+                Title::from(format!(" {title} ({profile_name}: {display_name}) "))
                     .alignment(Alignment::Left),
             )
-            .title(
-                Title::from(self.keymap.clone())
-                    .alignment(Alignment::Center)
-                    .position(Position::Bottom),
-            )
+            .title(Title::from(self.get_attachment_title()).alignment(Alignment::Right))
+            .title(Title::from(retrieval_title).alignment(Alignment::Right))
+            .title(status_title.position(Position::Bottom))
             .borders(Borders::ALL)
             .border_type(BorderType::Thick)
             .style(Style::default().fg(match self.state {
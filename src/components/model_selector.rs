@@ -52,6 +52,8 @@ pub struct ModelSelector {
     selected_model: HashMap<CompletionProviderID, (usize, Vec<ModelConfig>)>,
     selected_profile: (usize, Vec<Profile>),
     selected_tab: Tab,
+    /// Models marked to compare replies from, alongside the active model.
+    compare_models: Vec<ModelConfig>,
 }
 
 impl ModelSelector {
@@ -75,13 +77,14 @@ impl ModelSelector {
                 if let Some((selected_idx, models)) =
                     self.selected_model.get_mut(&self.selected_provider)
                 {
-                    if selected_idx < &mut (models.len() - 1) {
+                    // Providers can list no models, ie. when Ollama is not running
+                    if *selected_idx + 1 < models.len() {
                         *selected_idx += 1;
                     }
                 }
             }
             Tab::Profiles => {
-                if self.selected_profile.0 < (self.selected_profile.1.len() - 1) {
+                if self.selected_profile.0 + 1 < self.selected_profile.1.len() {
                     self.selected_profile.0 += 1;
                 }
             }
@@ -107,6 +110,26 @@ impl ModelSelector {
         }
     }
 
+    /// List the provider's models again in the background, as querying them can be slow.
+    fn refresh_models(&self, provider_id: CompletionProviderID) {
+        let Some(action_tx) = self.command_tx.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            let Some(provider) = COMPLETION_PROVIDERS.get_provider(&provider_id) else {
+                return;
+            };
+            if let Err(err) = provider.refresh_models().await {
+                log::warn!("Failed to list {provider_id} models: {err}");
+                return;
+            }
+            action_tx
+                .send(Action::UpdateModels(provider_id, provider.list_models()))
+                .await
+                .ok();
+        });
+    }
+
     fn get_selected_model_config(&self) -> anyhow::Result<ModelConfig> {
         if let Some(Some(model)) = self
             .selected_model
@@ -148,7 +171,8 @@ impl Component for ModelSelector {
                         self.selected_model
                             .insert(prev_provider.clone(), (0, models));
                     }
-                    self.selected_provider = prev_provider;
+                    self.selected_provider = prev_provider.clone();
+                    self.refresh_models(prev_provider);
                 }
             }
             Action::NextProvider => {
@@ -160,7 +184,33 @@ impl Component for ModelSelector {
                         self.selected_model
                             .insert(next_provider.clone(), (0, models));
                     }
-                    self.selected_provider = next_provider;
+                    self.selected_provider = next_provider.clone();
+                    self.refresh_models(next_provider);
+                }
+            }
+            Action::SwitchMode(Mode::ModelSelector) => {
+                self.refresh_models(self.selected_provider.clone());
+            }
+            Action::UpdateModels(provider_id, models) => {
+                // The selection stays on the same model, if it is still listed
+                if let Some((selected_idx, listed)) = self.selected_model.get_mut(&provider_id) {
+                    let selected = listed.get(*selected_idx);
+                    *selected_idx = selected
+                        .and_then(|selected| models.iter().position(|model| model == selected))
+                        .unwrap_or(0);
+                    *listed = models;
+                }
+            }
+            Action::ToggleCompareModel => {
+                if let (Tab::Models, Ok(model_config)) =
+                    (&self.selected_tab, self.get_selected_model_config())
+                {
+                    let marked = self.compare_models.len();
+                    self.compare_models.retain(|model| model != &model_config);
+                    if self.compare_models.len() == marked {
+                        self.compare_models.push(model_config);
+                    }
+                    return Ok(Some(Action::SetCompareModels(self.compare_models.clone())));
                 }
             }
             Action::SelectNextInConfigList => self.select_next(),
@@ -292,10 +342,14 @@ impl Component for ModelSelector {
                     .map(|x| x.1.clone())
                     .unwrap_or(Vec::new())
                 {
-                    items.push(ListItem::new(Line::from(vec![Span::styled(
-                        model.model_id.clone(),
-                        Style::default(),
-                    )])))
+                    let mut spans = vec![Span::styled(model.model_id.clone(), Style::default())];
+                    if self.compare_models.contains(&model) {
+                        spans.push(Span::styled(
+                            " (compare)",
+                            Style::default().fg(UNFOCUSED_COLOR),
+                        ));
+                    }
+                    items.push(ListItem::new(Line::from(spans)))
                 }
 
                 let paragraph = List::new(items)
@@ -2,6 +2,9 @@ use futures::StreamExt;
 use lazy_static::lazy_static;
 use ratatui::widgets::block::Title;
 use regex::Regex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::str::from_utf8;
 use std::time::{Duration, Instant};
 use std::{fmt, fs};
@@ -16,21 +19,78 @@ use ratatui::{prelude::*, widgets::*};
 use replicate_rs::predictions::PredictionStatus;
 
 use super::Component;
+use crate::markdown::{extract_code_blocks, render_markdown, CodeBlock};
 use crate::mode::Mode;
 use crate::styles::{
-    ACTIVE_COLOR, ASSISTANT_COLOR, FOCUSED_COLOR, SYSTEM_COLOR, UNFOCUSED_COLOR, USER_COLOR,
+    ACTIVE_COLOR, ASSISTANT_COLOR, FOCUSED_COLOR, SYSTEM_COLOR, TOOL_COLOR, UNFOCUSED_COLOR,
+    USER_COLOR,
 };
 use crate::{action::Action, tui::Frame};
+use archer::ai::conversation::search::tokenize;
 use archer::ai::conversation::{Conversation, ConversationManager};
 use async_channel::Sender;
 
 use crate::config::{Config, KeyBindings};
-use archer::ai::completion::{CompletionStatus, Message as CompletionMessage, MessageRole};
+use archer::ai::attachments::split_attachments;
+use archer::ai::completion::{
+    CompletionError, CompletionStatus, Message as CompletionMessage, MessageRole,
+};
+use archer::ai::usage::CompletionUsage;
 
 lazy_static! {
     static ref WHITESPACE_RE: Regex = Regex::new(r"\s*[^\s]+").unwrap();
 }
 
+/// Summarise a completion's token counts and timings, for its title line.
+fn format_usage(usage: &CompletionUsage) -> Option<String> {
+    let mut parts = Vec::new();
+    if usage.prompt_tokens.is_some() || usage.completion_tokens.is_some() {
+        parts.push(format!(
+            "{} in / {} out",
+            usage.prompt_tokens.unwrap_or_default(),
+            usage.completion_tokens.unwrap_or_default()
+        ));
+    }
+    if let Some(time_to_first_token_ms) = usage.time_to_first_token_ms {
+        parts.push(format!(
+            "first token {:.1}s",
+            time_to_first_token_ms as f64 / 1000.0
+        ));
+    }
+    if let Some(latency_ms) = usage.latency_ms {
+        parts.push(format!("total {:.1}s", latency_ms as f64 / 1000.0));
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!(" | {}", parts.join(" | ")))
+    }
+}
+
+/// Replies from several models to the same message, shown side by side until one is kept.
+struct Comparison {
+    responses: Vec<(Uuid, CompletionMessage)>,
+    selected: usize,
+    /// Lines scrolled past in every reply, so they can be read alongside each other.
+    scroll: usize,
+}
+
+impl Comparison {
+    fn is_finished(&self) -> bool {
+        self.responses.iter().all(|(_, message)| {
+            !matches!(
+                message.metadata.as_ref().map(|metadata| &metadata.status),
+                Some(
+                    CompletionStatus::Starting
+                        | CompletionStatus::Retrying { .. }
+                        | CompletionStatus::Processing
+                )
+            )
+        })
+    }
+}
+
 #[derive(Clone, Default)]
 enum ViewerState {
     Active,
@@ -49,6 +109,17 @@ pub struct Viewer {
     sticky_scroll: bool,
     visible_height: usize,
     scrollable: bool,
+    /// The message selected when last drawn, which tool calls are toggled for.
+    selected_uuid: Option<Uuid>,
+    /// Tool messages are collapsed to their call, unless expanded to show the result.
+    expanded_tool_calls: HashSet<Uuid>,
+    /// Rendered markdown for each message, with a hash of the content and width it was rendered
+    /// at, as rendering and highlighting every message on each frame is too slow.
+    markdown_cache: HashMap<Uuid, (u64, Vec<Line<'static>>)>,
+    /// The code blocks in the selected message when last drawn, and which of them is selected.
+    code_blocks: Vec<CodeBlock>,
+    selected_code_block: Option<usize>,
+    comparison: Option<Comparison>,
 }
 
 impl Viewer {
@@ -58,15 +129,27 @@ impl Viewer {
         }
     }
 
-    pub fn get_title_line<'a>(&self, message: &CompletionMessage, width: usize) -> Line<'a> {
+    pub fn get_title_line<'a>(
+        &self,
+        message: &CompletionMessage,
+        branch_position: Option<(usize, usize)>,
+        width: usize,
+    ) -> Line<'a> {
         let mut title_spans = Vec::new();
-        match message.role {
+        match &message.role {
             MessageRole::System => title_spans.push((
                 " System".to_string(),
                 Style::default().fg(SYSTEM_COLOR).bold(),
             )),
             MessageRole::User => {
-                title_spans.push((" User".to_string(), Style::default().fg(USER_COLOR).bold()))
+                title_spans.push((" User".to_string(), Style::default().fg(USER_COLOR).bold()));
+
+                if let Some((position, total)) = branch_position {
+                    title_spans.push((
+                        format!(" ({position}/{total})"),
+                        Style::default().fg(UNFOCUSED_COLOR),
+                    ));
+                }
             }
             MessageRole::Assistant => {
                 title_spans.push((
@@ -74,20 +157,51 @@ impl Viewer {
                     Style::default().fg(ASSISTANT_COLOR).bold(),
                 ));
 
-                title_spans.push((
-                    format!(
-                        ": {}",
-                        message.clone().metadata.unwrap().model_config.model_id
-                    ),
-                    Style::default().fg(ASSISTANT_COLOR),
-                ));
+                // Responses from a fallback are titled with the model which answered
+                let metadata = message.metadata.as_ref().unwrap();
+                match &metadata.fallback {
+                    Some(fallback) => {
+                        title_spans.push((
+                            format!(": {}", fallback.model_id),
+                            Style::default().fg(ASSISTANT_COLOR),
+                        ));
+                        title_spans.push((
+                            format!(" (fallback for {})", metadata.model_config.model_id),
+                            Style::default().fg(UNFOCUSED_COLOR),
+                        ));
+                    }
+                    None => title_spans.push((
+                        format!(": {}", metadata.model_config.model_id),
+                        Style::default().fg(ASSISTANT_COLOR),
+                    )),
+                }
+
+                if let Some((position, total)) = branch_position {
+                    title_spans.push((
+                        format!(" ({position}/{total})"),
+                        Style::default().fg(UNFOCUSED_COLOR),
+                    ));
+                }
+
+                if let Some(usage) = format_usage(&message.metadata.as_ref().unwrap().usage) {
+                    title_spans.push((usage, Style::default().fg(UNFOCUSED_COLOR)));
+                }
 
                 let (status_str, color) = match message.metadata.as_ref().unwrap().status {
-                    CompletionStatus::Starting => (" Starting...", Color::LightBlue),
-                    CompletionStatus::Processing => (" Processing...", Color::LightGreen),
-                    CompletionStatus::Succeeded => (" Succeeded", Color::LightGreen),
-                    CompletionStatus::Failed => (" Failed", Color::LightRed),
-                    CompletionStatus::Canceled => (" Canceled", Color::LightRed),
+                    CompletionStatus::Starting => (" Starting...".to_string(), Color::LightBlue),
+                    CompletionStatus::Retrying {
+                        attempt,
+                        max_retries,
+                    } => (
+                        format!(" Retrying ({attempt}/{max_retries})..."),
+                        Color::LightYellow,
+                    ),
+                    CompletionStatus::Processing => {
+                        (" Processing...".to_string(), Color::LightGreen)
+                    }
+                    CompletionStatus::Succeeded => (" Succeeded".to_string(), Color::LightGreen),
+                    CompletionStatus::Failed => (" Failed".to_string(), Color::LightRed),
+                    CompletionStatus::Canceled => (" Canceled".to_string(), Color::LightRed),
                 };
 
                 let total_span_chars: usize = title_spans
@@ -104,7 +218,34 @@ impl Viewer {
                 }
 
                 title_spans.push((pad, Style::default()));
-                title_spans.push((status_str.to_string(), Style::default().fg(color)));
+                title_spans.push((status_str, Style::default().fg(color)));
+            }
+            MessageRole::Tool(call) => {
+                title_spans.push((" Tool".to_string(), Style::default().fg(TOOL_COLOR).bold()));
+                title_spans.push((format!(": {}", call.name), Style::default().fg(TOOL_COLOR)));
+
+                // Show as much of the arguments as fits on the line
+                let used_chars = title_spans
+                    .iter()
+                    .map(|(span, _)| span.chars().count())
+                    .sum::<usize>()
+                    + 4;
+                let arguments = call.arguments.to_string();
+                let available = width.max(used_chars) - used_chars;
+                let arguments = if arguments.chars().count() > available {
+                    let mut arguments = arguments
+                        .chars()
+                        .take(available.max(1) - 1)
+                        .collect::<String>();
+                    arguments.push('…');
+                    arguments
+                } else {
+                    arguments
+                };
+                title_spans.push((
+                    format!(" {arguments}"),
+                    Style::default().fg(UNFOCUSED_COLOR),
+                ));
             }
         }
 
@@ -116,7 +257,12 @@ impl Viewer {
         )
     }
 
-    pub fn get_lines_from_content<'a>(&self, content: &'a str, width: usize) -> Vec<Line<'a>> {
+    pub fn get_lines_from_content<'a>(
+        &self,
+        content: &'a str,
+        width: usize,
+        search_terms: &[String],
+    ) -> Vec<Line<'a>> {
         let visible_width = width.max(4) - 4;
         let mut lines = vec![Line::styled("", Style::default())];
 
@@ -136,10 +282,95 @@ impl Viewer {
                     sub = format!(" {sub}");
                 }
 
-                lines.push(Line::styled(sub, Style::default().fg(Color::White)));
+                lines.push(highlight_search_terms(sub, search_terms));
+            }
+        }
+
+        lines
+    }
+
+    /// An assistant message rendered as markdown.
+    pub fn get_markdown_lines(
+        &mut self,
+        uuid: &Uuid,
+        content: &str,
+        width: usize,
+        search_terms: &[String],
+    ) -> Vec<Line<'static>> {
+        let selected_code_block = match self.selected_uuid {
+            Some(selected_uuid) if selected_uuid == *uuid => self.selected_code_block,
+            _ => None,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        (content, width, selected_code_block).hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let rendered = match self.markdown_cache.get(uuid) {
+            Some((cached_hash, lines)) if *cached_hash == hash => lines.clone(),
+            _ => {
+                let lines = render_markdown(content, width.max(4) - 3, selected_code_block);
+                self.markdown_cache.insert(*uuid, (hash, lines.clone()));
+                lines
             }
+        };
+
+        let mut lines = vec![Line::styled("", Style::default())];
+        lines.extend(
+            rendered
+                .into_iter()
+                .map(|line| highlight_line_search_terms(line, search_terms)),
+        );
+        lines
+    }
+
+    /// The result of a tool call, or a summary of it if collapsed.
+    pub fn get_tool_result_lines<'a>(
+        &self,
+        uuid: &Uuid,
+        content: &'a str,
+        width: usize,
+        search_terms: &[String],
+    ) -> Vec<Line<'a>> {
+        if !self.expanded_tool_calls.contains(uuid) {
+            let line_count = content.trim().lines().count();
+            return vec![
+                Line::styled("", Style::default()),
+                Line::styled(
+                    format!(" ▸ {line_count} lines of output"),
+                    Style::default().fg(UNFOCUSED_COLOR),
+                ),
+            ];
         }
 
+        let mut lines = vec![
+            Line::styled("", Style::default()),
+            Line::styled(" ▾ Output", Style::default().fg(UNFOCUSED_COLOR)),
+        ];
+        lines.extend(self.get_lines_from_content(content, width, search_terms));
+        lines
+    }
+
+    /// A message's text, followed by a line for each attached file in place of its contents.
+    pub fn get_user_message_lines<'a>(
+        &self,
+        content: &'a str,
+        width: usize,
+        search_terms: &[String],
+    ) -> Vec<Line<'a>> {
+        let (text, attached) = split_attachments(content);
+        let mut lines = self.get_lines_from_content(text, width, search_terms);
+        for file in attached {
+            lines.push(Line::styled(
+                format!(
+                    " ▪ {} ({} lines{})",
+                    file.path,
+                    file.lines,
+                    if file.truncated { ", truncated" } else { "" }
+                ),
+                Style::default().fg(UNFOCUSED_COLOR),
+            ));
+        }
         lines
     }
 
@@ -156,24 +387,47 @@ impl Viewer {
         &'a mut self,
         conversation: &'a Conversation,
         width: usize,
+        search_terms: &[String],
     ) -> VisibleMessages {
         let mut messages = Vec::new();
-        for (id, message) in &conversation.messages {
+        let mut rendered = HashSet::new();
+        for (id, message) in conversation.get_messages() {
             if message.role == MessageRole::System {
                 continue;
             }
 
-            let mut lines = vec![self.get_title_line(&message, width)];
+            let branch_position = conversation.get_branch_position(&id);
+            let mut lines = vec![self.get_title_line(message, branch_position, width)];
             let content = message.content.trim();
-            lines.extend(self.get_lines_from_content(content, width));
+            match message.role {
+                MessageRole::Tool(_) => {
+                    lines.extend(self.get_tool_result_lines(&id, content, width, search_terms))
+                }
+                MessageRole::User => {
+                    lines.extend(self.get_user_message_lines(content, width, search_terms))
+                }
+                MessageRole::Assistant => {
+                    rendered.insert(id);
+                    lines.extend(self.get_markdown_lines(&id, content, width, search_terms));
+                    if let Some(error) = message.metadata.as_ref().and_then(|x| x.error.as_ref()) {
+                        lines.extend(get_error_lines(error, width));
+                    }
+                }
+                _ => lines.extend(self.get_lines_from_content(content, width, search_terms)),
+            }
 
             messages.push(VisibleMessage {
                 lines,
                 role: message.role.clone(),
-                uuid: id.clone(),
+                uuid: id,
             });
         }
 
+        if let Some(comparison) = &self.comparison {
+            rendered.extend(comparison.responses.iter().map(|(id, _)| *id));
+        }
+        self.markdown_cache.retain(|id, _| rendered.contains(id));
+
         let messages = VisibleMessages {
             messages: messages.clone(),
         };
@@ -187,6 +441,66 @@ impl Viewer {
 
         messages
     }
+
+    /// The replies being compared, side by side in the order the models were chosen.
+    fn draw_comparison(&mut self, f: &mut Frame<'_>, rect: Rect) {
+        let Some(comparison) = &self.comparison else {
+            return;
+        };
+        let responses = comparison.responses.clone();
+        let selected = comparison.selected;
+
+        let panels = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                responses
+                    .iter()
+                    .map(|_| Constraint::Ratio(1, responses.len() as u32))
+                    .collect::<Vec<Constraint>>(),
+            )
+            .split(rect);
+
+        let width = |panel: Rect| panel.width.max(3) as usize - 2;
+        let mut response_lines = Vec::new();
+        for (idx, (uuid, message)) in responses.iter().enumerate() {
+            let mut lines =
+                self.get_markdown_lines(uuid, message.content.trim(), width(panels[idx]), &[]);
+            if let Some(error) = message.metadata.as_ref().and_then(|x| x.error.as_ref()) {
+                lines.extend(get_error_lines(error, width(panels[idx])));
+            }
+            response_lines.push(lines);
+        }
+
+        // Scrolling stops once the last line of the longest reply is reached
+        let longest = response_lines.iter().map(|lines| lines.len()).max();
+        let scroll = match self.comparison.as_mut() {
+            Some(comparison) => {
+                comparison.scroll = comparison
+                    .scroll
+                    .min(longest.unwrap_or_default().saturating_sub(1));
+                comparison.scroll
+            }
+            None => 0,
+        };
+
+        for (idx, ((_, message), lines)) in responses.iter().zip(response_lines).enumerate() {
+            let panel = panels[idx];
+            let color = if idx == selected {
+                ACTIVE_COLOR
+            } else {
+                UNFOCUSED_COLOR
+            };
+            let paragraph = Paragraph::new(lines).scroll((scroll as u16, 0)).block(
+                Block::default()
+                    .title(self.get_title_line(message, None, width(panel)))
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(color))
+                    .style(Style::default().bg(Color::Black)),
+            );
+            f.render_widget(paragraph, panel);
+        }
+    }
 }
 
 impl Component for Viewer {
@@ -212,10 +526,24 @@ impl Component for Viewer {
                 Mode::ModelSelector => {
                     self.state = ViewerState::Unfocused;
                 }
-                Mode::ActiveInput | Mode::ConversationManager => {
+                // The reply selected while comparing is highlighted instead of the viewer
+                Mode::ActiveInput
+                | Mode::ConversationManager
+                | Mode::ConversationSearch
+                | Mode::Compare => {
                     self.state = ViewerState::Unfocused;
                 }
             },
+            Action::ScrollUp if self.comparison.is_some() => {
+                if let Some(comparison) = self.comparison.as_mut() {
+                    comparison.scroll = comparison.scroll.saturating_sub(1);
+                }
+            }
+            Action::ScrollDown if self.comparison.is_some() => {
+                if let Some(comparison) = self.comparison.as_mut() {
+                    comparison.scroll += 1;
+                }
+            }
             Action::ScrollUp => {
                 if self.scrollable {
                     if self.visible_end > self.visible_height {
@@ -235,9 +563,79 @@ impl Component for Viewer {
                 }
                 self.sticky_scroll = false;
             }
-            Action::ReceiveMessage(..)
-            | Action::StreamMessage(..)
-            | Action::LoadSelectedConversation => {
+            Action::SelectNextCodeBlock => {
+                self.selected_code_block = match self.selected_code_block {
+                    _ if self.code_blocks.is_empty() => None,
+                    Some(idx) => Some((idx + 1) % self.code_blocks.len()),
+                    None => Some(0),
+                };
+            }
+            Action::CopySelectedCodeBlock | Action::SaveSelectedCodeBlock => {
+                // Without a selection, the first code block is used
+                let idx = self.selected_code_block.unwrap_or(0);
+                if let Some(code_block) = self.code_blocks.get(idx) {
+                    self.selected_code_block = Some(idx);
+                    return Ok(Some(match action {
+                        Action::CopySelectedCodeBlock => Action::CopyText(code_block.code.clone()),
+                        _ => Action::SaveCodeBlock(code_block.clone()),
+                    }));
+                }
+            }
+            Action::ToggleSelectedToolCall => {
+                if let Some(uuid) = self.selected_uuid {
+                    if !self.expanded_tool_calls.remove(&uuid) {
+                        self.expanded_tool_calls.insert(uuid);
+                    }
+                }
+            }
+            Action::StartComparison(responses) => {
+                self.comparison = Some(Comparison {
+                    responses,
+                    selected: 0,
+                    scroll: 0,
+                });
+            }
+            Action::SelectNextResponse | Action::SelectPreviousResponse => {
+                if let Some(comparison) = self.comparison.as_mut() {
+                    let count = comparison.responses.len();
+                    comparison.selected = match action {
+                        Action::SelectNextResponse => (comparison.selected + 1) % count,
+                        _ => (comparison.selected + count - 1) % count,
+                    };
+                }
+            }
+            Action::KeepSelectedResponse => {
+                if let Some(comparison) = &self.comparison {
+                    if !comparison.is_finished() {
+                        return Ok(Some(Action::Error(
+                            "wait for the replies to finish, or cancel them with ctrl+x"
+                                .to_string(),
+                        )));
+                    }
+                }
+                if let Some(mut comparison) = self.comparison.take() {
+                    let (uuid, message) = comparison.responses.swap_remove(comparison.selected);
+                    self.sticky_scroll = true;
+                    return Ok(Some(Action::KeepResponse(uuid, message)));
+                }
+            }
+            Action::DiscardComparison => {
+                self.comparison = None;
+                self.sticky_scroll = true;
+            }
+            Action::StreamMessage(uuid, message) => {
+                // Replies being compared are not in the conversation until one is kept
+                if let Some(response) = self.comparison.as_mut().and_then(|comparison| {
+                    comparison
+                        .responses
+                        .iter_mut()
+                        .find(|(response_uuid, _)| *response_uuid == uuid)
+                }) {
+                    response.1 = message;
+                }
+                self.sticky_scroll = true;
+            }
+            Action::ReceiveMessage(..) | Action::LoadSelectedConversation => {
                 self.sticky_scroll = true;
             }
             _ => {}
@@ -252,9 +650,18 @@ impl Component for Viewer {
         conversation: &Conversation,
         manager: &ConversationManager,
     ) -> Result<()> {
+        let conversation_usage = conversation.get_usage_totals();
+        let daily_usage = manager.get_daily_usage();
+        let usage_title = format!(
+            " Conversation: {} tokens | Today: {} tokens ",
+            conversation_usage.prompt_tokens + conversation_usage.completion_tokens,
+            daily_usage.prompt_tokens + daily_usage.completion_tokens
+        );
+
         let block = Block::default()
             .title("Viewer")
             .title_alignment(Alignment::Left)
+            .title(Title::from(usage_title).alignment(Alignment::Right))
             .borders(Borders::ALL)
             .border_type(BorderType::Thick)
             .style(
@@ -267,23 +674,51 @@ impl Component for Viewer {
             );
 
         f.render_widget(block.clone(), rect);
-        let inner = rect.inner(&Margin {
+        let mut inner = rect.inner(&Margin {
             vertical: 1,
             horizontal: 1,
         });
 
-        self.visible_height = (inner.height - 1) as usize;
+        // While comparing, the conversation is shortened to make room for the replies
+        if self.comparison.is_some() {
+            let panels = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![Constraint::Percentage(35), Constraint::Percentage(65)])
+                .split(inner);
+            inner = panels[0];
+            self.draw_comparison(f, panels[1]);
+        }
+
+        // On short terminals, the replies can leave no room for the conversation
+        self.visible_height = inner.height.saturating_sub(1) as usize;
+        if inner.height == 0 {
+            return Ok(());
+        }
         let message_width = (inner.width.min(105) - 1) as usize;
 
         let selected_uuid = match self.state {
             ViewerState::Active => conversation.get_selected_uuid(),
             _ => None,
         };
+        if selected_uuid != self.selected_uuid {
+            self.selected_code_block = None;
+        }
+        self.selected_uuid = selected_uuid;
+        self.code_blocks = selected_uuid
+            .and_then(|uuid| conversation.nodes.get(&uuid))
+            .filter(|node| node.message.role == MessageRole::Assistant)
+            .map(|node| extract_code_blocks(&node.message.content))
+            .unwrap_or_default();
 
         let state = self.state.clone();
         let visible_height = self.visible_height.clone();
         let (mut visible_start, visible_end) = self.get_visible_ranges();
-        let messages = self.get_visible_messages(conversation, message_width);
+        // Highlight the search terms, if this conversation was found by searching
+        let search_terms = match manager.get_search_matches(&conversation.id) {
+            Some(_) => tokenize(manager.get_search_query()).collect::<Vec<String>>(),
+            None => Vec::new(),
+        };
+        let messages = self.get_visible_messages(conversation, message_width, &search_terms);
         let total_len = messages.total_len();
 
         let (visible_start, visible_end) = match state {
@@ -328,6 +763,57 @@ impl Component for Viewer {
     }
 }
 
+/// Style the words in a line which start with any of the search terms.
+fn highlight_search_terms<'a>(line: String, search_terms: &[String]) -> Line<'a> {
+    let style = Style::default().fg(Color::White);
+    highlight_line_search_terms(Line::styled(line, style), search_terms)
+}
+
+/// Highlight search terms across the spans of an already styled line.
+fn highlight_line_search_terms<'a>(line: Line<'a>, search_terms: &[String]) -> Line<'a> {
+    if search_terms.is_empty() {
+        return line;
+    }
+
+    let highlight_style = Style::default().fg(Color::Black).bg(ACTIVE_COLOR);
+    let mut spans = Vec::<Span>::new();
+
+    for span in line.spans {
+        let style = span.style;
+        let mut current = String::new();
+        let mut in_word = false;
+
+        // Split the span into alternating runs of word and non word characters
+        let push_run = |run: &mut String, is_word: bool, spans: &mut Vec<Span>| {
+            if run.is_empty() {
+                return;
+            }
+
+            let word = run.to_lowercase();
+            let matched = is_word && search_terms.iter().any(|term| word.starts_with(term));
+            spans.push(Span::styled(
+                std::mem::take(run),
+                if matched {
+                    style.patch(highlight_style)
+                } else {
+                    style
+                },
+            ));
+        };
+
+        for c in span.content.chars() {
+            if c.is_alphanumeric() != in_word {
+                push_run(&mut current, in_word, &mut spans);
+                in_word = c.is_alphanumeric();
+            }
+            current.push(c);
+        }
+        push_run(&mut current, in_word, &mut spans);
+    }
+
+    Line::from(spans)
+}
+
 #[derive(Clone)]
 struct VisibleMessage<'a> {
     lines: Vec<Line<'a>>,
@@ -434,15 +920,20 @@ impl<'a> VisibleMessages<'a> {
                 FOCUSED_COLOR
             };
 
+            // Tool calls are set apart from the messages around them
+            let border_type = match message.role {
+                MessageRole::Tool(_) => BorderType::Plain,
+                _ => BorderType::Rounded,
+            };
             let block = Block::default()
                 .borders(borders)
-                .border_type(BorderType::Rounded)
+                .border_type(border_type)
                 .border_style(Style::default().fg(message_color));
             let paragraph = Paragraph::new(Text::from(message_lines)).block(block);
 
             let height = (message_len + border_height) as u16;
             let x = match message.role {
-                MessageRole::Assistant => rect.width - width,
+                MessageRole::Assistant | MessageRole::Tool(_) => rect.width - width,
                 _ => rect.x,
             };
 
@@ -460,6 +951,19 @@ impl<'a> VisibleMessages<'a> {
     }
 }
 
+/// The reason a response failed, shown below any content it streamed before failing.
+fn get_error_lines(error: &CompletionError, width: usize) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::default()];
+    let message = format!("✗ {error}");
+    for line in textwrap::wrap(&message, width.max(4) - 4) {
+        lines.push(Line::styled(
+            format!(" {line}"),
+            Style::default().fg(Color::LightRed),
+        ));
+    }
+    lines
+}
+
 fn split_sentence(line: &str) -> Box<dyn Iterator<Item = Word<'_>> + '_> {
     let words = WHITESPACE_RE
         .find_iter(line)
@@ -488,4 +992,17 @@ mod tests {
             vec!["    this", " is", " a", " sentence"]
         );
     }
+
+    #[test]
+    fn test_highlight_search_terms() {
+        let line =
+            highlight_search_terms(" Borrowed, not borrow".to_string(), &["borrow".to_string()]);
+        let highlighted = line
+            .spans
+            .iter()
+            .filter(|span| span.style.bg == Some(ACTIVE_COLOR))
+            .map(|span| span.content.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(highlighted, vec!["Borrowed", "borrow"]);
+    }
 }
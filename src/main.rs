@@ -5,15 +5,19 @@
 pub mod action;
 pub mod app;
 pub mod cli;
+pub mod commands;
 pub mod components;
 pub mod config;
+pub mod markdown;
 pub mod mode;
 pub mod styles;
 pub mod tui;
 pub mod utils;
 
+use archer::ai::config::load_config;
+use archer::ai::providers::COMPLETION_PROVIDERS;
 use clap::Parser;
-use cli::Cli;
+use cli::{Cli, Command};
 use color_eyre::eyre::Result;
 use replicate_rs::config::ReplicateConfig;
 
@@ -29,7 +33,18 @@ async fn tokio_main() -> anyhow::Result<()> {
 
     let args = Cli::parse();
 
-    if std::env::var("REPLICATE_API_KEY").is_ok() || std::env::var("TOGETHER_API_KEY").is_ok() {
+    // Surface malformed user config before the terminal is taken over
+    load_config()?;
+
+    if let Some(command) = args.command {
+        return match command {
+            Command::Ask(ask_args) => commands::ask(ask_args).await,
+            Command::Export(export_args) => commands::export(export_args),
+            Command::Index(index_args) => commands::index(index_args).await,
+        };
+    }
+
+    if COMPLETION_PROVIDERS.has_any_credentials() {
         let mut app = App::new(args.tick_rate, args.frame_rate)?;
         app.run().await?;
     }
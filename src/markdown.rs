@@ -0,0 +1,708 @@
+use lazy_static::lazy_static;
+use pulldown_cmark::{
+    Alignment as ColumnAlignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag,
+};
+use ratatui::prelude::*;
+use serde::Serialize;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::styles::{
+    ACTIVE_COLOR, CODE_COLOR, HEADING_COLOR, LINK_COLOR, QUOTE_COLOR, UNFOCUSED_COLOR,
+};
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME: Theme = ThemeSet::load_defaults()
+        .themes
+        .remove("base16-eighties.dark")
+        .unwrap_or_default();
+}
+
+const TAB_WIDTH: usize = 4;
+/// Starts the rest of a line of code which is too wide for the viewer.
+const CONTINUATION_MARKER: char = '↪';
+
+/// A code block from a message, which can be copied or saved on its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CodeBlock {
+    pub language: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// The file extension for the block's language, ie. `rs` for `rust`.
+    pub fn extension(&self) -> String {
+        SYNTAX_SET
+            .find_syntax_by_token(&self.language)
+            .and_then(|syntax| syntax.file_extensions.first())
+            .cloned()
+            .unwrap_or("txt".to_string())
+    }
+}
+
+fn parse(content: &str) -> Parser<'_, '_> {
+    Parser::new_ext(
+        content,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+}
+
+fn get_language(kind: CodeBlockKind) -> String {
+    match kind {
+        CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+        CodeBlockKind::Indented => String::new(),
+    }
+}
+
+/// Render markdown as styled lines, wrapped to `width`.
+///
+/// Code blocks are syntax highlighted when their language is known, and long lines are hard
+/// wrapped at the width, continuing at their indentation after a `↪` marker. The fences of the
+/// `selected_code_block` are highlighted.
+pub fn render_markdown(
+    content: &str,
+    width: usize,
+    selected_code_block: Option<usize>,
+) -> Vec<Line<'static>> {
+    let mut renderer = Renderer {
+        width: width.max(8),
+        selected_code_block,
+        ..Default::default()
+    };
+
+    for event in parse(content) {
+        renderer.handle_event(event);
+    }
+    renderer.flush();
+
+    renderer.lines
+}
+
+/// The code blocks in a message, in the order they are rendered.
+pub fn extract_code_blocks(content: &str) -> Vec<CodeBlock> {
+    let mut code_blocks = Vec::new();
+    let mut current = None;
+
+    for event in parse(content) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                current = Some(CodeBlock {
+                    language: get_language(kind),
+                    code: String::new(),
+                })
+            }
+            Event::Text(text) => {
+                if let Some(code_block) = current.as_mut() {
+                    code_block.code.push_str(&text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => code_blocks.extend(current.take()),
+            _ => {}
+        }
+    }
+
+    code_blocks
+}
+
+/// A table being collected, as it can only be laid out once every cell is known.
+#[derive(Default)]
+struct Table {
+    alignments: Vec<ColumnAlignment>,
+    rows: Vec<Vec<String>>,
+    header_rows: usize,
+}
+
+#[derive(Default)]
+struct Renderer {
+    width: usize,
+    lines: Vec<Line<'static>>,
+    /// The inline content of the current block, waiting to be wrapped.
+    spans: Vec<Span<'static>>,
+    styles: Vec<Style>,
+    /// The next number of each ordered list, or `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    /// The bullet or number starting the current list item, until its first line is written.
+    item_marker: Option<String>,
+    quote_depth: usize,
+    code_block: Option<(String, String)>,
+    code_block_count: usize,
+    selected_code_block: Option<usize>,
+    table: Option<Table>,
+    links: Vec<String>,
+}
+
+impl Renderer {
+    fn style(&self) -> Style {
+        self.styles
+            .last()
+            .copied()
+            .unwrap_or(Style::default().fg(Color::White))
+    }
+
+    fn push_style(&mut self, style: Style) {
+        self.styles.push(self.style().patch(style));
+    }
+
+    /// The prefixes for the first and following lines of a block, from enclosing quotes and lists.
+    fn prefixes(&mut self) -> (Span<'static>, Span<'static>) {
+        let mut prefix = " ".to_string();
+        prefix.push_str(&"│ ".repeat(self.quote_depth));
+        prefix.push_str(&"   ".repeat(self.lists.len().max(1) - 1));
+
+        let style = Style::default().fg(QUOTE_COLOR);
+        match self.item_marker.take() {
+            Some(marker) => {
+                let rest = format!("{prefix}{}", " ".repeat(marker.chars().count()));
+                (
+                    Span::styled(format!("{prefix}{marker}"), style),
+                    Span::styled(rest, style),
+                )
+            }
+            None => {
+                let indent = if self.lists.is_empty() { "" } else { "   " };
+                let prefix = format!("{prefix}{indent}");
+                (
+                    Span::styled(prefix.clone(), style),
+                    Span::styled(prefix, style),
+                )
+            }
+        }
+    }
+
+    /// Separate blocks with an empty line, except within lists.
+    fn start_block(&mut self) {
+        self.flush();
+        if !self.lists.is_empty() {
+            return;
+        }
+        let is_blank = |line: &Line| line.spans.iter().all(|span| span.content.trim().is_empty());
+        if self.lines.last().is_some_and(|line| !is_blank(line)) {
+            let (prefix, _) = self.prefixes();
+            self.lines.push(Line::from(prefix));
+        }
+    }
+
+    /// Wrap the pending inline content into lines.
+    fn flush(&mut self) {
+        if self.spans.is_empty() {
+            return;
+        }
+
+        let spans = std::mem::take(&mut self.spans);
+        let (first_prefix, rest_prefix) = self.prefixes();
+        let wrapped = wrap_spans(spans, self.width, first_prefix, rest_prefix);
+        self.lines.extend(wrapped);
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if let Some((_, code)) = self.code_block.as_mut() {
+            code.push_str(text);
+        } else if let Some(row) = self.table.as_mut().and_then(|table| table.rows.last_mut()) {
+            if let Some(cell) = row.last_mut() {
+                cell.push_str(text);
+            }
+        } else {
+            let style = self.style();
+            self.spans.push(Span::styled(text.to_string(), style));
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start_tag(tag),
+            Event::End(tag) => self.end_tag(tag),
+            Event::Text(text) | Event::Html(text) => self.push_text(&text),
+            Event::Code(code) => {
+                if self.table.is_some() {
+                    self.push_text(&code);
+                } else {
+                    let style = self.style().fg(CODE_COLOR);
+                    self.spans.push(Span::styled(code.to_string(), style));
+                }
+            }
+            Event::SoftBreak => self.push_text(" "),
+            Event::HardBreak => self.flush(),
+            Event::Rule => {
+                self.start_block();
+                let (prefix, _) = self.prefixes();
+                let rule_width = self.width.saturating_sub(prefix.width() + 1);
+                self.lines.push(Line::from(vec![
+                    prefix,
+                    Span::styled("─".repeat(rule_width), Style::default().fg(UNFOCUSED_COLOR)),
+                ]));
+            }
+            Event::TaskListMarker(checked) => {
+                self.push_text(if checked { "[x] " } else { "[ ] " });
+            }
+            Event::FootnoteReference(_) => {}
+        }
+    }
+
+    fn start_tag(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading(level, ..) => {
+                self.start_block();
+                let mut style = Style::default().fg(HEADING_COLOR).bold();
+                if level == HeadingLevel::H1 {
+                    style = style.underlined();
+                }
+                self.push_style(style);
+                let hashes = "#".repeat(level as usize);
+                self.push_text(&format!("{hashes} "));
+            }
+            Tag::BlockQuote => {
+                self.start_block();
+                self.quote_depth += 1;
+                self.push_style(Style::default().fg(QUOTE_COLOR).italic());
+            }
+            Tag::CodeBlock(kind) => {
+                self.start_block();
+                self.code_block = Some((get_language(kind), String::new()));
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else {
+                    self.flush();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.item_marker = Some(marker);
+            }
+            Tag::Table(alignments) => {
+                self.start_block();
+                self.table = Some(Table {
+                    alignments,
+                    ..Default::default()
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => {
+                if let Some(row) = self.table.as_mut().and_then(|table| table.rows.last_mut()) {
+                    row.push(String::new());
+                }
+            }
+            Tag::Emphasis => self.push_style(Style::default().italic()),
+            Tag::Strong => self.push_style(Style::default().bold()),
+            Tag::Strikethrough => self.push_style(Style::default().crossed_out()),
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => {
+                self.links.push(url.to_string());
+                self.push_style(Style::default().fg(LINK_COLOR).underlined());
+            }
+            Tag::FootnoteDefinition(_) => {}
+        }
+    }
+
+    fn end_tag(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.flush(),
+            Tag::Heading(..) => {
+                self.styles.pop();
+                self.flush();
+            }
+            Tag::BlockQuote => {
+                self.flush();
+                self.styles.pop();
+                self.quote_depth -= 1;
+            }
+            Tag::CodeBlock(_) => {
+                if let Some((language, code)) = self.code_block.take() {
+                    self.render_code_block(&language, &code);
+                }
+            }
+            Tag::List(_) => {
+                self.flush();
+                self.lists.pop();
+            }
+            Tag::Item => self.flush(),
+            Tag::Table(_) => {
+                if let Some(table) = self.table.take() {
+                    self.render_table(table);
+                }
+            }
+            Tag::TableHead => {
+                if let Some(table) = self.table.as_mut() {
+                    table.header_rows = table.rows.len();
+                }
+            }
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough => {
+                self.styles.pop();
+            }
+            Tag::Link(..) | Tag::Image(..) => {
+                self.styles.pop();
+                // Show where links go, unless the text is already the url
+                if let Some(url) = self.links.pop() {
+                    let shown = self
+                        .spans
+                        .last()
+                        .is_some_and(|span| span.content.as_ref() == url);
+                    if !shown && !url.is_empty() {
+                        self.spans.push(Span::styled(
+                            format!(" ({url})"),
+                            Style::default().fg(UNFOCUSED_COLOR),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn render_code_block(&mut self, language: &str, code: &str) {
+        let fence_style = if self.selected_code_block == Some(self.code_block_count) {
+            Style::default().fg(ACTIVE_COLOR).bold()
+        } else {
+            Style::default().fg(UNFOCUSED_COLOR)
+        };
+        self.code_block_count += 1;
+        let (prefix, _) = self.prefixes();
+        let available = self.width.saturating_sub(prefix.width() + 1);
+        self.lines.push(Line::from(vec![
+            prefix.clone(),
+            Span::styled(format!("```{language}"), fence_style),
+        ]));
+
+        let syntax = SYNTAX_SET.find_syntax_by_token(language);
+        let mut highlighter = syntax.map(|syntax| HighlightLines::new(syntax, &THEME));
+        for line in LinesWithEndings::from(code) {
+            let line = line.replace('\t', &" ".repeat(TAB_WIDTH));
+            let spans = match highlighter
+                .as_mut()
+                .and_then(|highlighter| highlighter.highlight_line(&line, &SYNTAX_SET).ok())
+            {
+                Some(ranges) => ranges
+                    .into_iter()
+                    .map(|(style, text)| {
+                        let color =
+                            Color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b);
+                        Span::styled(
+                            text.trim_end_matches('\n').to_string(),
+                            Style::default().fg(color),
+                        )
+                    })
+                    .collect(),
+                None => vec![Span::styled(
+                    line.trim_end_matches('\n').to_string(),
+                    Style::default().fg(CODE_COLOR),
+                )],
+            };
+
+            for spans in hard_wrap_spans(spans, available) {
+                let mut code_line = vec![prefix.clone(), Span::raw(" ")];
+                code_line.extend(spans);
+                self.lines.push(Line::from(code_line));
+            }
+        }
+
+        self.lines
+            .push(Line::from(vec![prefix, Span::styled("```", fence_style)]));
+    }
+
+    fn render_table(&mut self, table: Table) {
+        let columns = table.rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+
+        let (prefix, _) = self.prefixes();
+        let mut widths = vec![1; columns];
+        for row in &table.rows {
+            for (idx, cell) in row.iter().enumerate() {
+                widths[idx] = widths[idx].max(cell.trim().chars().count());
+            }
+        }
+
+        // Shrink the widest columns until the table fits
+        let available = self
+            .width
+            .saturating_sub(prefix.width() + 1 + 3 * (columns - 1));
+        while widths.iter().sum::<usize>() > available {
+            let Some((widest, width)) = widths.iter().enumerate().max_by_key(|(_, width)| **width)
+            else {
+                break;
+            };
+            if *width <= 3 {
+                break;
+            }
+            widths[widest] -= 1;
+        }
+
+        let border_style = Style::default().fg(UNFOCUSED_COLOR);
+        for (row_idx, row) in table.rows.iter().enumerate() {
+            let style = if row_idx < table.header_rows {
+                Style::default().fg(Color::White).bold()
+            } else {
+                Style::default().fg(Color::White)
+            };
+
+            let mut spans = vec![prefix.clone()];
+            for (idx, width) in widths.iter().enumerate() {
+                if idx > 0 {
+                    spans.push(Span::styled(" │ ", border_style));
+                }
+                let cell = row.get(idx).map(|cell| cell.trim()).unwrap_or("");
+                let alignment = table
+                    .alignments
+                    .get(idx)
+                    .copied()
+                    .unwrap_or(ColumnAlignment::None);
+                spans.push(Span::styled(align_cell(cell, *width, alignment), style));
+            }
+            self.lines.push(Line::from(spans));
+
+            if row_idx + 1 == table.header_rows {
+                let separator = widths
+                    .iter()
+                    .map(|width| "─".repeat(*width))
+                    .collect::<Vec<String>>()
+                    .join("─┼─");
+                self.lines.push(Line::from(vec![
+                    prefix.clone(),
+                    Span::styled(separator, border_style),
+                ]));
+            }
+        }
+    }
+}
+
+fn align_cell(cell: &str, width: usize, alignment: ColumnAlignment) -> String {
+    let length = cell.chars().count();
+    if length > width {
+        let mut cell = cell.chars().take(width.max(1) - 1).collect::<String>();
+        cell.push('…');
+        return cell;
+    }
+
+    let padding = width - length;
+    match alignment {
+        ColumnAlignment::Right => format!("{}{cell}", " ".repeat(padding)),
+        ColumnAlignment::Center => format!(
+            "{}{cell}{}",
+            " ".repeat(padding / 2),
+            " ".repeat(padding - padding / 2)
+        ),
+        _ => format!("{cell}{}", " ".repeat(padding)),
+    }
+}
+
+/// Break a line of code every `width` characters, without re-flowing it.
+///
+/// The rest of the line is continued with a marker, at the line's own indentation so the
+/// structure of the code stays readable.
+fn hard_wrap_spans(spans: Vec<Span<'static>>, width: usize) -> Vec<Vec<Span<'static>>> {
+    let chars = spans
+        .iter()
+        .flat_map(|span| span.content.chars().map(|c| (c, span.style)))
+        .collect::<Vec<(char, Style)>>();
+    if chars.len() <= width {
+        return vec![spans];
+    }
+
+    // Deeply indented lines keep at least half the width for their code
+    let indent = chars
+        .iter()
+        .take_while(|(c, _)| *c == ' ')
+        .count()
+        .min(width / 2);
+    let continuation = format!("{}{CONTINUATION_MARKER} ", " ".repeat(indent));
+    let continued_width = width.saturating_sub(continuation.chars().count()).max(1);
+
+    let mut lines = Vec::new();
+    let mut rest = chars.as_slice();
+    let mut line_width = width.max(1);
+    while !rest.is_empty() {
+        let (line, remaining) = rest.split_at(line_width.min(rest.len()));
+        let mut line_spans = Vec::new();
+        if !lines.is_empty() {
+            line_spans.push(Span::styled(
+                continuation.clone(),
+                Style::default().fg(UNFOCUSED_COLOR),
+            ));
+        }
+        line_spans.extend(group_styled_chars(line));
+        lines.push(line_spans);
+        rest = remaining;
+        line_width = continued_width;
+    }
+    lines
+}
+
+/// Join runs of characters with the same style back into spans.
+fn group_styled_chars(chars: &[(char, Style)]) -> Vec<Span<'static>> {
+    let mut spans = Vec::<Span<'static>>::new();
+    let mut content = String::new();
+    let mut current = None;
+    for (c, style) in chars {
+        if current.is_some_and(|current| current != *style) {
+            spans.push(Span::styled(std::mem::take(&mut content), current.unwrap()));
+        }
+        current = Some(*style);
+        content.push(*c);
+    }
+    if let Some(style) = current {
+        spans.push(Span::styled(content, style));
+    }
+    spans
+}
+
+/// Word wrap styled spans, starting each line with a prefix.
+fn wrap_spans(
+    spans: Vec<Span<'static>>,
+    width: usize,
+    first_prefix: Span<'static>,
+    rest_prefix: Span<'static>,
+) -> Vec<Line<'static>> {
+    // Split into words, each keeping its trailing whitespace, and the styles of its characters
+    let mut words = Vec::<Vec<(char, Style)>>::new();
+    let mut after_whitespace = true;
+    for span in &spans {
+        for c in span.content.chars() {
+            if !c.is_whitespace() && after_whitespace {
+                words.push(Vec::new());
+            }
+            after_whitespace = c.is_whitespace();
+            match words.last_mut() {
+                Some(word) => word.push((c, span.style)),
+                // Leading whitespace is dropped
+                None => after_whitespace = true,
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut line = Vec::<(char, Style)>::new();
+    let mut prefix = first_prefix;
+    let mut available = width.saturating_sub(prefix.width()).max(1);
+
+    for word in words {
+        let visible = word.iter().filter(|(c, _)| !c.is_whitespace()).count();
+        if !line.is_empty() && line.len() + visible > available {
+            lines.push(build_line(prefix, std::mem::take(&mut line)));
+            prefix = rest_prefix.clone();
+            available = width.saturating_sub(prefix.width()).max(1);
+        }
+
+        for (c, style) in word {
+            // Words longer than a line are broken wherever they reach the end of it
+            if line.len() >= available {
+                if c.is_whitespace() {
+                    continue;
+                }
+                lines.push(build_line(prefix, std::mem::take(&mut line)));
+                prefix = rest_prefix.clone();
+                available = width.saturating_sub(prefix.width()).max(1);
+            }
+            line.push((c, style));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(build_line(prefix, line));
+    }
+
+    lines
+}
+
+/// Join styled characters back into spans, merging runs with the same style.
+fn build_line(prefix: Span<'static>, chars: Vec<(char, Style)>) -> Line<'static> {
+    let mut spans = vec![prefix];
+    let mut current = String::new();
+    let mut current_style = None;
+
+    for (c, style) in chars {
+        if current_style.is_some_and(|current_style| current_style != style) {
+            spans.push(Span::styled(
+                std::mem::take(&mut current),
+                current_style.unwrap_or_default(),
+            ));
+        }
+        current_style = Some(style);
+        current.push(c);
+    }
+    if let Some(style) = current_style {
+        spans.push(Span::styled(current.trim_end().to_string(), style));
+    }
+
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_strings(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .map(|span| span.content.as_ref())
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let content = "# Title\n\nSome **bold** text which wraps\n\n- one\n- two\n\n```rust\nfn main() { println!(\"a long line of code\"); }\n```\n\n| a | b |\n|---|--:|\n| x | 10 |";
+        let lines = render_markdown(content, 24, None);
+
+        assert_eq!(
+            to_strings(&lines),
+            vec![
+                " # Title",
+                " ",
+                " Some bold text which",
+                " wraps",
+                " ",
+                " • one",
+                " • two",
+                " ",
+                " ```rust",
+                "  fn main() { println!(\"",
+                "  ↪ a long line of code\"",
+                "  ↪ ); }",
+                " ```",
+                " ",
+                " a │  b",
+                " ──┼───",
+                " x │ 10",
+            ]
+        );
+
+        let bold = lines[2]
+            .spans
+            .iter()
+            .find(|span| span.content == "bold")
+            .unwrap();
+        assert!(bold.style.add_modifier.contains(Modifier::BOLD));
+
+        // Wrapped code continues at the indentation of its line
+        let lines = render_markdown("```\n    let value = 1234567890123;\n```", 20, None);
+        assert_eq!(
+            to_strings(&lines)[1..3],
+            ["      let value = 12", "      ↪ 34567890123;"]
+        );
+
+        let code_blocks = extract_code_blocks(content);
+        assert_eq!(code_blocks.len(), 1);
+        assert_eq!(code_blocks[0].extension(), "rs");
+        assert!(code_blocks[0].code.ends_with("code\"); }\n"));
+    }
+}
@@ -8,4 +8,7 @@ pub enum Mode {
     ActiveViewer,
     ModelSelector,
     ConversationManager,
+    ConversationSearch,
+    /// Choosing between replies from several models to the same message.
+    Compare,
 }
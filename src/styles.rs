@@ -6,3 +6,8 @@ pub(crate) const UNFOCUSED_COLOR: Color = Color::Gray;
 pub(crate) const USER_COLOR: Color = Color::Green;
 pub(crate) const ASSISTANT_COLOR: Color = Color::Blue;
 pub(crate) const SYSTEM_COLOR: Color = Color::LightYellow;
+pub(crate) const TOOL_COLOR: Color = Color::Magenta;
+pub(crate) const HEADING_COLOR: Color = Color::Cyan;
+pub(crate) const CODE_COLOR: Color = Color::LightRed;
+pub(crate) const QUOTE_COLOR: Color = Color::Gray;
+pub(crate) const LINK_COLOR: Color = Color::LightCyan;